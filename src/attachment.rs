@@ -0,0 +1,246 @@
+//! Payloads attached to PNG files as chunks instead of pixels
+//!
+//! A private ancillary chunk holds the payload verbatim and is copied along by
+//! any tool that keeps unknown safe-to-copy chunks. Text chunks are kept by
+//! even more tools, so the payload can also be base64-encoded and spread over
+//! compressed comments. Either way the payload starts with the same
+//! [`Header`](crate::header::Header) as a pixel payload, which is how the
+//! decoder recognizes it.
+use crate::chunk::Chunk;
+use crate::header::MAGIC;
+use crate::options::Carrier;
+use flate2::{Compression, read::ZlibDecoder, write::ZlibEncoder};
+use std::io::{Read, Write};
+
+/// Private chunk type: ancillary, private, reserved bit clear, safe to copy
+const PRIVATE: [u8; 4] = *b"stGr";
+
+/// Keyword of the text chunks carrying a payload
+const KEYWORD: &[u8] = b"Comment";
+
+/// Largest number of base64 characters in a single text chunk
+const TEXT_PIECE_LEN: usize = 8192;
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Wraps a payload into chunks for one of the chunk carriers
+///
+/// # Arguments:
+/// - **payload**: `&[u8]`
+///   - header followed by the message body
+/// - **carrier**: `Carrier`
+///   - `Carrier::Chunk` or `Carrier::Text`
+/// # Returns:
+/// - `Vec<Chunk>` chunks to insert before IEND, none for `Carrier::Pixels`
+///   and the plain text carriers
+pub(crate) fn chunks(payload: &[u8], carrier: Carrier) -> Vec<Chunk> {
+    match carrier {
+        Carrier::Pixels | Carrier::Whitespace | Carrier::ZeroWidth => Vec::new(),
+        Carrier::Chunk => vec![Chunk::new(PRIVATE, payload.to_vec())],
+        Carrier::Text => base64_encode(payload)
+            .chunks(TEXT_PIECE_LEN)
+            .map(|piece| {
+                let mut data = KEYWORD.to_vec();
+                // Null separator, then compression method 0 (zlib)
+                data.extend([0, 0]);
+                let mut encoder = ZlibEncoder::new(data, Compression::best());
+                // Writing to a Vec cannot fail
+                encoder.write_all(piece).expect("in-memory write");
+                Chunk::new(*b"zTXt", encoder.finish().expect("in-memory write"))
+            })
+            .collect(),
+    }
+}
+
+/// Finds a payload attached by [`chunks`]
+///
+/// The private chunk is looked for first, then the base64 comments.
+///
+/// # Arguments:
+/// - **chunks**: `&[Chunk]`
+///   - chunks of the carrier, in file order
+/// # Returns:
+/// - `Option<Vec<u8>>` candidate payload, still to be checked for a header
+pub(crate) fn find(chunks: &[Chunk]) -> Option<Vec<u8>> {
+    let positions = positions(chunks);
+    let first = &chunks[*positions.first()?];
+    if first.kind == PRIVATE {
+        return Some(first.data.clone());
+    }
+    let text: Vec<u8> = positions
+        .iter()
+        .filter_map(|index| comment(&chunks[*index]))
+        .flatten()
+        .collect();
+    base64_decode(&text)
+}
+
+/// Indices of the chunks holding an attached payload
+///
+/// Private chunks come first. Text pieces start with the base64 form of the
+/// header magic and run on as long as the previous piece was full, so that
+/// ordinary comments are never mistaken for a payload. Also used to drop a
+/// payload left by an earlier encoding, which would shadow the new one.
+///
+/// # Arguments:
+/// - **chunks**: `&[Chunk]`
+///   - chunks of the carrier, in file order
+/// # Returns:
+/// - `Vec<usize>` positions in `chunks`, empty if nothing is attached
+pub(crate) fn positions(chunks: &[Chunk]) -> Vec<usize> {
+    // The fifth character only depends on the top bits of the fourth byte
+    let magic = &base64_encode(&MAGIC)[..5];
+    let mut positions: Vec<usize> = (0..chunks.len())
+        .filter(|index| chunks[*index].kind == PRIVATE)
+        .collect();
+    let mut open = false;
+    let mut found = false;
+    for (index, chunk) in chunks.iter().enumerate() {
+        let Some(text) = comment(chunk) else {
+            continue;
+        };
+        if open && text.iter().all(|byte| is_base64(*byte)) {
+            positions.push(index);
+        } else if !found && text.starts_with(magic) {
+            positions.push(index);
+            found = true;
+        } else {
+            open = false;
+            continue;
+        }
+        open = text.len() == TEXT_PIECE_LEN;
+    }
+    positions
+}
+
+/// Text of a tEXt or zTXt chunk with the payload keyword
+///
+/// Compressed text is only inflated up to the length of a piece, anything
+/// longer cannot be one and is skipped.
+fn comment(chunk: &Chunk) -> Option<Vec<u8>> {
+    let text = chunk
+        .data
+        .strip_prefix(KEYWORD)
+        .and_then(|rest| rest.strip_prefix(&[0]))?;
+    match &chunk.kind {
+        b"tEXt" => Some(text.to_vec()),
+        b"zTXt" => {
+            let (method, compressed) = text.split_first()?;
+            if *method != 0 {
+                return None;
+            }
+            let mut inflated = Vec::new();
+            ZlibDecoder::new(compressed)
+                .take(TEXT_PIECE_LEN as u64 + 1)
+                .read_to_end(&mut inflated)
+                .ok()?;
+            (inflated.len() <= TEXT_PIECE_LEN).then_some(inflated)
+        }
+        _ => None,
+    }
+}
+
+fn is_base64(byte: u8) -> bool {
+    BASE64.contains(&byte) || byte == b'='
+}
+
+/// Encodes bytes as padded standard base64
+fn base64_encode(bytes: &[u8]) -> Vec<u8> {
+    let mut text = Vec::with_capacity(bytes.len().div_ceil(3) * 4);
+    for group in bytes.chunks(3) {
+        let bits = group.iter().enumerate().fold(0u32, |bits, (i, byte)| {
+            bits | (*byte as u32) << (16 - 8 * i)
+        });
+        for i in 0..4 {
+            if i <= group.len() {
+                text.push(BASE64[(bits >> (18 - 6 * i) & 0x3F) as usize]);
+            } else {
+                text.push(b'=');
+            }
+        }
+    }
+    text
+}
+
+/// Decodes padded standard base64, `None` if the text is malformed
+fn base64_decode(text: &[u8]) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(4) {
+        return None;
+    }
+    let mut bytes = Vec::with_capacity(text.len() / 4 * 3);
+    for (index, group) in text.chunks(4).enumerate() {
+        let padding = group.iter().rev().take_while(|byte| **byte == b'=').count();
+        let last = index == text.len() / 4 - 1;
+        if padding > 2 || (padding > 0 && !last) {
+            return None;
+        }
+        let mut bits = 0u32;
+        for (i, byte) in group[..4 - padding].iter().enumerate() {
+            let value = BASE64.iter().position(|symbol| symbol == byte)? as u32;
+            bits |= value << (18 - 6 * i);
+        }
+        bytes.extend_from_slice(&bits.to_be_bytes()[1..4 - padding]);
+    }
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_base64_roundtrip() {
+        assert_eq!(base64_encode(b"Man"), b"TWFu");
+        assert_eq!(base64_encode(b"Ma"), b"TWE=");
+        assert_eq!(base64_encode(b"M"), b"TQ==");
+        for len in 0..10 {
+            let bytes: Vec<u8> = (0..len).map(|i| (i * 97) as u8).collect();
+            assert_eq!(base64_decode(&base64_encode(&bytes)).unwrap(), bytes);
+        }
+        assert!(base64_decode(b"TQ=").is_none());
+        assert!(base64_decode(b"TQ==TWFu").is_none());
+        assert!(base64_decode(b"T*==").is_none());
+    }
+
+    #[test]
+    fn test_chunks_are_found_again() {
+        let mut payload = MAGIC.to_vec();
+        payload.extend((0..20_000).map(|i| (i % 251) as u8));
+        assert!(chunks(&payload, Carrier::Pixels).is_empty());
+        assert_eq!(find(&chunks(&payload, Carrier::Chunk)).unwrap(), payload);
+
+        let text = chunks(&payload, Carrier::Text);
+        assert_eq!(text.len(), 4);
+        // An unrelated comment does not get in the way
+        let mut with_comment = vec![Chunk::new(*b"tEXt", b"Comment\0Hello!".to_vec())];
+        with_comment.extend(text);
+        assert_eq!(find(&with_comment).unwrap(), payload);
+        assert!(find(&with_comment[..1]).is_none());
+        assert_eq!(positions(&with_comment), vec![1, 2, 3, 4]);
+
+        // Comments made of base64 characters only are not mistaken for pieces
+        let plain = Chunk::new(*b"tEXt", b"Comment\0holiday".to_vec());
+        assert!(positions(std::slice::from_ref(&plain)).is_empty());
+        with_comment.push(plain);
+        assert_eq!(find(&with_comment).unwrap(), payload);
+    }
+
+    #[test]
+    fn test_compressed_comments_are_bounded() {
+        // 16 MiB of base64 characters, compressed to some 16 KiB
+        let mut data = KEYWORD.to_vec();
+        data.extend([0, 0]);
+        let mut encoder = ZlibEncoder::new(data, Compression::best());
+        let magic = &base64_encode(&MAGIC)[..5];
+        encoder.write_all(magic).unwrap();
+        for _ in 0..256 {
+            encoder.write_all(&[b'A'; 64 * 1024]).unwrap();
+        }
+        let bomb = Chunk::new(*b"zTXt", encoder.finish().unwrap());
+        assert!(bomb.data.len() < 1 << 15);
+
+        assert!(comment(&bomb).is_none());
+        assert!(positions(std::slice::from_ref(&bomb)).is_empty());
+        assert!(find(&[bomb]).is_none());
+    }
+}
@@ -80,6 +80,34 @@ impl BitUtils {
             .collect()
     }
 
+    /// Reads the `count` least significant bits of a byte, most significant first
+    ///
+    /// # Arguments:
+    /// - **byte**: `u8`
+    ///  - input byte
+    /// - **count**: `u8`
+    ///  - number of low-order bits to read, 1 to 8
+    /// # Returns:
+    ///  - `Vec<u8>` bit array of length `count`
+    pub fn read_low_bits(byte: u8, count: u8) -> Vec<u8> {
+        (0..count).rev().map(|i| (byte >> i) & 1).collect()
+    }
+
+    /// Replaces the least significant bits of a byte
+    ///
+    /// # Arguments:
+    /// - **byte**: `u8`
+    ///  - input byte
+    /// - **bits**: `&[u8]`
+    ///  - 1 to 8 bits, most significant first, replacing the low `bits.len()` bits
+    /// # Returns:
+    ///  - `u8` byte carrying the bits
+    pub fn write_low_bits(byte: u8, bits: &[u8]) -> u8 {
+        let mask = (0xFFu16 >> (8 - bits.len())) as u8;
+        let value = bits.iter().fold(0, |acc, bit| (acc << 1) | bit);
+        (byte & !mask) | value
+    }
+
     // Takes bytes and transforms them into a bit array
     // bytes: input byte array
     // returns: vector of bits
@@ -230,6 +258,45 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_read_low_bits() {
+        assert_eq!(BitUtils::read_low_bits(0b1010_0110, 1), vec![0]);
+        assert_eq!(BitUtils::read_low_bits(0b1010_0110, 2), vec![1, 0]);
+        assert_eq!(BitUtils::read_low_bits(0b1010_0110, 4), vec![0, 1, 1, 0]);
+        assert_eq!(
+            BitUtils::read_low_bits(0b1010_0110, 8),
+            BitUtils::byte_to_bit(0b1010_0110)
+        );
+    }
+
+    #[test]
+    fn test_write_low_bits() {
+        assert_eq!(BitUtils::write_low_bits(0b1010_0110, &[1]), 0b1010_0111);
+        assert_eq!(BitUtils::write_low_bits(0b1010_0110, &[0, 1]), 0b1010_0101);
+        assert_eq!(
+            BitUtils::write_low_bits(0b1111_1111, &[0, 0, 0, 0]),
+            0b1111_0000
+        );
+        assert_eq!(
+            BitUtils::write_low_bits(0, &[1, 0, 1, 0, 1, 0, 1, 0]),
+            0b1010_1010
+        );
+    }
+
+    #[test]
+    fn test_low_bits_roundtrip() {
+        for count in 1..=8 {
+            for byte in 0..=255u8 {
+                let bits = BitUtils::read_low_bits(byte, count);
+                assert_eq!(BitUtils::write_low_bits(byte, &bits), byte);
+                assert_eq!(
+                    BitUtils::write_low_bits(0, &bits) as u16,
+                    byte as u16 % (1 << count)
+                );
+            }
+        }
+    }
+
     #[test]
     fn test_make_bits_and_back() {
         let test_bytes = vec![0, 1, 127, 128, 255, 42, 170, 85];
@@ -0,0 +1,320 @@
+//! Uncompressed BMP reading and writing
+//!
+//! 24- and 32-bit true-color and 8-bit paletted bitmaps are supported. The
+//! file is kept as it was read and only the pixel bytes are overwritten on
+//! writing, so headers, color tables, row padding and trailing data all
+//! survive unchanged.
+use crate::error::{Result, StegError};
+use crate::image::{Format, Image};
+use png::{BitDepth, ColorType};
+
+/// Signature every BMP file starts with
+pub(crate) const SIGNATURE: [u8; 2] = *b"BM";
+
+/// BI_RGB: uncompressed pixels
+const BI_RGB: u32 = 0;
+/// BI_BITFIELDS: uncompressed pixels with explicit channel masks
+const BI_BITFIELDS: u32 = 3;
+
+/// Where the pixels of a BMP file lie, so they can be written back in place
+pub(crate) struct Bitmap {
+    /// Complete file contents, as read
+    file: Vec<u8>,
+    /// Offset of the first stored row
+    offset: usize,
+    /// Bytes between the starts of two stored rows, padding included
+    stride: usize,
+    /// Whether the last row of the image is stored first, as usual in BMP
+    bottom_up: bool,
+    /// Bytes per pixel: 1, 3 or 4
+    pixel_len: usize,
+}
+
+/// Decodes an in-memory BMP file
+///
+/// # Arguments:
+/// - **bytes**: `&[u8]`
+///   - complete BMP file contents
+/// # Returns:
+/// - `Result<Image>` pixels in RGB(A) order or as palette indices
+///   - `StegError::ImageDecode` for a truncated, compressed or unsupported bitmap
+pub(crate) fn read(bytes: &[u8]) -> Result<Image> {
+    let invalid = |reason: &str| StegError::ImageDecode(format!("BMP: {}", reason));
+    let u16_at = |offset: usize| {
+        bytes
+            .get(offset..offset + 2)
+            .map(|field| u16::from_le_bytes([field[0], field[1]]))
+            .ok_or_else(|| invalid("truncated header"))
+    };
+    let u32_at = |offset: usize| {
+        bytes
+            .get(offset..offset + 4)
+            .map(|field| u32::from_le_bytes([field[0], field[1], field[2], field[3]]))
+            .ok_or_else(|| invalid("truncated header"))
+    };
+
+    if !bytes.starts_with(&SIGNATURE) {
+        return Err(invalid("missing signature"));
+    }
+    let offset = u32_at(10)? as usize;
+    let header_len = u32_at(14)? as usize;
+    if header_len < 40 {
+        return Err(invalid("OS/2 bitmaps are not supported"));
+    }
+    let width = u32_at(18)? as i32;
+    let height = u32_at(22)? as i32;
+    let bits_per_pixel = u16_at(28)?;
+    let compression = u32_at(30)?;
+    if width <= 0 || height == 0 {
+        return Err(invalid("empty image"));
+    }
+
+    let color_type = match (bits_per_pixel, compression) {
+        (8, BI_RGB) => ColorType::Indexed,
+        (24, BI_RGB) => ColorType::Rgb,
+        (32, BI_RGB) => ColorType::Rgba,
+        // Masks follow the 40-byte header, or sit at the same place in later ones
+        (32, BI_BITFIELDS)
+            if (u32_at(54)?, u32_at(58)?, u32_at(62)?)
+                == (0x00FF_0000, 0x0000_FF00, 0x0000_00FF) =>
+        {
+            ColorType::Rgba
+        }
+        (_, BI_RGB | BI_BITFIELDS) => {
+            return Err(invalid(&format!(
+                "{} bits per pixel are not supported",
+                bits_per_pixel
+            )));
+        }
+        _ => return Err(invalid("compressed bitmaps are not supported")),
+    };
+
+    let palette = match color_type {
+        ColorType::Indexed => {
+            let colors = match u32_at(46)? {
+                0 => 256,
+                colors => colors.min(256) as usize,
+            };
+            let table = bytes
+                .get(14 + header_len..)
+                .and_then(|table| table.get(..colors * 4))
+                .ok_or_else(|| invalid("truncated color table"))?;
+            // Stored as blue, green, red and a reserved byte
+            Some(
+                table
+                    .chunks(4)
+                    .flat_map(|bgr| [bgr[2], bgr[1], bgr[0]])
+                    .collect(),
+            )
+        }
+        _ => None,
+    };
+
+    let pixel_len = bits_per_pixel as usize / 8;
+    let (width, rows) = (width as usize, height.unsigned_abs() as usize);
+    let stride = width
+        .checked_mul(pixel_len)
+        .map(|row_len| row_len.div_ceil(4) * 4)
+        .ok_or_else(|| invalid("image dimensions are too large"))?;
+    // Pixels never start inside the headers they would overwrite
+    if offset < 14 + header_len {
+        return Err(invalid("pixel data overlaps the header"));
+    }
+    if stride
+        .checked_mul(rows)
+        .and_then(|len| len.checked_add(offset))
+        .is_none_or(|end| end > bytes.len())
+    {
+        return Err(invalid("truncated pixel data"));
+    }
+    let bitmap = Bitmap {
+        file: bytes.to_vec(),
+        offset,
+        stride,
+        bottom_up: height > 0,
+        pixel_len,
+    };
+
+    let mut data = Vec::with_capacity(width * rows * pixel_len);
+    for y in 0..rows {
+        let start = bitmap.row_start(y, rows);
+        for pixel in bytes[start..start + width * pixel_len].chunks(pixel_len) {
+            data.extend(from_stored(pixel));
+        }
+    }
+    Ok(Image {
+        width: width as u32,
+        height: rows as u32,
+        color_type,
+        bit_depth: BitDepth::Eight,
+        palette,
+        trns: None,
+        interlaced: false,
+        chunks: Vec::new(),
+        attachments: Vec::new(),
+        data,
+        frames: Vec::new(),
+        format: Format::Bmp(bitmap),
+    })
+}
+
+/// Encodes an image read from a BMP file back into it
+///
+/// # Arguments:
+/// - **image**: `&Image`
+///   - image returned by [`read`], with changed pixels
+/// - **bitmap**: `&Bitmap`
+///   - layout of the original file
+/// # Returns:
+/// - `Vec<u8>` complete BMP file contents
+pub(crate) fn write(image: &Image, bitmap: &Bitmap) -> Vec<u8> {
+    let mut file = bitmap.file.clone();
+    let rows = image.height as usize;
+    let row_len = image.width as usize * bitmap.pixel_len;
+    for (y, row) in image.data.chunks(row_len).enumerate() {
+        let start = bitmap.row_start(y, rows);
+        for (stored, pixel) in file[start..start + row_len]
+            .chunks_mut(bitmap.pixel_len)
+            .zip(row.chunks(bitmap.pixel_len))
+        {
+            stored.copy_from_slice(&from_stored(pixel));
+        }
+    }
+    file
+}
+
+impl Bitmap {
+    /// Offset of a row of the image, counted from the top
+    fn row_start(&self, y: usize, rows: usize) -> usize {
+        let stored = if self.bottom_up { rows - 1 - y } else { y };
+        self.offset + stored * self.stride
+    }
+}
+
+/// Swaps stored BGR(A) pixels to RGB(A) order; the swap is its own inverse
+fn from_stored(pixel: &[u8]) -> Vec<u8> {
+    match pixel {
+        [b, g, r] => vec![*r, *g, *b],
+        [b, g, r, a] => vec![*r, *g, *b, *a],
+        _ => pixel.to_vec(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{decode_bytes, encode_bytes};
+
+    // 32-bit bitmap with a version 5 header and channel masks, from the test
+    // data of CPython's imghdr module
+    const PYTHON: &[u8] = include_bytes!("../testdata/python.bmp");
+
+    // BMP file with a 40-byte header, rows given top to bottom
+    fn bitmap_file(width: i32, height: i32, bits_per_pixel: u16, rows: &[Vec<u8>]) -> Vec<u8> {
+        let colors: u32 = if bits_per_pixel == 8 { 4 } else { 0 };
+        let offset = 54 + colors * 4;
+        let mut file = SIGNATURE.to_vec();
+        file.extend(0u32.to_le_bytes());
+        file.extend(0u32.to_le_bytes());
+        file.extend(offset.to_le_bytes());
+        file.extend(40u32.to_le_bytes());
+        file.extend(width.to_le_bytes());
+        file.extend(height.to_le_bytes());
+        file.extend(1u16.to_le_bytes());
+        file.extend(bits_per_pixel.to_le_bytes());
+        file.extend([0; 16]);
+        file.extend(colors.to_le_bytes());
+        file.extend(0u32.to_le_bytes());
+        // Black, white, blue and red
+        if colors > 0 {
+            file.extend([0, 0, 0, 0, 255, 255, 255, 0, 255, 0, 0, 0, 0, 0, 255, 0]);
+        }
+        let stored: Vec<&Vec<u8>> = match height > 0 {
+            true => rows.iter().rev().collect(),
+            false => rows.iter().collect(),
+        };
+        for row in stored {
+            file.extend(row);
+            file.extend(vec![0; row.len().div_ceil(4) * 4 - row.len()]);
+        }
+        file
+    }
+
+    #[test]
+    fn test_read_converts_rows_and_channels() {
+        let rows = vec![vec![1, 2, 3, 4, 5, 6], vec![7, 8, 9, 10, 11, 12]];
+        for height in [2, -2] {
+            let image = read(&bitmap_file(2, height, 24, &rows)).unwrap();
+            assert_eq!(image.color_type, ColorType::Rgb);
+            assert_eq!(image.data, vec![3, 2, 1, 6, 5, 4, 9, 8, 7, 12, 11, 10]);
+        }
+
+        let image = read(&bitmap_file(3, 1, 8, &[vec![0, 3, 2]])).unwrap();
+        assert_eq!(image.color_type, ColorType::Indexed);
+        assert_eq!(image.data, vec![0, 3, 2]);
+        assert_eq!(&image.palette.unwrap()[6..], &[0, 0, 255, 255, 0, 0]);
+    }
+
+    #[test]
+    fn test_read_rejects_malformed_bitmaps() {
+        let file = PYTHON;
+        let edited = |edits: &[(usize, &[u8])]| {
+            let mut file = file.to_vec();
+            for (offset, bytes) in edits {
+                file[*offset..offset + bytes.len()].copy_from_slice(bytes);
+            }
+            file
+        };
+        let compressed = edited(&[(30, &[1])]);
+        let oversized = edited(&[(18, &[0xFF, 0xFF, 0xFF, 0x7F, 0, 0, 0, 0x80])]);
+        let overlapping = edited(&[(10, &[2])]);
+        // 8 bits per pixel, with a color table beyond a header of 4 GiB
+        let palette_past_end = edited(&[(14, &[0xFF; 4]), (28, &[8]), (30, &[0])]);
+        for refused in [
+            &compressed[..],
+            &oversized,
+            &overlapping,
+            &palette_past_end,
+            &file[..file.len() - 1],
+            &file[..20],
+        ] {
+            assert!(matches!(read(refused), Err(StegError::ImageDecode(_))));
+        }
+    }
+
+    #[test]
+    fn test_write_only_touches_pixels() {
+        let rows: Vec<Vec<u8>> = (0..3).map(|y| vec![y * 40; 5 * 4]).collect();
+        let mut file = bitmap_file(5, 3, 32, &rows);
+        file.extend(b"trailing");
+        let mut image = read(&file).unwrap();
+        assert_eq!(write(&image, bitmap(&image)), file);
+
+        image.data[0] ^= 1;
+        let written = write(&image, bitmap(&image));
+        let changed: Vec<usize> = (0..file.len())
+            .filter(|i| file[*i] != written[*i])
+            .collect();
+        // Red of the top-left pixel, stored third in the last row
+        assert_eq!(changed, vec![54 + 2 * 20 + 2]);
+    }
+
+    fn bitmap(image: &Image) -> &Bitmap {
+        match &image.format {
+            Format::Bmp(bitmap) => bitmap,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_encoding_keeps_headers_and_size() {
+        let paletted: Vec<Vec<u8>> = (0..20).map(|y| vec![y as u8 % 4; 21]).collect();
+        for carrier in [PYTHON.to_vec(), bitmap_file(21, -20, 8, &paletted)] {
+            let offset = u32::from_le_bytes(carrier[10..14].try_into().unwrap()) as usize;
+            let encoded = encode_bytes(&carrier, b"bitmap").unwrap();
+            assert_eq!(encoded.len(), carrier.len());
+            assert_eq!(&encoded[..offset], &carrier[..offset]);
+            assert_eq!(decode_bytes(&encoded).unwrap(), b"bitmap");
+        }
+    }
+}
@@ -0,0 +1,138 @@
+//! Splitting PNG files into chunks and putting them back together
+//!
+//! The `png` crate decodes the pixels but only keeps the chunks it knows
+//! about, so the carrier's chunks are kept verbatim here and the re-encoded
+//! image data is spliced in between them.
+use crate::error::Result;
+use crc32fast::Hasher;
+use std::io;
+
+/// Signature every PNG file starts with
+pub(crate) const SIGNATURE: [u8; 8] = *b"\x89PNG\r\n\x1a\n";
+
+pub(crate) const IHDR: [u8; 4] = *b"IHDR";
+pub(crate) const IDAT: [u8; 4] = *b"IDAT";
+pub(crate) const IEND: [u8; 4] = *b"IEND";
+pub(crate) const TIME: [u8; 4] = *b"tIME";
+pub(crate) const FCTL: [u8; 4] = *b"fcTL";
+pub(crate) const FDAT: [u8; 4] = *b"fdAT";
+
+/// Chunk types holding free-form text about the image
+pub(crate) const TEXT_CHUNKS: [[u8; 4]; 3] = [*b"tEXt", *b"zTXt", *b"iTXt"];
+
+/// A single PNG chunk
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Chunk {
+    pub kind: [u8; 4],
+    pub data: Vec<u8>,
+}
+
+impl Chunk {
+    pub fn new(kind: [u8; 4], data: Vec<u8>) -> Chunk {
+        Chunk { kind, data }
+    }
+
+    /// Appends the length, type, data and CRC of the chunk to a buffer
+    pub fn write_to(&self, output: &mut Vec<u8>) {
+        output.extend_from_slice(&(self.data.len() as u32).to_be_bytes());
+        output.extend_from_slice(&self.kind);
+        output.extend_from_slice(&self.data);
+        let mut hasher = Hasher::new();
+        hasher.update(&self.kind);
+        hasher.update(&self.data);
+        output.extend_from_slice(&hasher.finalize().to_be_bytes());
+    }
+}
+
+/// Splits a PNG file into its chunks, up to and including IEND
+///
+/// # Arguments:
+/// - **bytes**: `&[u8]`
+///   - complete PNG file contents
+/// # Returns:
+/// - `Result<Vec<Chunk>>` chunks in file order
+///   - `StegError::PngDecode` for a missing signature or IHDR, or a truncated chunk
+pub(crate) fn parse(bytes: &[u8]) -> Result<Vec<Chunk>> {
+    let invalid = |reason: &str| {
+        png::DecodingError::from(io::Error::new(
+            io::ErrorKind::InvalidData,
+            reason.to_string(),
+        ))
+    };
+    let mut rest = bytes
+        .strip_prefix(&SIGNATURE)
+        .ok_or_else(|| invalid("missing PNG signature"))?;
+    let mut chunks = Vec::new();
+    while !rest.is_empty() {
+        if rest.len() < 12 {
+            return Err(invalid("truncated chunk header").into());
+        }
+        let length = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+        let kind = [rest[4], rest[5], rest[6], rest[7]];
+        let data = rest
+            .get(8..8 + length)
+            .filter(|_| rest.len() >= 12 + length)
+            .ok_or_else(|| invalid("truncated chunk"))?;
+        chunks.push(Chunk::new(kind, data.to_vec()));
+        rest = &rest[12 + length..];
+        if kind == IEND {
+            break;
+        }
+    }
+    if chunks.first().map(|chunk| chunk.kind) != Some(IHDR) {
+        return Err(invalid("first chunk is not IHDR").into());
+    }
+    Ok(chunks)
+}
+
+/// Joins chunks into a PNG file
+///
+/// # Arguments:
+/// - **chunks**: `&[Chunk]`
+///   - chunks in file order, starting with IHDR and ending with IEND
+/// # Returns:
+/// - `Vec<u8>` complete PNG file contents
+pub(crate) fn serialize(chunks: &[Chunk]) -> Vec<u8> {
+    let mut output = SIGNATURE.to_vec();
+    for chunk in chunks {
+        chunk.write_to(&mut output);
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::StegError;
+
+    #[test]
+    fn test_parse_and_serialize_roundtrip() {
+        let chunks = vec![
+            Chunk::new(IHDR, vec![0, 0, 0, 1, 0, 0, 0, 1, 8, 0, 0, 0, 0]),
+            Chunk::new(*b"tEXt", b"Comment\0hi".to_vec()),
+            Chunk::new(IDAT, vec![1, 2, 3]),
+            Chunk::new(IEND, Vec::new()),
+        ];
+        let bytes = serialize(&chunks);
+        assert_eq!(&bytes[..8], &SIGNATURE);
+        // The CRC of IEND is a well-known constant
+        assert_eq!(&bytes[bytes.len() - 4..], &[0xAE, 0x42, 0x60, 0x82]);
+
+        let mut trailing = bytes.clone();
+        trailing.extend_from_slice(b"garbage");
+        assert_eq!(parse(&bytes).unwrap(), chunks);
+        assert_eq!(parse(&trailing).unwrap(), chunks);
+    }
+
+    #[test]
+    fn test_parse_rejects_malformed_files() {
+        assert!(matches!(parse(b"GIF89a"), Err(StegError::PngDecode(_))));
+
+        let bytes = serialize(&[Chunk::new(IDAT, vec![0; 10])]);
+        assert!(matches!(parse(&bytes), Err(StegError::PngDecode(_))));
+        assert!(matches!(
+            parse(&bytes[..bytes.len() - 6]),
+            Err(StegError::PngDecode(_))
+        ));
+    }
+}
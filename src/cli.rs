@@ -0,0 +1,121 @@
+//! CLI definition for stegarst - A simple steganography tool
+use clap::Parser;
+use std::{fs::read, io};
+use stegarst::{
+    Carrier, Channels, Compression, DecodeOptions, Embedding, EncodeOptions, Filter, Metadata,
+};
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+// CLI for stegarst - A simple steganography tool
+// ## Options:
+// ```
+// -o, --option <OPTION>        Specify 'read' to extract a message or 'write' to hide a message
+// -f, --file <FILE>           Path to the file to hide (required for 'write' option)
+// -i, --image <IMAGE>         Path to the image (or WAV, FLAC or text) file
+// --output <OUTPUT>           Path to output the result (message file or image file)
+// --passphrase <PASSPHRASE>   Passphrase to encrypt or decrypt the hidden message
+// --key-file <KEY_FILE>       Path to a file whose contents are used as the passphrase
+// --walk-key <WALK_KEY>       Key scattering the message pseudo-randomly over the image
+// --embedding <EMBEDDING>     'replacement' (default) or 'matching' to use LSB matching
+// --bits-per-sample <BITS>    Number of low-order bits of each sample carrying the message, 1 to 4 (8 for 16-bit images)
+// --channels <CHANNELS>       'color' (default, skips alpha), 'all', or letters from 'rgbya'
+// --metadata <METADATA>       'keep' (default), 'strip' or 'touch' the carrier's timestamp and text chunks
+// --compression <LEVEL>       'match' (default) to reuse the carrier's zlib level, or a level from 0 to 9
+// --filter <FILTER>           'match' (default), 'adaptive', 'none', 'sub', 'up', 'average' or 'paeth'
+// --carrier <CARRIER>         'pixels' (default), 'chunk' for a private chunk, 'text' for comment chunks,
+//                             or 'whitespace' or 'zero-width' to hide it in a plain text
+// ```
+pub struct Cli {
+    /// If is to read or to hide
+    #[arg(short, long)]
+    pub option: String,
+
+    /// The path to the file to read
+    #[arg(short, long)]
+    pub file: Option<String>,
+
+    /// The path to the image (or WAV, FLAC or text file) to use
+    #[arg(short, long)]
+    pub image: String,
+
+    /// The path to output the file
+    #[arg(long)]
+    pub output: String,
+
+    /// The passphrase to encrypt or decrypt the hidden message with
+    #[arg(long, conflicts_with = "key_file")]
+    pub passphrase: Option<String>,
+
+    /// The path to a file whose contents are used as the passphrase
+    #[arg(long)]
+    pub key_file: Option<String>,
+
+    /// The key scattering the message pseudo-randomly over the image
+    #[arg(long)]
+    pub walk_key: Option<String>,
+
+    /// How samples are changed to hold the message: 'replacement' or 'matching'
+    #[arg(long, default_value_t = Embedding::Replacement)]
+    pub embedding: Embedding,
+
+    /// The number of low-order bits of each sample carrying the message, 1 to 4 (8 for 16-bit images)
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u8).range(1..=8))]
+    pub bits_per_sample: u8,
+
+    /// The channels carrying the message: 'color', 'all', or letters from 'rgbya' (y is gray)
+    #[arg(long, default_value_t = Channels::COLOR)]
+    pub channels: Channels,
+
+    /// What happens to the carrier's timestamp and text chunks: 'keep', 'strip' or 'touch'
+    #[arg(long, default_value_t = Metadata::Keep)]
+    pub metadata: Metadata,
+
+    /// The zlib level of the output image: 'match' to reuse the carrier's, or 0 to 9
+    #[arg(long, default_value_t = Compression::Match)]
+    pub compression: Compression,
+
+    /// The filter applied to each row of the output image: 'match' to reuse the carrier's,
+    /// 'adaptive', 'none', 'sub', 'up', 'average' or 'paeth'
+    #[arg(long, default_value_t = Filter::Match)]
+    pub filter: Filter,
+
+    /// Where the message is hidden: 'pixels', 'chunk' for a private chunk, 'text' for comment chunks,
+    /// or 'whitespace' or 'zero-width' to hide it in a plain text
+    #[arg(long, default_value_t = Carrier::Pixels)]
+    pub carrier: Carrier,
+}
+
+impl Cli {
+    /// Builds the library options for the 'write' option
+    pub fn encode_options(&self) -> io::Result<EncodeOptions> {
+        Ok(EncodeOptions {
+            passphrase: self.secret()?,
+            walk_key: self.walk_key.as_ref().map(|key| key.as_bytes().to_vec()),
+            embedding: self.embedding,
+            bits_per_sample: self.bits_per_sample,
+            channels: self.channels,
+            metadata: self.metadata,
+            compression: self.compression,
+            filter: self.filter,
+            carrier: self.carrier,
+        })
+    }
+
+    /// Builds the library options for the 'read' option
+    pub fn decode_options(&self) -> io::Result<DecodeOptions> {
+        Ok(DecodeOptions {
+            passphrase: self.secret()?,
+            walk_key: self.walk_key.as_ref().map(|key| key.as_bytes().to_vec()),
+        })
+    }
+
+    // Passphrase given on the command line or read from the key file
+    fn secret(&self) -> io::Result<Option<Vec<u8>>> {
+        match (&self.passphrase, &self.key_file) {
+            (Some(passphrase), _) => Ok(Some(passphrase.as_bytes().to_vec())),
+            (None, Some(key_file)) => read(key_file).map(Some),
+            (None, None) => Ok(None),
+        }
+    }
+}
@@ -0,0 +1,233 @@
+//! Passphrase-based authenticated encryption of hidden messages
+//!
+//! The key is derived from the passphrase with Argon2id and the message is
+//! sealed with XChaCha20-Poly1305. The salt, nonce and KDF cost parameters
+//! travel in the message [`Header`](crate::header::Header), so only the
+//! passphrase is needed to open it again.
+use crate::error::{Result, StegError};
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::{
+    Key, KeyInit, XChaCha20Poly1305, XNonce,
+    aead::{Aead, AeadCore, OsRng, rand_core::RngCore},
+};
+
+/// Size of the Argon2id salt in bytes
+pub const SALT_LEN: usize = 16;
+/// Size of the XChaCha20-Poly1305 nonce in bytes
+pub const NONCE_LEN: usize = 24;
+/// Size of the serialized cipher parameters in bytes
+pub const CIPHER_PARAMS_LEN: usize = SALT_LEN + NONCE_LEN + 12;
+/// Largest Argon2id memory cost accepted from a header, in KiB (76 MiB).
+/// Messages are always written with the defaults, so the header gets little
+/// room to make opening one slow
+const MAX_M_COST: u32 = 4 * Params::DEFAULT_M_COST;
+/// Largest Argon2id iteration count accepted from a header
+const MAX_T_COST: u32 = 4 * Params::DEFAULT_T_COST;
+/// Largest Argon2id parallelism accepted from a header
+const MAX_P_COST: u32 = 4 * Params::DEFAULT_P_COST;
+
+/// Key derivation and cipher inputs stored alongside an encrypted message
+///
+/// ## Layout:
+/// ```text
+/// offset  size  field
+///      0    16  Argon2id salt
+///     16    24  XChaCha20-Poly1305 nonce
+///     40     4  Argon2id memory cost in KiB, big-endian
+///     44     4  Argon2id iterations, big-endian
+///     48     4  Argon2id parallelism, big-endian
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CipherParams {
+    pub salt: [u8; SALT_LEN],
+    pub nonce: [u8; NONCE_LEN],
+    pub m_cost: u32,
+    pub t_cost: u32,
+    pub p_cost: u32,
+}
+
+impl CipherParams {
+    /// Generates a fresh random salt and nonce with the default Argon2id costs
+    ///
+    /// # Returns:
+    /// - `CipherParams` parameters for a single message
+    pub fn generate() -> CipherParams {
+        let mut salt = [0; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        CipherParams {
+            salt,
+            nonce: XChaCha20Poly1305::generate_nonce(&mut OsRng).into(),
+            m_cost: Params::DEFAULT_M_COST,
+            t_cost: Params::DEFAULT_T_COST,
+            p_cost: Params::DEFAULT_P_COST,
+        }
+    }
+
+    /// Serializes the parameters
+    ///
+    /// # Returns:
+    /// - `[u8; CIPHER_PARAMS_LEN]` parameter bytes
+    pub fn to_bytes(&self) -> [u8; CIPHER_PARAMS_LEN] {
+        let mut bytes = [0; CIPHER_PARAMS_LEN];
+        bytes[..16].copy_from_slice(&self.salt);
+        bytes[16..40].copy_from_slice(&self.nonce);
+        bytes[40..44].copy_from_slice(&self.m_cost.to_be_bytes());
+        bytes[44..48].copy_from_slice(&self.t_cost.to_be_bytes());
+        bytes[48..].copy_from_slice(&self.p_cost.to_be_bytes());
+        bytes
+    }
+
+    /// Deserializes the parameters
+    ///
+    /// # Arguments:
+    /// - **bytes**: `&[u8; CIPHER_PARAMS_LEN]`
+    ///   - parameter bytes
+    /// # Returns:
+    /// - `CipherParams` parameters read from the header
+    pub fn from_bytes(bytes: &[u8; CIPHER_PARAMS_LEN]) -> CipherParams {
+        let word = |at: usize| {
+            u32::from_be_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+        };
+        let mut salt = [0; SALT_LEN];
+        salt.copy_from_slice(&bytes[..16]);
+        let mut nonce = [0; NONCE_LEN];
+        nonce.copy_from_slice(&bytes[16..40]);
+        CipherParams {
+            salt,
+            nonce,
+            m_cost: word(40),
+            t_cost: word(44),
+            p_cost: word(48),
+        }
+    }
+
+    /// Derives the message key from a passphrase with Argon2id
+    fn derive_key(&self, passphrase: &[u8]) -> Result<Key> {
+        if self.m_cost > MAX_M_COST || self.t_cost > MAX_T_COST || self.p_cost > MAX_P_COST {
+            return Err(StegError::CorruptHeader(format!(
+                "key derivation costs m={} t={} p={} exceed the supported limits",
+                self.m_cost, self.t_cost, self.p_cost
+            )));
+        }
+        let params =
+            Params::new(self.m_cost, self.t_cost, self.p_cost, Some(32)).map_err(|err| {
+                StegError::CorruptHeader(format!("invalid key derivation costs: {}", err))
+            })?;
+        let mut key = Key::default();
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(passphrase, &self.salt, &mut key)
+            .map_err(|err| StegError::Encryption(err.to_string()))?;
+        Ok(key)
+    }
+}
+
+/// Encrypts and authenticates a message
+///
+/// # Arguments:
+/// - **passphrase**: `&[u8]`
+///   - secret the key is derived from
+/// - **params**: `&CipherParams`
+///   - salt, nonce and key derivation costs
+/// - **message**: `&[u8]`
+///   - plaintext message
+/// # Returns:
+/// - `Result<Vec<u8>>` ciphertext followed by the 16-byte authentication tag
+pub fn encrypt(passphrase: &[u8], params: &CipherParams, message: &[u8]) -> Result<Vec<u8>> {
+    let cipher = XChaCha20Poly1305::new(&params.derive_key(passphrase)?);
+    cipher
+        .encrypt(XNonce::from_slice(&params.nonce), message)
+        .map_err(|err| StegError::Encryption(err.to_string()))
+}
+
+/// Decrypts a message, checking that it was not tampered with
+///
+/// # Arguments:
+/// - **passphrase**: `&[u8]`
+///   - secret the key is derived from
+/// - **params**: `&CipherParams`
+///   - salt, nonce and key derivation costs read from the header
+/// - **ciphertext**: `&[u8]`
+///   - ciphertext followed by the authentication tag
+/// # Returns:
+/// - `Result<Vec<u8>>` plaintext message
+///   - `StegError::DecryptionFailed` for a wrong passphrase or a modified message
+pub fn decrypt(passphrase: &[u8], params: &CipherParams, ciphertext: &[u8]) -> Result<Vec<u8>> {
+    let cipher = XChaCha20Poly1305::new(&params.derive_key(passphrase)?);
+    cipher
+        .decrypt(XNonce::from_slice(&params.nonce), ciphertext)
+        .map_err(|_| StegError::DecryptionFailed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Cheap costs keep the tests fast; the format does not depend on them
+    fn test_params() -> CipherParams {
+        CipherParams {
+            m_cost: 64,
+            t_cost: 1,
+            ..CipherParams::generate()
+        }
+    }
+
+    #[test]
+    fn test_encrypt_decrypt_roundtrip() {
+        let params = test_params();
+        let sealed = encrypt(b"hunter2", &params, b"attack at dawn").unwrap();
+        assert_eq!(sealed.len(), b"attack at dawn".len() + 16);
+        assert_ne!(&sealed[..14], b"attack at dawn");
+        assert_eq!(
+            decrypt(b"hunter2", &params, &sealed).unwrap(),
+            b"attack at dawn"
+        );
+    }
+
+    #[test]
+    fn test_decrypt_rejects_wrong_passphrase_and_tampering() {
+        let params = test_params();
+        let mut sealed = encrypt(b"hunter2", &params, b"attack at dawn").unwrap();
+        assert!(matches!(
+            decrypt(b"hunter3", &params, &sealed),
+            Err(StegError::DecryptionFailed)
+        ));
+
+        sealed[0] ^= 1;
+        assert!(matches!(
+            decrypt(b"hunter2", &params, &sealed),
+            Err(StegError::DecryptionFailed)
+        ));
+    }
+
+    #[test]
+    fn test_params_roundtrip_and_limits() {
+        let params = CipherParams::generate();
+        assert_eq!(CipherParams::from_bytes(&params.to_bytes()), params);
+        assert_ne!(CipherParams::generate().salt, params.salt);
+
+        for greedy in [
+            CipherParams {
+                m_cost: MAX_M_COST + 1,
+                ..params
+            },
+            CipherParams {
+                t_cost: MAX_T_COST + 1,
+                ..params
+            },
+            CipherParams {
+                p_cost: MAX_P_COST + 1,
+                ..params
+            },
+            CipherParams {
+                m_cost: 1 << 20,
+                t_cost: 64,
+                ..params
+            },
+        ] {
+            assert!(matches!(
+                decrypt(b"hunter2", &greedy, &[0; 32]),
+                Err(StegError::CorruptHeader(_))
+            ));
+        }
+    }
+}
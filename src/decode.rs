@@ -0,0 +1,950 @@
+//! Module for decoding messages hidden in images using LSB steganography
+//!
+//! The channels and the number of bits per sample holding the message body
+//! are read from the header, so no setting is needed to decode them. Palette
+//! images are read through the ranks of their colors, see [`Palette`]. A
+//! message attached as chunks is found before the pixels are looked at, and
+//! one hidden in a plain text before the carrier is read as an image. The
+//! samples of every APNG frame are read one frame after the other. PNG images
+//! written by older releases, which carry only a 32-bit size in front of the
+//! message, are still readable.
+use crate::attachment;
+use crate::bit_utils::BitUtils;
+use crate::crypto;
+use crate::embed::{Extractor, Selection};
+use crate::error::{Result, StegError};
+use crate::header::Header;
+use crate::image::{Format, Image};
+use crate::options::{Channels, DecodeOptions};
+use crate::palette::Palette;
+use crate::text;
+use png::{BitDepth, ColorType};
+use std::{
+    fs::{read, write},
+    io::{Read, Write},
+};
+
+/// Number of carrier bytes holding the legacy message size, one bit each
+const SIZE_HEADER_BITS: usize = 32;
+
+/// Retrieves a message hidden inside an image using LSB steganography
+///
+/// ## Arguments:
+///   - **src:** `&str`
+///     -  path to the source image
+///   - **dest:** `&str`
+///     - path to the destination file to save the message
+/// ## Returns:
+/// - `Result<()>`
+///   - `StegError::NoPayload` if the image does not carry a hidden message
+///   - `StegError::CorruptHeader` if the message header or checksum is invalid
+///   - `StegError::PassphraseRequired` if the message is encrypted
+///
+/// ## Example:
+/// ```no_run
+///   stegarst::decode("input.png", "output_message.txt")?;
+/// # Ok::<(), stegarst::StegError>(())
+/// ```
+pub fn decode(src: &str, dest: &str) -> Result<()> {
+    decode_with(src, dest, &DecodeOptions::default())
+}
+
+/// Retrieves a message hidden inside an image, see [`decode`]
+///
+/// ## Arguments:
+///   - **src:** `&str`
+///     -  path to the source image
+///   - **dest:** `&str`
+///     - path to the destination file to save the message
+///   - **options:** `&DecodeOptions`
+///     - decryption and walk settings
+/// ## Returns:
+/// - `Result<()>`
+///   - `StegError::DecryptionFailed` for a wrong passphrase or a tampered image
+///
+/// ## Example:
+/// ```no_run
+///   let options = stegarst::DecodeOptions::default().with_passphrase("hunter2");
+///   stegarst::decode_with("input.png", "output_message.txt", &options)?;
+/// # Ok::<(), stegarst::StegError>(())
+/// ```
+pub fn decode_with(src: &str, dest: &str, options: &DecodeOptions) -> Result<()> {
+    let message = decode_bytes_with(&read(src)?, options)?;
+    write(dest, message)?;
+    Ok(())
+}
+
+/// Retrieves a message hidden inside an image read from a stream
+///
+/// ## Arguments:
+///   - **carrier:** `impl Read`
+///     - PNG image holding the message
+///   - **output:** `impl Write`
+///     - destination for the retrieved message
+/// ## Returns:
+/// - `Result<()>`
+///
+/// ## Example:
+/// ```no_run
+///   let carrier = std::fs::File::open("input.png")?;
+///   stegarst::decode_stream(carrier, std::io::stdout())?;
+/// # Ok::<(), stegarst::StegError>(())
+/// ```
+pub fn decode_stream(carrier: impl Read, output: impl Write) -> Result<()> {
+    decode_stream_with(carrier, output, &DecodeOptions::default())
+}
+
+/// Retrieves a message hidden inside an image read from a stream, see
+/// [`decode_stream`]
+///
+/// ## Arguments:
+///   - **carrier:** `impl Read`
+///     - PNG image holding the message
+///   - **output:** `impl Write`
+///     - destination for the retrieved message
+///   - **options:** `&DecodeOptions`
+///     - decryption and walk settings
+/// ## Returns:
+/// - `Result<()>`
+pub fn decode_stream_with(
+    mut carrier: impl Read,
+    mut output: impl Write,
+    options: &DecodeOptions,
+) -> Result<()> {
+    let mut carrier_bytes = Vec::new();
+    carrier.read_to_end(&mut carrier_bytes)?;
+
+    output.write_all(&decode_bytes_with(&carrier_bytes, options)?)?;
+    output.flush()?;
+    Ok(())
+}
+
+/// Retrieves a message hidden inside an in-memory PNG image
+///
+/// ## Arguments:
+///   - **carrier:** `&[u8]`
+///     - contents of the PNG image holding the message
+/// ## Returns:
+/// - `Result<Vec<u8>>` the retrieved message
+///
+/// ## Example:
+/// ```no_run
+///   let carrier = std::fs::read("input.png")?;
+///   let message = stegarst::decode_bytes(&carrier)?;
+/// # Ok::<(), stegarst::StegError>(())
+/// ```
+pub fn decode_bytes(carrier: &[u8]) -> Result<Vec<u8>> {
+    decode_bytes_with(carrier, &DecodeOptions::default())
+}
+
+/// Retrieves a message hidden inside an in-memory PNG image, see
+/// [`decode_bytes`]
+///
+/// ## Arguments:
+///   - **carrier:** `&[u8]`
+///     - contents of the PNG image holding the message
+///   - **options:** `&DecodeOptions`
+///     - decryption and walk settings
+/// ## Returns:
+/// - `Result<Vec<u8>>` the retrieved message
+pub fn decode_bytes_with(carrier: &[u8], options: &DecodeOptions) -> Result<Vec<u8>> {
+    // Plain text carriers are not images, so they are looked for first. A run
+    // left damaged by an edit must not keep the next one, or the image, from
+    // being read, so its error only counts if nothing else is found
+    let mut text_error = None;
+    for payload in text::payloads(carrier) {
+        let found = Header::parse(&payload).and_then(|header| match header {
+            Some(header) => open(&header, &payload, options).map(Some),
+            None => Ok(None),
+        });
+        match found {
+            Ok(Some(message)) => return Ok(message),
+            Ok(None) => {}
+            Err(error) => {
+                text_error.get_or_insert(error);
+            }
+        }
+    }
+    match (decode_image(carrier, options), text_error) {
+        (
+            Err(
+                StegError::PngDecode(_)
+                | StegError::ImageDecode(_)
+                | StegError::UnsupportedColorType(..)
+                | StegError::NoPayload(_),
+            ),
+            Some(error),
+        ) => Err(error),
+        (result, _) => result,
+    }
+}
+
+/// Retrieves a message hidden inside an in-memory image, as chunks or in its
+/// samples, see [`decode_bytes`]
+///
+/// ## Arguments:
+///   - **carrier:** `&[u8]`
+///     - contents of the image holding the message
+///   - **options:** `&DecodeOptions`
+///     - decryption and walk settings
+/// ## Returns:
+/// - `Result<Vec<u8>>` the retrieved message
+fn decode_image(carrier: &[u8], options: &DecodeOptions) -> Result<Vec<u8>> {
+    let image = Image::read(carrier)?;
+    if let Some(payload) = attachment::find(&image.chunks)
+        && let Some(header) = Header::parse(&payload)?
+    {
+        return open(&header, &payload, options);
+    }
+
+    let data = match image.color_type {
+        ColorType::Indexed => Palette::new(
+            image.palette.as_deref().unwrap_or_default(),
+            image.trns.as_deref(),
+        )
+        .ranks(&image.samples()),
+        _ => image.samples(),
+    };
+
+    let walk_key = options.walk_key.as_deref();
+    let (header, mut extractor, available) =
+        match find_header(&data, image.color_type, image.bit_depth, walk_key)? {
+            Some(found) => found,
+            None if walk_key.is_some() => {
+                return Err(StegError::NoPayload(
+                    "no message header found along the keyed walk".to_string(),
+                ));
+            }
+            // Only baseline PNGs were written by the releases before the header
+            None if matches!(image.format, Format::Png)
+                && image.color_type != ColorType::Indexed =>
+            {
+                return Ok(lsb_bytes(legacy_payload_bytes(&data)?));
+            }
+            None => {
+                return Err(StegError::NoPayload("no message header found".to_string()));
+            }
+        };
+
+    let bits_per_sample = header.layout.bits_per_sample;
+    let body: Vec<u8> = extractor
+        .bytes(bits_per_sample)
+        .take(header.length as usize)
+        .collect();
+    if body.len() < header.length as usize {
+        let body_samples = available.saturating_sub(header.encoded_len() * 8);
+        return Err(StegError::CorruptHeader(format!(
+            "message size of {} bytes exceeds the {} bytes available",
+            header.length,
+            body_samples * bits_per_sample as usize / 8
+        )));
+    }
+    unseal(&header, &body, options)
+}
+
+/// Retrieves the message of a payload held whole by its carrier
+///
+/// ## Arguments:
+///   - **header:** `&Header`
+///     - header parsed from the start of the payload
+///   - **payload:** `&[u8]`
+///     - header followed by the message body
+///   - **options:** `&DecodeOptions`
+///     - decryption settings
+/// ## Returns:
+/// - `Result<Vec<u8>>` the retrieved message
+fn open(header: &Header, payload: &[u8], options: &DecodeOptions) -> Result<Vec<u8>> {
+    let body = &payload[header.encoded_len().min(payload.len())..];
+    if body.len() < header.length as usize {
+        return Err(StegError::CorruptHeader(format!(
+            "message size of {} bytes exceeds the {} bytes attached",
+            header.length,
+            body.len()
+        )));
+    }
+    unseal(header, &body[..header.length as usize], options)
+}
+
+/// Checks a message body against its header and decrypts it if needed
+///
+/// ## Arguments:
+///   - **header:** `&Header`
+///     - header read in front of the body
+///   - **body:** `&[u8]`
+///     - message body, as long as the header says
+///   - **options:** `&DecodeOptions`
+///     - decryption settings
+/// ## Returns:
+/// - `Result<Vec<u8>>` the retrieved message
+fn unseal(header: &Header, body: &[u8], options: &DecodeOptions) -> Result<Vec<u8>> {
+    header.verify(body)?;
+    match (&header.cipher, &options.passphrase) {
+        (Some(cipher), Some(passphrase)) => crypto::decrypt(passphrase, cipher, body),
+        (Some(_), None) => Err(StegError::PassphraseRequired),
+        (None, _) => Ok(body.to_vec()),
+    }
+}
+
+/// Looks for a message header in every channel selection the image allows
+///
+/// The selection is only known once the header is read, so the default
+/// selection and all channels are tried first, then every other combination.
+/// A header only counts when it names the selection it was found in.
+///
+/// ## Arguments:
+///   - **data:** `&[u8]`
+///     - raw sample bytes of the carrier image
+///   - **color_type:** `ColorType`
+///     - color type reported by the PNG decoder
+///   - **bit_depth:** `BitDepth`
+///     - bits per sample reported by the PNG decoder
+///   - **walk_key:** `Option<&[u8]>`
+///     - key the message was scattered with, if any
+/// ## Returns:
+/// - `Result<Option<(Header, Extractor, usize)>>` the header, the extractor
+///   positioned at the message body and the number of selected samples
+fn find_header<'a>(
+    data: &'a [u8],
+    color_type: ColorType,
+    bit_depth: BitDepth,
+    walk_key: Option<&[u8]>,
+) -> Result<Option<(Header, Extractor<'a>, usize)>> {
+    let samples_per_pixel = color_type.samples();
+
+    let every = (1u8 << samples_per_pixel) - 1;
+    let preferred = [Channels::default().mask(color_type), every];
+    let others = (1..every).filter(|mask| !preferred.contains(mask));
+    for mask in preferred.into_iter().chain(others) {
+        let selection = Selection::new(samples_per_pixel, bit_depth as u8, mask);
+        let available = selection.len(data.len());
+        let mut extractor = Extractor::new(data, selection, walk_key);
+        let header = Header::read(&mut extractor.bytes(1))?;
+        if let Some(header) = header {
+            let channels = match header.layout.channels {
+                0 => every,
+                channels => channels,
+            };
+            if channels == mask {
+                return Ok(Some((header, extractor, available)));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Reassembles bytes from the LSBs of the given samples
+fn lsb_bytes(samples: &[u8]) -> Vec<u8> {
+    BitUtils::bits_to_bytes(BitUtils::read_lsb(samples.to_vec()))
+}
+
+/// Validates the size-only header written by releases before the versioned
+/// [`Header`] against the image capacity
+///
+/// ## Arguments:
+///   - **data:** `&[u8]`
+///     - raw sample bytes of the carrier image
+/// ## Returns:
+/// - `Result<&[u8]>` the sample bytes whose LSBs hold the message
+///   - `StegError::NoPayload` if the header cannot describe a message in this image
+fn legacy_payload_bytes(data: &[u8]) -> Result<&[u8]> {
+    if data.len() < SIZE_HEADER_BITS {
+        return Err(StegError::NoPayload(format!(
+            "image holds {} bytes, too few for a message header",
+            data.len()
+        )));
+    }
+    let (message_len, image_data) = data.split_at(SIZE_HEADER_BITS);
+    let message_len = BitUtils::byte_u32_to_decimal(BitUtils::read_lsb(message_len.to_vec()));
+    // Dark or silent carriers read as an empty message
+    if message_len == 0 {
+        return Err(StegError::NoPayload("message size of 0 bits".to_string()));
+    }
+    if !message_len.is_multiple_of(8) {
+        return Err(StegError::NoPayload(format!(
+            "message size of {} bits is not a whole number of bytes",
+            message_len
+        )));
+    }
+    image_data.get(..message_len as usize).ok_or_else(|| {
+        StegError::NoPayload(format!(
+            "message size of {} bits exceeds the {} bits available",
+            message_len,
+            image_data.len()
+        ))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encode;
+    use crate::header::HEADER_LEN;
+    use crate::options::{Carrier, Channels, Embedding, EncodeOptions};
+    use png::{BitDepth, ColorType, Encoder};
+    use std::fs;
+    use std::io::BufWriter;
+    use std::io::Write;
+
+    #[test]
+    fn test_decode_retrieves_message() {
+        let temp_dir = std::env::temp_dir();
+        let src_path = temp_dir.join("test_decode_input.png");
+        let encoded_path = temp_dir.join("test_decode_encoded.png");
+        let decoded_path = temp_dir.join("test_decode_decoded.txt");
+        let msg_path = temp_dir.join("test_decode_message.txt");
+
+        // Create a small PNG image
+        {
+            let file = fs::File::create(&src_path).unwrap();
+            let w = BufWriter::new(file);
+            let mut encoder = Encoder::new(w, 10, 10);
+            encoder.set_color(ColorType::Rgb);
+            encoder.set_depth(BitDepth::Eight);
+            let mut writer = encoder.write_header().unwrap();
+            let data = vec![255; 10 * 10 * 3];
+            writer.write_image_data(&data).unwrap();
+        }
+
+        // Create message file
+        let message = b"Hello, world!";
+        {
+            let mut msg_file = fs::File::create(&msg_path).unwrap();
+            msg_file.write_all(message).unwrap();
+        }
+
+        // Encode
+        encode::encode(
+            src_path.to_str().unwrap(),
+            msg_path.to_str().unwrap(),
+            encoded_path.to_str().unwrap(),
+        )
+        .unwrap();
+
+        // Decode
+        decode(
+            encoded_path.to_str().unwrap(),
+            decoded_path.to_str().unwrap(),
+        )
+        .unwrap();
+
+        // Check the decoded message
+        let decoded_message = fs::read(&decoded_path).unwrap();
+        assert_eq!(decoded_message, message);
+
+        // Cleanup
+        let _ = fs::remove_file(&src_path);
+        let _ = fs::remove_file(&msg_path);
+        let _ = fs::remove_file(&encoded_path);
+        let _ = fs::remove_file(&decoded_path);
+    }
+
+    #[test]
+    fn test_decode_rejects_oversized_header() {
+        let temp_dir = std::env::temp_dir();
+        let src_path = temp_dir.join("test_decode_oversized.png");
+        let decoded_path = temp_dir.join("test_decode_oversized.txt");
+
+        // All samples odd: the size header reads as u32::MAX bits
+        {
+            let file = fs::File::create(&src_path).unwrap();
+            let w = BufWriter::new(file);
+            let mut encoder = Encoder::new(w, 10, 10);
+            encoder.set_color(ColorType::Rgb);
+            encoder.set_depth(BitDepth::Eight);
+            let mut writer = encoder.write_header().unwrap();
+            let data = vec![255; 10 * 10 * 3];
+            writer.write_image_data(&data).unwrap();
+        }
+
+        let result = decode(src_path.to_str().unwrap(), decoded_path.to_str().unwrap());
+        assert!(matches!(result, Err(StegError::NoPayload(_))));
+        assert!(!decoded_path.exists());
+
+        // Cleanup
+        let _ = fs::remove_file(&src_path);
+    }
+
+    #[test]
+    fn test_decode_missing_file_is_io_error() {
+        let result = decode("does_not_exist.png", "never_written.txt");
+        assert!(matches!(result, Err(StegError::Io(_))));
+    }
+
+    #[test]
+    fn test_decode_bytes_roundtrip_in_memory() {
+        let mut carrier = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut carrier, 10, 10);
+            encoder.set_color(ColorType::Rgba);
+            encoder.set_depth(BitDepth::Eight);
+            let mut writer = encoder.write_header().unwrap();
+            writer.write_image_data(&[128; 10 * 10 * 4]).unwrap();
+        }
+
+        let encoded = encode::encode_bytes(&carrier, b"in memory").unwrap();
+        assert_eq!(decode_bytes(&encoded).unwrap(), b"in memory");
+
+        let mut streamed = Vec::new();
+        decode_stream(std::io::Cursor::new(&encoded), &mut streamed).unwrap();
+        assert_eq!(streamed, b"in memory");
+    }
+
+    #[test]
+    fn test_decode_bytes_rejects_images_without_payload() {
+        // Gradients, stripes, black and tiny images must all fail cleanly instead of panicking
+        let gradient: Vec<u8> = (0..300).map(|i| (i % 256) as u8).collect();
+        let steep: Vec<u8> = (0..300).map(|i| (i * 7 % 256) as u8).collect();
+        let stripes: Vec<u8> = (0..300).map(|i| (i % 5 == 0) as u8).collect();
+        let black = vec![0; 300];
+        let cases = [
+            (10, gradient),
+            (10, steep),
+            (10, stripes),
+            (10, black),
+            (2, vec![0; 12]),
+        ];
+
+        for (side, data) in cases {
+            let mut carrier = Vec::new();
+            {
+                let mut encoder = Encoder::new(&mut carrier, side, side);
+                encoder.set_color(ColorType::Rgb);
+                encoder.set_depth(BitDepth::Eight);
+                let mut writer = encoder.write_header().unwrap();
+                writer.write_image_data(&data).unwrap();
+            }
+
+            let result = decode_bytes(&carrier);
+            assert!(
+                matches!(result, Err(StegError::NoPayload(_))),
+                "unexpected result: {:?}",
+                result
+            );
+        }
+    }
+
+    #[test]
+    fn test_decode_bytes_rejects_unaligned_size() {
+        // Size header of 9 bits: only the last two of the 32 header samples are odd
+        let mut data = vec![0u8; 10 * 10 * 3];
+        data[28] = 1;
+        data[31] = 1;
+        let mut carrier = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut carrier, 10, 10);
+            encoder.set_color(ColorType::Rgb);
+            encoder.set_depth(BitDepth::Eight);
+            let mut writer = encoder.write_header().unwrap();
+            writer.write_image_data(&data).unwrap();
+        }
+
+        assert!(matches!(
+            decode_bytes(&carrier),
+            Err(StegError::NoPayload(_))
+        ));
+    }
+
+    #[test]
+    fn test_decode_bytes_rejects_non_png() {
+        assert!(matches!(
+            decode_bytes(b"definitely not a png"),
+            Err(StegError::PngDecode(_))
+        ));
+    }
+
+    // Writes raw RGB samples into an in-memory PNG
+    fn rgb_png(width: u32, height: u32, data: &[u8]) -> Vec<u8> {
+        png_of(ColorType::Rgb, width, height, data)
+    }
+
+    fn png_of(color_type: ColorType, width: u32, height: u32, data: &[u8]) -> Vec<u8> {
+        png_at(color_type, BitDepth::Eight, width, height, data)
+    }
+
+    fn png_at(
+        color_type: ColorType,
+        bit_depth: BitDepth,
+        width: u32,
+        height: u32,
+        data: &[u8],
+    ) -> Vec<u8> {
+        let mut carrier = Vec::new();
+        let mut encoder = Encoder::new(&mut carrier, width, height);
+        encoder.set_color(color_type);
+        encoder.set_depth(bit_depth);
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(data).unwrap();
+        writer.finish().unwrap();
+        carrier
+    }
+
+    #[test]
+    fn test_decode_bytes_reads_legacy_images() {
+        // Legacy layout: 32-bit size in bits, then the message bits
+        let message = b"old";
+        let mut bits = BitUtils::byte_u32_to_bit((message.len() * 8) as u32);
+        bits.extend(BitUtils::make_bits(message.to_vec()));
+        let mut data = vec![200u8; 10 * 10 * 3];
+        for (byte, bit) in data.iter_mut().zip(bits) {
+            *byte = (*byte & !1) | bit;
+        }
+
+        assert_eq!(decode_bytes(&rgb_png(10, 10, &data)).unwrap(), message);
+    }
+
+    #[test]
+    fn test_decode_bytes_detects_corrupted_message() {
+        let encoded =
+            encode::encode_bytes(&rgb_png(10, 10, &[100; 10 * 10 * 3]), b"checksum").unwrap();
+        let mut data = Image::read(&encoded).unwrap().data;
+        // Flip one LSB inside the message body, past the header
+        data[HEADER_LEN * 8 + 3] ^= 1;
+
+        assert!(matches!(
+            decode_bytes(&rgb_png(10, 10, &data)),
+            Err(StegError::CorruptHeader(_))
+        ));
+    }
+
+    #[test]
+    fn test_decode_bytes_with_passphrase() {
+        let carrier = rgb_png(20, 20, &[100; 20 * 20 * 3]);
+        let options = EncodeOptions::default().with_passphrase("hunter2");
+        let encoded = encode::encode_bytes_with(&carrier, b"secret", &options).unwrap();
+
+        let data = Image::read(&encoded).unwrap().data;
+        assert!(
+            !lsb_bytes(&data)
+                .windows(6)
+                .any(|window| window == b"secret")
+        );
+
+        let right = DecodeOptions::default().with_passphrase("hunter2");
+        assert_eq!(decode_bytes_with(&encoded, &right).unwrap(), b"secret");
+
+        let wrong = DecodeOptions::default().with_passphrase("hunter3");
+        assert!(matches!(
+            decode_bytes_with(&encoded, &wrong),
+            Err(StegError::DecryptionFailed)
+        ));
+        assert!(matches!(
+            decode_bytes(&encoded),
+            Err(StegError::PassphraseRequired)
+        ));
+    }
+
+    #[test]
+    fn test_decode_bytes_with_walk_key() {
+        let carrier = rgb_png(20, 20, &[100; 20 * 20 * 3]);
+        let options = EncodeOptions::default().with_walk_key("scatter");
+        let encoded = encode::encode_bytes_with(&carrier, b"spread out", &options).unwrap();
+
+        // Changes are spread over the image rather than packed at the start
+        let data = Image::read(&encoded).unwrap().data;
+        let changed: Vec<usize> = (0..data.len()).filter(|i| data[*i] != 100).collect();
+        assert!(changed.iter().any(|i| *i > data.len() / 2));
+
+        let keyed = DecodeOptions::default().with_walk_key("scatter");
+        assert_eq!(decode_bytes_with(&encoded, &keyed).unwrap(), b"spread out");
+
+        let wrong = DecodeOptions::default().with_walk_key("gather");
+        assert!(matches!(
+            decode_bytes_with(&encoded, &wrong),
+            Err(StegError::NoPayload(_))
+        ));
+        // Without the key only the legacy fallback can run, finding no message
+        assert!(matches!(
+            decode_bytes(&encoded),
+            Err(StegError::NoPayload(_))
+        ));
+    }
+
+    #[test]
+    fn test_decode_bytes_reads_lsb_matching() {
+        let data: Vec<u8> = (0..20 * 20 * 3).map(|i| (i * 37 % 256) as u8).collect();
+        let carrier = rgb_png(20, 20, &data);
+        let options = EncodeOptions::default().with_embedding(Embedding::Matching);
+        let encoded = encode::encode_bytes_with(&carrier, b"plus or minus", &options).unwrap();
+
+        let changed = Image::read(&encoded).unwrap().data;
+        assert!(
+            changed
+                .iter()
+                .zip(&data)
+                .all(|(new, old)| new.abs_diff(*old) <= 1)
+        );
+        assert_eq!(decode_bytes(&encoded).unwrap(), b"plus or minus");
+    }
+
+    #[test]
+    fn test_decode_bytes_reads_multiple_bits_per_sample() {
+        let data: Vec<u8> = (0..20 * 20 * 3).map(|i| (i * 37 % 256) as u8).collect();
+        let carrier = rgb_png(20, 20, &data);
+        let message: Vec<u8> = (0..200).map(|i| (i * 7 % 256) as u8).collect();
+        // 200 bytes need 1600 samples at one bit each, more than the 1200 available
+        assert!(matches!(
+            encode::encode_bytes(&carrier, &message),
+            Err(StegError::InsufficientCapacity { .. })
+        ));
+
+        for bits_per_sample in 2..=4 {
+            for embedding in [Embedding::Replacement, Embedding::Matching] {
+                let options = EncodeOptions::default()
+                    .with_bits_per_sample(bits_per_sample)
+                    .with_embedding(embedding)
+                    .with_walk_key("deep");
+                let encoded = encode::encode_bytes_with(&carrier, &message, &options).unwrap();
+
+                // Matching stays within half a step, away from the ends of the range
+                let changed = Image::read(&encoded).unwrap().data;
+                let limit = match embedding {
+                    Embedding::Replacement => (1 << bits_per_sample) - 1,
+                    Embedding::Matching => 1 << (bits_per_sample - 1),
+                };
+                assert!(
+                    changed
+                        .iter()
+                        .zip(&data)
+                        .filter(|(_, old)| (16..240).contains(*old))
+                        .all(|(new, old)| new.abs_diff(*old) <= limit)
+                );
+
+                let keyed = DecodeOptions::default().with_walk_key("deep");
+                assert_eq!(decode_bytes_with(&encoded, &keyed).unwrap(), message);
+            }
+        }
+    }
+
+    #[test]
+    fn test_decode_bytes_skips_alpha_by_default() {
+        let data: Vec<u8> = (0..20 * 20 * 4)
+            .map(|i| if i % 4 == 3 { 255 } else { 100 })
+            .collect();
+        let carrier = png_of(ColorType::Rgba, 20, 20, &data);
+        let encoded = encode::encode_bytes(&carrier, b"opaque").unwrap();
+
+        let changed = Image::read(&encoded).unwrap().data;
+        assert!(changed.chunks(4).all(|pixel| pixel[3] == 255));
+        assert_ne!(changed, data);
+        assert_eq!(decode_bytes(&encoded).unwrap(), b"opaque");
+    }
+
+    #[test]
+    fn test_decode_bytes_reads_selected_channels() {
+        let carrier = png_of(ColorType::Rgba, 20, 20, &[100; 20 * 20 * 4]);
+        for channels in [
+            Channels::BLUE,
+            Channels::RED | Channels::ALPHA,
+            Channels::ALL,
+        ] {
+            let options = EncodeOptions::default()
+                .with_channels(channels)
+                .with_walk_key("channels");
+            let encoded = encode::encode_bytes_with(&carrier, b"picky", &options).unwrap();
+
+            let mask = channels.mask(ColorType::Rgba);
+            let changed = Image::read(&encoded).unwrap().data;
+            assert!(
+                changed
+                    .iter()
+                    .enumerate()
+                    .all(|(i, sample)| *sample == 100 || mask & 1 << (i % 4) != 0)
+            );
+            let keyed = DecodeOptions::default().with_walk_key("channels");
+            assert_eq!(decode_bytes_with(&encoded, &keyed).unwrap(), b"picky");
+        }
+
+        // Alpha alone does not exist in an RGB image
+        let options = EncodeOptions::default().with_channels(Channels::ALPHA);
+        assert!(matches!(
+            encode::encode_bytes_with(&rgb_png(10, 10, &[100; 300]), b"", &options),
+            Err(StegError::InvalidOptions(_))
+        ));
+    }
+
+    #[test]
+    fn test_decode_bytes_roundtrip_at_both_depths() {
+        let message: Vec<u8> = (0..100).map(|i| (i * 11 % 256) as u8).collect();
+        let samples: Vec<u16> = (0..20 * 20 * 3)
+            .map(|i| (i * 4099 % 65536) as u16)
+            .collect();
+        let wide: Vec<u8> = samples
+            .iter()
+            .flat_map(|sample| sample.to_be_bytes())
+            .collect();
+        let narrow: Vec<u8> = samples.iter().map(|sample| (sample >> 8) as u8).collect();
+
+        for bits_per_sample in [1, 4] {
+            let options = EncodeOptions::default()
+                .with_bits_per_sample(bits_per_sample)
+                .with_embedding(Embedding::Matching);
+            let carrier = png_at(ColorType::Rgb, BitDepth::Eight, 20, 20, &narrow);
+            let encoded = encode::encode_bytes_with(&carrier, &message, &options).unwrap();
+            assert_eq!(decode_bytes(&encoded).unwrap(), message);
+        }
+
+        for bits_per_sample in [1, 4, 8] {
+            for embedding in [Embedding::Replacement, Embedding::Matching] {
+                let options = EncodeOptions::default()
+                    .with_bits_per_sample(bits_per_sample)
+                    .with_embedding(embedding);
+                let carrier = png_at(ColorType::Rgb, BitDepth::Sixteen, 20, 20, &wide);
+                let encoded = encode::encode_bytes_with(&carrier, &message, &options).unwrap();
+
+                // Only the low-order bits move, never by 256 levels
+                let changed = Image::read(&encoded).unwrap().data;
+                let limit = 1 << bits_per_sample;
+                assert!(changed.chunks(2).zip(&samples).all(|(new, old)| {
+                    u16::from_be_bytes([new[0], new[1]]).abs_diff(*old) < limit
+                }));
+                assert_eq!(decode_bytes(&encoded).unwrap(), message);
+            }
+        }
+
+        // Eight bits per sample are only allowed with 16-bit samples
+        let options = EncodeOptions::default().with_bits_per_sample(8);
+        let carrier = png_at(ColorType::Rgb, BitDepth::Eight, 20, 20, &narrow);
+        assert!(matches!(
+            encode::encode_bytes_with(&carrier, &message, &options),
+            Err(StegError::InvalidOptions(_))
+        ));
+    }
+
+    #[test]
+    fn test_decode_bytes_reads_palette_images() {
+        // 16 gray levels in scrambled order, the darkest ones translucent
+        let levels: Vec<u8> = (0..16).map(|i| (i * 7 % 16 * 17) as u8).collect();
+        let plte: Vec<u8> = levels.iter().flat_map(|level| [*level; 3]).collect();
+        let trns: Vec<u8> = levels.iter().map(|level| *level.max(&128)).collect();
+        let data: Vec<u8> = (0..20 * 20).map(|i| (i * 5 % 16) as u8).collect();
+
+        let mut carrier = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut carrier, 20, 20);
+            encoder.set_color(ColorType::Indexed);
+            encoder.set_depth(BitDepth::Eight);
+            encoder.set_palette(plte.as_slice());
+            encoder.set_trns(trns.as_slice());
+            let mut writer = encoder.write_header().unwrap();
+            writer.write_image_data(&data).unwrap();
+        }
+        let encoded = encode::encode_bytes(&carrier, b"indexed").unwrap();
+
+        let image = Image::read(&encoded).unwrap();
+        assert_eq!(image.palette.as_deref(), Some(plte.as_slice()));
+        assert_eq!(image.trns.as_deref(), Some(trns.as_slice()));
+        assert_ne!(image.data, data);
+        // Pixels only move to the neighbouring gray level
+        assert!(
+            image
+                .data
+                .iter()
+                .zip(&data)
+                .all(|(new, old)| levels[*new as usize].abs_diff(levels[*old as usize]) <= 17)
+        );
+        assert_eq!(decode_bytes(&encoded).unwrap(), b"indexed");
+
+        let options = EncodeOptions::default().with_bits_per_sample(2);
+        assert!(matches!(
+            encode::encode_bytes_with(&carrier, b"indexed", &options),
+            Err(StegError::InvalidOptions(_))
+        ));
+    }
+
+    #[test]
+    fn test_decode_bytes_reads_sub_byte_grayscale() {
+        for bit_depth in [BitDepth::One, BitDepth::Two, BitDepth::Four] {
+            let depth = bit_depth as usize;
+            // 40 samples per row, packed into whole bytes
+            let row: Vec<u8> = (0..40 * depth / 8).map(|i| (i * 89 % 256) as u8).collect();
+            let data = row.repeat(40);
+            let carrier = png_at(ColorType::Grayscale, bit_depth, 40, 40, &data);
+            let options = EncodeOptions::default().with_embedding(Embedding::Matching);
+            let encoded = encode::encode_bytes_with(&carrier, b"packed", &options).unwrap();
+
+            let before = Image::read(&carrier).unwrap().data;
+            let after = Image::read(&encoded).unwrap();
+            assert_eq!(after.bit_depth, bit_depth);
+            assert!(
+                after
+                    .data
+                    .iter()
+                    .all(|sample| (*sample as usize) < 1 << depth)
+            );
+            assert!(
+                after
+                    .data
+                    .iter()
+                    .zip(&before)
+                    .all(|(new, old)| new.abs_diff(*old) <= 1)
+            );
+            assert_eq!(decode_bytes(&encoded).unwrap(), b"packed");
+
+            let options = EncodeOptions::default().with_bits_per_sample(2);
+            assert!(matches!(
+                encode::encode_bytes_with(&carrier, b"packed", &options),
+                Err(StegError::InvalidOptions(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_decode_bytes_reads_chunk_carriers() {
+        // Far more than the pixels could hold
+        let message: Vec<u8> = (0..5_000).map(|i| (i % 253) as u8).collect();
+        let carrier = rgb_png(8, 8, &[100; 8 * 8 * 3]);
+        for carrier_kind in [Carrier::Chunk, Carrier::Text] {
+            let options = EncodeOptions::default()
+                .with_carrier(carrier_kind)
+                .with_passphrase("hunter2");
+            let encoded = encode::encode_bytes_with(&carrier, &message, &options).unwrap();
+            assert_eq!(
+                Image::read(&encoded).unwrap().data,
+                Image::read(&carrier).unwrap().data
+            );
+
+            let right = DecodeOptions::default().with_passphrase("hunter2");
+            assert_eq!(decode_bytes_with(&encoded, &right).unwrap(), message);
+            assert!(matches!(
+                decode_bytes(&encoded),
+                Err(StegError::PassphraseRequired)
+            ));
+
+            // Hiding a new message in the pixels drops the stale attachment
+            let reencoded = encode::encode_bytes(&encoded, b"pixels").unwrap();
+            assert_eq!(decode_bytes(&reencoded).unwrap(), b"pixels");
+        }
+    }
+
+    #[test]
+    fn test_decode_bytes_spreads_over_apng_frames() {
+        let mut carrier = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut carrier, 8, 8);
+            encoder.set_color(ColorType::Rgb);
+            encoder.set_animated(4, 0).unwrap();
+            let mut writer = encoder.write_header().unwrap();
+            for frame in 0..4 {
+                writer.write_image_data(&[frame * 50; 8 * 8 * 3]).unwrap();
+            }
+        }
+        // One frame holds 24 bytes, the header included
+        let message: Vec<u8> = (0..60).collect();
+        for options in [
+            EncodeOptions::default(),
+            EncodeOptions::default().with_walk_key("frames"),
+        ] {
+            let encoded = encode::encode_bytes_with(&carrier, &message, &options).unwrap();
+            let image = Image::read(&encoded).unwrap();
+            assert_eq!(image.frames.len(), 3);
+            let decode_options = match &options.walk_key {
+                Some(key) => DecodeOptions::default().with_walk_key(key),
+                None => DecodeOptions::default(),
+            };
+            assert_eq!(
+                decode_bytes_with(&encoded, &decode_options).unwrap(),
+                message
+            );
+        }
+    }
+}
@@ -0,0 +1,393 @@
+//! Writing message bits into carrier samples and reading them back
+//!
+//! Both directions follow the same [`Walk`] over the same [`Selection`] of
+//! samples, so the extractor visits the samples in exactly the order the
+//! embedder wrote them. Samples are one byte wide, or two big-endian bytes in
+//! 16-bit images, and only their low-order bits are ever changed.
+use crate::bit_utils::BitUtils;
+use crate::options::Embedding;
+use crate::walk::Walk;
+use rand_chacha::{
+    ChaCha20Rng,
+    rand_core::{RngCore, SeedableRng},
+};
+
+/// Samples of an image allowed to carry the message
+///
+/// The walk runs over the selected samples only; their positions in the
+/// image are computed on the fly rather than collected up front.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Selection {
+    /// Number of samples in each pixel
+    samples_per_pixel: usize,
+    /// Number of bytes in each sample, 1 or 2
+    sample_bytes: usize,
+    /// Largest value a sample can hold
+    max: u16,
+    /// Positions of the selected samples within a pixel, in order
+    selected: Vec<usize>,
+}
+
+impl Selection {
+    /// Selects samples by their position within a pixel
+    ///
+    /// # Arguments:
+    /// - **samples_per_pixel**: `usize`
+    ///   - number of samples in each pixel, 1 to 4
+    /// - **sample_bits**: `u8`
+    ///   - bit depth of the samples; samples below 8 bits are stored one per
+    ///     byte and 16-bit samples in two bytes
+    /// - **mask**: `u8`
+    ///   - bit `i` set to select the `i`-th sample of every pixel
+    pub fn new(samples_per_pixel: usize, sample_bits: u8, mask: u8) -> Selection {
+        Selection {
+            samples_per_pixel,
+            sample_bytes: (sample_bits as usize).div_ceil(8),
+            max: ((1u32 << sample_bits) - 1) as u16,
+            selected: (0..samples_per_pixel)
+                .filter(|index| mask & 1 << index != 0)
+                .collect(),
+        }
+    }
+
+    /// Number of selected samples in `bytes` bytes of image data
+    pub fn len(&self, bytes: usize) -> usize {
+        let samples = bytes / self.sample_bytes;
+        let pixels = samples / self.samples_per_pixel;
+        let rest = samples % self.samples_per_pixel;
+        pixels * self.selected.len() + self.selected.iter().filter(|i| **i < rest).count()
+    }
+
+    /// Byte offset in the image data of the `index`-th selected sample
+    pub fn position(&self, index: usize) -> usize {
+        let count = self.selected.len();
+        (index / count * self.samples_per_pixel + self.selected[index % count]) * self.sample_bytes
+    }
+
+    /// Reads the sample starting at a byte offset
+    fn sample(&self, data: &[u8], offset: usize) -> u16 {
+        data[offset..offset + self.sample_bytes]
+            .iter()
+            .fold(0, |value, byte| value << 8 | *byte as u16)
+    }
+}
+
+/// Writes message bits into the samples visited by a walk
+pub(crate) struct Embedder<'a> {
+    data: &'a mut [u8],
+    selection: Selection,
+    walk: Walk,
+    embedding: Embedding,
+    rng: ChaCha20Rng,
+}
+
+impl<'a> Embedder<'a> {
+    /// Builds an embedder walking over the selected samples of `data`
+    ///
+    /// # Arguments:
+    /// - **data**: `&mut [u8]`
+    ///   - raw samples of the carrier
+    /// - **selection**: `Selection`
+    ///   - samples allowed to carry the message
+    /// - **walk_key**: `Option<&[u8]>`
+    ///   - key shuffling the order of the selected samples
+    /// - **embedding**: `Embedding`
+    ///   - LSB replacement or LSB matching
+    pub fn new(
+        data: &'a mut [u8],
+        selection: Selection,
+        walk_key: Option<&[u8]>,
+        embedding: Embedding,
+    ) -> Embedder<'a> {
+        let walk = Walk::new(selection.len(data.len()), walk_key);
+        Embedder {
+            data,
+            selection,
+            walk,
+            embedding,
+            rng: ChaCha20Rng::from_os_rng(),
+        }
+    }
+
+    /// Embeds bits into the next samples of the walk
+    ///
+    /// # Arguments:
+    /// - **bits**: `&[u8]`
+    ///   - message bits; the last sample is padded with zeros
+    /// - **bits_per_sample**: `u8`
+    ///   - number of low-order bits replaced in each sample
+    /// # Returns:
+    /// - `usize` number of samples written, less than needed if the walk ran out
+    pub fn write(&mut self, bits: &[u8], bits_per_sample: u8) -> usize {
+        let mut written = 0;
+        for (chunk, position) in bits
+            .chunks(bits_per_sample as usize)
+            .zip(self.walk.by_ref())
+        {
+            let mut value = chunk.to_vec();
+            value.resize(bits_per_sample as usize, 0);
+            let offset = self.selection.position(position);
+            let mut sample = self.selection.sample(self.data, offset);
+            embed_value(
+                &mut sample,
+                self.selection.max,
+                &value,
+                self.embedding,
+                &mut self.rng,
+            );
+            let bytes = &mut self.data[offset..offset + self.selection.sample_bytes];
+            for (index, byte) in bytes.iter_mut().rev().enumerate() {
+                *byte = (sample >> (8 * index)) as u8;
+            }
+            written += 1;
+        }
+        written
+    }
+}
+
+/// Reads message bits back from the samples visited by a walk
+pub(crate) struct Extractor<'a> {
+    data: &'a [u8],
+    selection: Selection,
+    walk: Walk,
+}
+
+impl<'a> Extractor<'a> {
+    /// Builds an extractor walking over the selected samples of `data`, see
+    /// [`Embedder::new`]
+    pub fn new(data: &'a [u8], selection: Selection, walk_key: Option<&[u8]>) -> Extractor<'a> {
+        let walk = Walk::new(selection.len(data.len()), walk_key);
+        Extractor {
+            data,
+            selection,
+            walk,
+        }
+    }
+
+    /// Streams bytes reassembled from the next samples of the walk
+    ///
+    /// # Arguments:
+    /// - **bits_per_sample**: `u8`
+    ///   - number of low-order bits read from each sample
+    /// # Returns:
+    /// - `impl Iterator<Item = u8>` bytes, ending when the walk runs out
+    pub fn bytes(&mut self, bits_per_sample: u8) -> impl Iterator<Item = u8> + '_ {
+        let data = self.data;
+        let selection = &self.selection;
+        let mut bits = self.walk.by_ref().flat_map(move |index| {
+            let sample = selection.sample(data, selection.position(index));
+            BitUtils::read_low_bits(sample as u8, bits_per_sample)
+        });
+        std::iter::from_fn(move || {
+            let byte: Vec<u8> = bits.by_ref().take(8).collect();
+            (byte.len() == 8).then(|| BitUtils::byte_to_decimal(byte))
+        })
+    }
+}
+
+/// Changes a sample so that its low-order bits carry the given bits
+///
+/// # Arguments:
+/// - **sample**: `&mut u16`
+///   - carrier sample to change
+/// - **max**: `u16`
+///   - largest value of the sample, e.g. 1, 255 or 65535
+/// - **bits**: `&[u8]`
+///   - 1 to 8 message bits, most significant first
+/// - **embedding**: `Embedding`
+///   - LSB replacement, or LSB matching which picks the closest value
+///     carrying the bits, breaking ties at random
+/// - **rng**: `&mut ChaCha20Rng`
+///   - source of the random direction used by LSB matching
+pub(crate) fn embed_value(
+    sample: &mut u16,
+    max: u16,
+    bits: &[u8],
+    embedding: Embedding,
+    rng: &mut ChaCha20Rng,
+) {
+    let replaced = *sample & !0xFF | BitUtils::write_low_bits(*sample as u8, bits) as u16;
+    if replaced == *sample {
+        return;
+    }
+    match embedding {
+        Embedding::Replacement => *sample = replaced,
+        Embedding::Matching => {
+            let step = 1i32 << bits.len();
+            let original = *sample as i32;
+            let candidates = [
+                replaced as i32 - step,
+                replaced as i32,
+                replaced as i32 + step,
+            ];
+            let in_range = candidates
+                .iter()
+                .filter(|value| (0..=max as i32).contains(*value));
+            let closest = in_range
+                .clone()
+                .map(|value| (value - original).abs())
+                .min()
+                .unwrap_or(0);
+            let ties: Vec<i32> = in_range
+                .filter(|value| (*value - original).abs() == closest)
+                .copied()
+                .collect();
+            let pick = ties[(rng.next_u32() as usize) % ties.len()];
+            *sample = pick as u16;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_embed_value_replacement() {
+        let mut rng = ChaCha20Rng::seed_from_u64(0);
+        for (sample, bits, expected) in [
+            (10, vec![1], 11),
+            (11, vec![0], 10),
+            (10, vec![0], 10),
+            (255, vec![0], 254),
+            (0b1010_0000, vec![1, 1, 0], 0b1010_0110),
+        ] {
+            let mut value = sample;
+            embed_value(&mut value, 255, &bits, Embedding::Replacement, &mut rng);
+            assert_eq!(value, expected);
+        }
+    }
+
+    #[test]
+    fn test_embed_value_matching() {
+        let mut rng = ChaCha20Rng::seed_from_u64(0);
+        let mut went_up = false;
+        let mut went_down = false;
+        for _ in 0..64 {
+            let mut value = 100;
+            embed_value(&mut value, 255, &[1], Embedding::Matching, &mut rng);
+            assert!(value == 99 || value == 101);
+            went_up |= value == 101;
+            went_down |= value == 99;
+        }
+        assert!(went_up && went_down);
+
+        // Clamped at the ends of the range
+        let mut value = 0;
+        embed_value(&mut value, 255, &[1], Embedding::Matching, &mut rng);
+        assert_eq!(value, 1);
+        let mut value = 255;
+        embed_value(&mut value, 255, &[0], Embedding::Matching, &mut rng);
+        assert_eq!(value, 254);
+        let mut value = 42;
+        embed_value(&mut value, 255, &[0], Embedding::Matching, &mut rng);
+        assert_eq!(value, 42);
+    }
+
+    #[test]
+    fn test_embed_value_matching_multiple_bits() {
+        let mut rng = ChaCha20Rng::seed_from_u64(0);
+        // 0b0110_0011 carrying 0b00: stepping up by one beats replacing by three
+        let mut value = 0b0110_0011;
+        embed_value(&mut value, 255, &[0, 0], Embedding::Matching, &mut rng);
+        assert_eq!(value, 0b0110_0100);
+
+        // 0b0110_0000 carrying 0b11: wrapping down to 0b0101_1111 is closer than replacing
+        let mut value = 0b0110_0000;
+        embed_value(&mut value, 255, &[1, 1], Embedding::Matching, &mut rng);
+        assert_eq!(value, 0b0101_1111);
+    }
+
+    #[test]
+    fn test_embedder_and_extractor_roundtrip() {
+        let message = BitUtils::make_bits(b"low bits".to_vec());
+        for bits_per_sample in 1..=4 {
+            let mut data: Vec<u8> = (0..100).map(|i| (i * 13 % 256) as u8).collect();
+            let written = Embedder::new(
+                &mut data,
+                Selection::new(4, 8, 0b1111),
+                Some(b"key"),
+                Embedding::Matching,
+            )
+            .write(&message, bits_per_sample);
+            assert_eq!(written, message.len().div_ceil(bits_per_sample as usize));
+
+            let read: Vec<u8> = Extractor::new(&data, Selection::new(4, 8, 0b1111), Some(b"key"))
+                .bytes(bits_per_sample)
+                .take(8)
+                .collect();
+            assert_eq!(read, b"low bits");
+        }
+    }
+
+    #[test]
+    fn test_selection_skips_unselected_channels() {
+        // Red and blue of an RGBA image, alpha and green untouched
+        let selection = Selection::new(4, 8, 0b0101);
+        assert_eq!(selection.len(12), 6);
+        assert_eq!(selection.len(13), 7);
+        let positions: Vec<usize> = (0..6).map(|index| selection.position(index)).collect();
+        assert_eq!(positions, vec![0, 2, 4, 6, 8, 10]);
+
+        let mut data = vec![0x80u8; 40];
+        let message = BitUtils::make_bits(b"rb".to_vec());
+        Embedder::new(&mut data, selection.clone(), None, Embedding::Replacement)
+            .write(&message, 1);
+        assert!(
+            data.chunks(4)
+                .all(|pixel| pixel[1] == 0x80 && pixel[3] == 0x80)
+        );
+        let read: Vec<u8> = Extractor::new(&data, selection, None)
+            .bytes(1)
+            .take(2)
+            .collect();
+        assert_eq!(read, b"rb");
+    }
+
+    #[test]
+    fn test_embed_value_uses_the_whole_16_bit_range() {
+        let mut rng = ChaCha20Rng::seed_from_u64(0);
+        // Matching carries into the high byte instead of wrapping the low one
+        let mut value = 0x12FF;
+        embed_value(&mut value, u16::MAX, &[0, 0], Embedding::Matching, &mut rng);
+        assert_eq!(value, 0x1300);
+
+        let mut value = 0xFFFF;
+        embed_value(&mut value, u16::MAX, &[0, 0], Embedding::Matching, &mut rng);
+        assert_eq!(value, 0xFFFC);
+
+        let mut value = 0xABCD;
+        embed_value(
+            &mut value,
+            u16::MAX,
+            &[0; 8],
+            Embedding::Replacement,
+            &mut rng,
+        );
+        assert_eq!(value, 0xAB00);
+    }
+
+    #[test]
+    fn test_embedder_touches_only_the_low_byte_of_16_bit_samples() {
+        let message = BitUtils::make_bits(b"wide".to_vec());
+        let selection = Selection::new(3, 16, 0b111);
+        assert_eq!(selection.len(80), 40);
+        assert_eq!(selection.position(4), 8);
+
+        let mut data: Vec<u8> = (0..80).map(|i| (i * 29 % 256) as u8).collect();
+        let original = data.clone();
+        Embedder::new(&mut data, selection.clone(), None, Embedding::Replacement)
+            .write(&message, 1);
+        assert!(
+            data.iter()
+                .zip(&original)
+                .step_by(2)
+                .all(|(high, old)| high == old)
+        );
+        let read: Vec<u8> = Extractor::new(&data, selection, None)
+            .bytes(1)
+            .take(4)
+            .collect();
+        assert_eq!(read, b"wide");
+    }
+}
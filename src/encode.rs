@@ -0,0 +1,506 @@
+//! Module to provide ability to hide a message inside an image, using LSB steganography
+//!
+//! The message is prefixed with a versioned [`Header`] before embedding, so
+//! decoders can tell it apart from image noise and detect corruption. The
+//! samples are visited in the order given by a [`Walk`], which is shuffled
+//! when a walk key is set. Only the channels picked by
+//! [`EncodeOptions::channels`] are touched, alpha being skipped by default.
+//! The header takes one bit per sample, the message body as many as
+//! [`EncodeOptions::bits_per_sample`] asks for. Palette images carry one bit
+//! per pixel in the parity of the pixel's color rank, see [`Palette`]. In an
+//! APNG the message runs on through the samples of every frame.
+//! All other chunks of the carrier are written back unchanged, unless
+//! [`EncodeOptions::metadata`] asks to strip or touch the text and timestamp,
+//! and the image data is filtered, compressed and split into chunks the way
+//! the carrier's was. With [`EncodeOptions::carrier`] set to a chunk carrier,
+//! the message is attached as chunks and the pixels are left alone. The
+//! whitespace and zero-width carriers write it into a plain text cover
+//! instead of an image.
+//!
+//! [`Walk`]: crate::walk::Walk
+
+use crate::attachment;
+use crate::bit_utils::BitUtils;
+use crate::crypto::{self, CipherParams};
+use crate::embed::{Embedder, Selection};
+use crate::error::{Result, StegError};
+use crate::header::{Header, Layout, MAX_BITS_PER_SAMPLE};
+use crate::image::{Format, Image};
+use crate::options::{Carrier, Compression, Embedding, EncodeOptions};
+use crate::palette::Palette;
+use crate::text;
+use png::{BitDepth, ColorType};
+use std::{
+    fs::{read, write},
+    io::{Read, Write},
+};
+
+///
+/// Hides a message inside an image using LSB steganography
+///
+/// ## Arguments:
+///   - **src:** `&str` -  path to the source image
+///   - **msg_src:** `&str`
+///     -  path to the message file to hide
+///   - **dest:** `&str`
+///     - path to the destination image to save the result
+/// ## Returns:
+/// - `Result<()>`
+///   - `StegError::InsufficientCapacity` if the message does not fit in the image
+///   - `StegError::InvalidOptions` for more bits per sample than 4, or 8 in
+///     16-bit images and 1 in palette and sub-byte images, or a
+///     channel selection matching no channel of the image
+///
+/// ## Example:
+/// ```no_run
+///   stegarst::encode("input.png", "message.txt", "output.png")?;
+/// # Ok::<(), stegarst::StegError>(())
+/// ```
+pub fn encode(src: &str, msg_src: &str, dest: &str) -> Result<()> {
+    encode_with(src, msg_src, dest, &EncodeOptions::default())
+}
+
+/// Hides a message inside an image, see [`encode`]
+///
+/// ## Arguments:
+///   - **src:** `&str` -  path to the source image
+///   - **msg_src:** `&str`
+///     -  path to the message file to hide
+///   - **dest:** `&str`
+///     - path to the destination image to save the result
+///   - **options:** `&EncodeOptions`
+///     - encryption and embedding settings
+/// ## Returns:
+/// - `Result<()>`
+///
+/// ## Example:
+/// ```no_run
+///   let options = stegarst::EncodeOptions::default().with_passphrase("hunter2");
+///   stegarst::encode_with("input.png", "message.txt", "output.png", &options)?;
+/// # Ok::<(), stegarst::StegError>(())
+/// ```
+pub fn encode_with(src: &str, msg_src: &str, dest: &str, options: &EncodeOptions) -> Result<()> {
+    let encoded = encode_bytes_with(&read(src)?, &read(msg_src)?, options)?;
+    write(dest, encoded)?;
+    Ok(())
+}
+
+/// Hides a message read from a stream inside an image read from a stream
+///
+/// ## Arguments:
+///   - **carrier:** `impl Read`
+///     - PNG image to hide the message in
+///   - **message:** `impl Read`
+///     - message to hide
+///   - **output:** `impl Write`
+///     - destination for the resulting PNG image
+/// ## Returns:
+/// - `Result<()>`
+///
+/// ## Example:
+/// ```no_run
+///   let carrier = std::fs::File::open("input.png")?;
+///   let output = std::fs::File::create("output.png")?;
+///   stegarst::encode_stream(carrier, &b"secret"[..], output)?;
+/// # Ok::<(), stegarst::StegError>(())
+/// ```
+pub fn encode_stream(carrier: impl Read, message: impl Read, output: impl Write) -> Result<()> {
+    encode_stream_with(carrier, message, output, &EncodeOptions::default())
+}
+
+/// Hides a message read from a stream inside an image read from a stream,
+/// see [`encode_stream`]
+///
+/// ## Arguments:
+///   - **carrier:** `impl Read`
+///     - PNG image to hide the message in
+///   - **message:** `impl Read`
+///     - message to hide
+///   - **output:** `impl Write`
+///     - destination for the resulting PNG image
+///   - **options:** `&EncodeOptions`
+///     - encryption and embedding settings
+/// ## Returns:
+/// - `Result<()>`
+pub fn encode_stream_with(
+    mut carrier: impl Read,
+    mut message: impl Read,
+    mut output: impl Write,
+    options: &EncodeOptions,
+) -> Result<()> {
+    let mut carrier_bytes = Vec::new();
+    carrier.read_to_end(&mut carrier_bytes)?;
+    let mut message_bytes = Vec::new();
+    message.read_to_end(&mut message_bytes)?;
+
+    output.write_all(&encode_bytes_with(&carrier_bytes, &message_bytes, options)?)?;
+    output.flush()?;
+    Ok(())
+}
+
+/// Hides a message inside an in-memory PNG image
+///
+/// ## Arguments:
+///   - **carrier:** `&[u8]`
+///     - contents of the PNG image to hide the message in
+///   - **message:** `&[u8]`
+///     - message to hide
+/// ## Returns:
+/// - `Result<Vec<u8>>` contents of the resulting PNG image
+///
+/// ## Example:
+/// ```no_run
+///   let carrier = std::fs::read("input.png")?;
+///   let encoded = stegarst::encode_bytes(&carrier, b"secret")?;
+/// # Ok::<(), stegarst::StegError>(())
+/// ```
+pub fn encode_bytes(carrier: &[u8], message: &[u8]) -> Result<Vec<u8>> {
+    encode_bytes_with(carrier, message, &EncodeOptions::default())
+}
+
+/// Hides a message inside an in-memory PNG image, see [`encode_bytes`]
+///
+/// ## Arguments:
+///   - **carrier:** `&[u8]`
+///     - contents of the PNG image to hide the message in
+///   - **message:** `&[u8]`
+///     - message to hide
+///   - **options:** `&EncodeOptions`
+///     - encryption and embedding settings
+/// ## Returns:
+/// - `Result<Vec<u8>>` contents of the resulting PNG image
+pub fn encode_bytes_with(
+    carrier: &[u8],
+    message: &[u8],
+    options: &EncodeOptions,
+) -> Result<Vec<u8>> {
+    if let Compression::Level(level) = options.compression
+        && level > 9
+    {
+        return Err(StegError::InvalidOptions(format!(
+            "compression level {} requested, expected 0 to 9",
+            level
+        )));
+    }
+    if matches!(options.carrier, Carrier::Whitespace | Carrier::ZeroWidth) {
+        let cover = std::str::from_utf8(carrier).map_err(|_| {
+            StegError::InvalidOptions(format!(
+                "the '{}' carrier needs a UTF-8 text",
+                options.carrier
+            ))
+        })?;
+        let payload = payload(message, options)?;
+        return Ok(text::hide(cover, &payload, options.carrier).into_bytes());
+    }
+    let mut image = Image::read(carrier)?;
+    if options.carrier != Carrier::Pixels {
+        if !matches!(image.format, Format::Png) {
+            return Err(StegError::InvalidOptions(format!(
+                "the '{}' carrier needs a PNG image",
+                options.carrier
+            )));
+        }
+        image.attachments = attachment::chunks(&payload(message, options)?, options.carrier);
+        return Ok(image.write(options));
+    }
+    let bits_per_sample = options.bits_per_sample;
+    // Only the LSB of a JPEG coefficient can change without changing its
+    // size, and GIF pixels only have the parity of their palette rank
+    let single_bit = match image.format {
+        Format::Jpeg(_) => Some("JPEG images carry 1 bit per coefficient"),
+        Format::Gif(_) => Some("GIF images carry 1 bit per pixel"),
+        _ => None,
+    };
+    if let Some(reason) = single_bit
+        && bits_per_sample != 1
+    {
+        return Err(StegError::InvalidOptions(format!(
+            "{} bits per sample requested, {}",
+            bits_per_sample, reason
+        )));
+    }
+    let max_bits_per_sample = max_bits_per_sample(image.color_type, image.bit_depth);
+    if !(1..=max_bits_per_sample).contains(&bits_per_sample) {
+        return Err(StegError::InvalidOptions(format!(
+            "{} bits per sample requested, expected 1 to {} in a {:?} image at {} bits per sample",
+            bits_per_sample, max_bits_per_sample, image.color_type, image.bit_depth as u8
+        )));
+    }
+
+    // Palette images carry the message in the ranks of their pixels, which
+    // have a single channel whatever the selection
+    let palette = (image.color_type == ColorType::Indexed).then(|| {
+        Palette::new(
+            image.palette.as_deref().unwrap_or_default(),
+            image.trns.as_deref(),
+        )
+    });
+    let channels = match palette {
+        Some(_) => 1,
+        None => options.channels.mask(image.color_type),
+    };
+    if channels == 0 {
+        return Err(StegError::InvalidOptions(format!(
+            "none of the channels '{}' exist in a {:?} image",
+            options.channels, image.color_type
+        )));
+    }
+    let selection = Selection::new(image.color_type.samples(), image.bit_depth as u8, channels);
+
+    let layout = Layout {
+        bits_per_sample,
+        channels,
+    };
+    let (header, body) = seal(message, layout, options)?;
+    let header_bits = BitUtils::make_bits(header.to_bytes());
+    let body_bits = BitUtils::make_bits(body);
+    let required = header_bits.len() + body_bits.len().div_ceil(bits_per_sample as usize);
+
+    let embed = |samples: &mut [u8], embedding| {
+        let available = selection.len(samples.len());
+        if required > available {
+            return Err(StegError::InsufficientCapacity {
+                required,
+                available,
+            });
+        }
+        let walk_key = options.walk_key.as_deref();
+        let mut embedder = Embedder::new(samples, selection.clone(), walk_key, embedding);
+        embedder.write(&header_bits, 1);
+        embedder.write(&body_bits, bits_per_sample);
+        Ok(())
+    };
+    // Every frame of an animation adds to the capacity
+    let mut samples = image.samples();
+    match &palette {
+        Some(palette) => {
+            // Only swapping ranks within their pair keeps the pixel decodable
+            let mut ranks = palette.ranks(&samples);
+            embed(&mut ranks, Embedding::Replacement)?;
+            palette.apply(&mut samples, &ranks);
+        }
+        None => embed(&mut samples, options.embedding)?,
+    }
+    image.set_samples(&samples);
+
+    Ok(image.write(options))
+}
+
+/// Encrypts a message if a passphrase is set and builds its header
+///
+/// ## Arguments:
+///   - **message:** `&[u8]`
+///     - message to hide
+///   - **layout:** `Layout`
+///     - how the message is spread over the carrier samples
+///   - **options:** `&EncodeOptions`
+///     - encryption settings
+/// ## Returns:
+/// - `Result<(Header, Vec<u8>)>` the header and the message body
+fn seal(message: &[u8], layout: Layout, options: &EncodeOptions) -> Result<(Header, Vec<u8>)> {
+    match &options.passphrase {
+        Some(passphrase) => {
+            let cipher = CipherParams::generate();
+            let sealed = crypto::encrypt(passphrase, &cipher, message)?;
+            Ok((Header::new(&sealed, Some(cipher), layout)?, sealed))
+        }
+        None => Ok((Header::new(message, None, layout)?, message.to_vec())),
+    }
+}
+
+/// Seals a message and puts its header in front, for the carriers holding
+/// the payload whole rather than spread over samples
+///
+/// ## Arguments:
+///   - **message:** `&[u8]`
+///     - message to hide
+///   - **options:** `&EncodeOptions`
+///     - encryption settings
+/// ## Returns:
+/// - `Result<Vec<u8>>` the header followed by the message body
+fn payload(message: &[u8], options: &EncodeOptions) -> Result<Vec<u8>> {
+    let (header, body) = seal(message, Layout::default(), options)?;
+    let mut payload = header.to_bytes();
+    payload.extend(body);
+    Ok(payload)
+}
+
+/// Largest number of low-order bits worth changing in samples of an image
+///
+/// 16-bit samples have plenty of headroom: even 8 changed bits stay below
+/// one level of the 8 most significant bits. Palette ranks only have a parity,
+/// and samples of 4 bits or less are coarse enough that one bit shows.
+fn max_bits_per_sample(color_type: ColorType, bit_depth: BitDepth) -> u8 {
+    match (color_type, bit_depth) {
+        (ColorType::Indexed, _) => 1,
+        (_, BitDepth::One | BitDepth::Two | BitDepth::Four) => 1,
+        (_, BitDepth::Eight) => 4,
+        (_, BitDepth::Sixteen) => MAX_BITS_PER_SAMPLE,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use png::{BitDepth, ColorType, Encoder};
+    use std::fs;
+    use std::io::BufWriter;
+    use std::io::Write;
+
+    #[test]
+    fn test_encode_creates_file() {
+        let temp_dir = std::env::temp_dir();
+        let src_path = temp_dir.join("test_encode_input.png");
+        let msg_path = temp_dir.join("test_encode_message.txt");
+        let dest_path = temp_dir.join("test_encode_output.png");
+
+        // Create a simple PNG image (10x10 for sufficient capacity)
+        {
+            let file = fs::File::create(&src_path).unwrap();
+            let w = BufWriter::new(file);
+            let mut encoder = Encoder::new(w, 10, 10);
+            encoder.set_color(ColorType::Rgb);
+            encoder.set_depth(BitDepth::Eight);
+            let mut writer = encoder.write_header().unwrap();
+            let data = vec![255; 10 * 10 * 3]; // 300 bytes of data
+            writer.write_image_data(&data).unwrap();
+        }
+
+        // Create message file
+        {
+            let mut msg_file = fs::File::create(&msg_path).unwrap();
+            msg_file.write_all(b"Hi").unwrap();
+        }
+
+        // Encode
+        encode(
+            src_path.to_str().unwrap(),
+            msg_path.to_str().unwrap(),
+            dest_path.to_str().unwrap(),
+        )
+        .unwrap();
+
+        // Check if dest exists
+        assert!(dest_path.exists());
+
+        // Cleanup
+        let _ = fs::remove_file(&src_path);
+        let _ = fs::remove_file(&msg_path);
+        let _ = fs::remove_file(&dest_path);
+    }
+
+    #[test]
+    fn test_encode_rejects_message_too_large() {
+        let temp_dir = std::env::temp_dir();
+        let src_path = temp_dir.join("test_encode_small_input.png");
+        let msg_path = temp_dir.join("test_encode_large_message.txt");
+        let dest_path = temp_dir.join("test_encode_small_output.png");
+
+        // 2x2 RGB image only offers 12 bytes of capacity
+        {
+            let file = fs::File::create(&src_path).unwrap();
+            let w = BufWriter::new(file);
+            let mut encoder = Encoder::new(w, 2, 2);
+            encoder.set_color(ColorType::Rgb);
+            encoder.set_depth(BitDepth::Eight);
+            let mut writer = encoder.write_header().unwrap();
+            writer.write_image_data(&[0; 2 * 2 * 3]).unwrap();
+        }
+        fs::write(&msg_path, b"Too long").unwrap();
+
+        let result = encode(
+            src_path.to_str().unwrap(),
+            msg_path.to_str().unwrap(),
+            dest_path.to_str().unwrap(),
+        );
+        assert!(matches!(
+            result,
+            Err(StegError::InsufficientCapacity {
+                required: 184,
+                available: 12
+            })
+        ));
+        assert!(!dest_path.exists());
+
+        // Cleanup
+        let _ = fs::remove_file(&src_path);
+        let _ = fs::remove_file(&msg_path);
+    }
+
+    #[test]
+    fn test_encode_keeps_sub_byte_indexed_image_packed() {
+        let temp_dir = std::env::temp_dir();
+        let src_path = temp_dir.join("test_encode_indexed_input.png");
+        let msg_path = temp_dir.join("test_encode_indexed_message.txt");
+        let dest_path = temp_dir.join("test_encode_indexed_output.png");
+
+        {
+            let file = fs::File::create(&src_path).unwrap();
+            let w = BufWriter::new(file);
+            let mut encoder = Encoder::new(w, 20, 20);
+            encoder.set_color(ColorType::Indexed);
+            encoder.set_depth(BitDepth::Four);
+            encoder.set_palette(vec![0, 0, 0, 255, 255, 255]);
+            let mut writer = encoder.write_header().unwrap();
+            writer.write_image_data(&[0x11; 10 * 20]).unwrap();
+        }
+        fs::write(&msg_path, b"Hi").unwrap();
+
+        encode(
+            src_path.to_str().unwrap(),
+            msg_path.to_str().unwrap(),
+            dest_path.to_str().unwrap(),
+        )
+        .unwrap();
+
+        // Every pixel still points at one of the two palette entries
+        let image = Image::read(&fs::read(&dest_path).unwrap()).unwrap();
+        assert_eq!(image.bit_depth, BitDepth::Four);
+        assert_eq!(image.data.len(), 20 * 20);
+        assert!(image.data.iter().all(|index| *index <= 1));
+        assert!(image.data.contains(&0));
+
+        // Cleanup
+        let _ = fs::remove_file(&src_path);
+        let _ = fs::remove_file(&msg_path);
+        let _ = fs::remove_file(&dest_path);
+    }
+
+    #[test]
+    fn test_encode_stream_writes_png() {
+        let mut carrier = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut carrier, 10, 10);
+            encoder.set_color(ColorType::Rgb);
+            encoder.set_depth(BitDepth::Eight);
+            let mut writer = encoder.write_header().unwrap();
+            writer.write_image_data(&[255; 10 * 10 * 3]).unwrap();
+        }
+
+        let mut output = Vec::new();
+        encode_stream(std::io::Cursor::new(&carrier), &b"Hi"[..], &mut output).unwrap();
+
+        assert_eq!(&output[..8], b"\x89PNG\r\n\x1a\n");
+        assert_eq!(output, encode_bytes(&carrier, b"Hi").unwrap());
+    }
+
+    #[test]
+    fn test_encode_rejects_invalid_bits_per_sample() {
+        let mut carrier = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut carrier, 10, 10);
+            encoder.set_color(ColorType::Rgb);
+            encoder.set_depth(BitDepth::Eight);
+            let mut writer = encoder.write_header().unwrap();
+            writer.write_image_data(&[255; 10 * 10 * 3]).unwrap();
+        }
+
+        for bits_per_sample in [0, 5] {
+            let options = EncodeOptions::default().with_bits_per_sample(bits_per_sample);
+            assert!(matches!(
+                encode_bytes_with(&carrier, b"Hi", &options),
+                Err(StegError::InvalidOptions(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,131 @@
+//! Error type returned by the encoding and decoding routines
+use png::{BitDepth, ColorType};
+use std::{fmt, io};
+
+/// Everything that can go wrong while hiding or retrieving a message
+#[derive(Debug)]
+pub enum StegError {
+    /// Reading or writing a file or stream failed
+    Io(io::Error),
+    /// The carrier image could not be decoded as a PNG
+    PngDecode(png::DecodingError),
+    /// The resulting image could not be encoded as a PNG
+    PngEncode(png::EncodingError),
+    /// The carrier image is not a valid or supported file of its format
+    ImageDecode(String),
+    /// The carrier image uses a pixel layout the embedder cannot handle
+    UnsupportedColorType(ColorType, BitDepth),
+    /// The message does not fit inside the carrier image
+    InsufficientCapacity {
+        /// Number of carrier bytes needed to hold the message
+        required: usize,
+        /// Number of carrier bytes available in the image
+        available: usize,
+    },
+    /// The image does not carry a hidden message
+    NoPayload(String),
+    /// The hidden message header is present but inconsistent with the image
+    CorruptHeader(String),
+    /// The hidden message is encrypted but no passphrase was given
+    PassphraseRequired,
+    /// The passphrase is wrong or the encrypted message was modified
+    DecryptionFailed,
+    /// Deriving the key or encrypting the message failed
+    Encryption(String),
+    /// The encoding options are out of range
+    InvalidOptions(String),
+}
+
+/// Result alias used throughout the crate
+pub type Result<T> = std::result::Result<T, StegError>;
+
+impl fmt::Display for StegError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StegError::Io(err) => write!(f, "I/O error: {}", err),
+            StegError::PngDecode(err) => write!(f, "could not decode PNG image: {}", err),
+            StegError::PngEncode(err) => write!(f, "could not encode PNG image: {}", err),
+            StegError::ImageDecode(reason) => write!(f, "could not decode image: {}", reason),
+            StegError::UnsupportedColorType(color, depth) => write!(
+                f,
+                "unsupported image format: {:?} at {} bits per sample",
+                color, *depth as u8
+            ),
+            StegError::InsufficientCapacity {
+                required,
+                available,
+            } => write!(
+                f,
+                "image is too small: message size is {} and image allows for {}",
+                required, available
+            ),
+            StegError::NoPayload(reason) => write!(f, "no hidden message found: {}", reason),
+            StegError::CorruptHeader(reason) => write!(f, "corrupt message header: {}", reason),
+            StegError::PassphraseRequired => {
+                write!(
+                    f,
+                    "the hidden message is encrypted: a passphrase is required"
+                )
+            }
+            StegError::DecryptionFailed => write!(
+                f,
+                "could not decrypt message: wrong passphrase or tampered image"
+            ),
+            StegError::Encryption(reason) => write!(f, "could not encrypt message: {}", reason),
+            StegError::InvalidOptions(reason) => write!(f, "invalid options: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for StegError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            StegError::Io(err) => Some(err),
+            StegError::PngDecode(err) => Some(err),
+            StegError::PngEncode(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for StegError {
+    fn from(err: io::Error) -> Self {
+        StegError::Io(err)
+    }
+}
+
+impl From<png::DecodingError> for StegError {
+    fn from(err: png::DecodingError) -> Self {
+        StegError::PngDecode(err)
+    }
+}
+
+impl From<png::EncodingError> for StegError {
+    fn from(err: png::EncodingError) -> Self {
+        StegError::PngEncode(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display_insufficient_capacity() {
+        let err = StegError::InsufficientCapacity {
+            required: 64,
+            available: 32,
+        };
+        assert_eq!(
+            err.to_string(),
+            "image is too small: message size is 64 and image allows for 32"
+        );
+    }
+
+    #[test]
+    fn test_io_error_converts_and_exposes_source() {
+        let err: StegError = io::Error::new(io::ErrorKind::NotFound, "missing").into();
+        assert!(matches!(err, StegError::Io(_)));
+        assert!(std::error::Error::source(&err).is_some());
+    }
+}
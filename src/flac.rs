@@ -0,0 +1,914 @@
+//! FLAC reading and writing
+//!
+//! FLAC files are decoded to their samples, which are handed out like those of
+//! WAV files, and encoded again frame by frame: every frame keeps its number
+//! of samples and is compressed with fixed predictors and Rice-coded
+//! residuals, with the stereo decorrelation that suits it best. Metadata
+//! blocks, Vorbis comments and pictures included, are copied over as they
+//! were; only STREAMINFO gets the new frame sizes and audio checksum, and
+//! seek points the new frame offsets. No LPC coefficients are searched for,
+//! so the output may come out somewhat larger than the carrier.
+use crate::error::{Result, StegError};
+use crate::image::{Format, Image};
+use claxon::FlacReader;
+use md5::{Digest, Md5};
+use png::{BitDepth, ColorType};
+use std::io::Cursor;
+
+/// Signature every FLAC file starts with
+pub(crate) const SIGNATURE: [u8; 4] = *b"fLaC";
+
+/// Metadata block type of STREAMINFO
+const STREAMINFO: u8 = 0;
+/// Metadata block type of SEEKTABLE
+const SEEKTABLE: u8 = 3;
+/// Sample number of a seek point kept free for later use
+const PLACEHOLDER: u64 = u64::MAX;
+/// Highest Rice partition order tried
+const MAX_PARTITION_ORDER: u32 = 8;
+/// Most samples a byte of FLAC file may decode to. Audio rarely compresses
+/// to less than a bit per sample, but runs of constant samples do, so only a
+/// file claiming far more is taken for a decompression bomb
+const MAX_SAMPLES_PER_BYTE: usize = 64;
+
+/// A decoded FLAC file, with its metadata and frame layout
+pub(crate) struct Flac {
+    /// Type and contents of every metadata block, in file order
+    blocks: Vec<(u8, Vec<u8>)>,
+    channels: usize,
+    bits_per_sample: u32,
+    /// Whether frames are numbered by their first sample rather than their index
+    variable: bool,
+    /// Number of samples per channel of every frame
+    block_sizes: Vec<usize>,
+    /// Samples of every channel, interleaved, as decoded
+    samples: Vec<i32>,
+}
+
+/// Decodes an in-memory FLAC file
+///
+/// # Arguments:
+/// - **bytes**: `&[u8]`
+///   - complete FLAC file contents
+/// # Returns:
+/// - `Result<Image>` samples of every channel, interleaved, big-endian pairs
+///   above 8 bits
+///   - `StegError::ImageDecode` for a malformed or unsupported file
+pub(crate) fn read(bytes: &[u8]) -> Result<Image> {
+    let invalid =
+        |reason: &dyn std::fmt::Display| StegError::ImageDecode(format!("FLAC: {}", reason));
+
+    let mut blocks = Vec::new();
+    let mut position = SIGNATURE.len();
+    loop {
+        let header = bytes
+            .get(position..position + 4)
+            .ok_or_else(|| invalid(&"truncated metadata"))?;
+        let len = u32::from_be_bytes([0, header[1], header[2], header[3]]) as usize;
+        let body = bytes
+            .get(position + 4..position + 4 + len)
+            .ok_or_else(|| invalid(&"truncated metadata"))?;
+        blocks.push((header[0] & 0x7F, body.to_vec()));
+        position += 4 + len;
+        if header[0] & 0x80 != 0 {
+            break;
+        }
+    }
+    if blocks[0].0 != STREAMINFO || blocks[0].1.len() != 34 {
+        return Err(invalid(&"missing STREAMINFO block"));
+    }
+    // The blocking strategy is the last bit of the second byte of every frame
+    let variable = bytes.get(position + 1).is_some_and(|byte| byte & 1 == 1);
+
+    let mut reader = FlacReader::new(Cursor::new(bytes)).map_err(|e| invalid(&e))?;
+    let info = reader.streaminfo();
+    let (channels, bits_per_sample) = (info.channels as usize, info.bits_per_sample);
+    if !matches!(bits_per_sample, 8 | 16 | 24) {
+        return Err(invalid(&format!(
+            "{}-bit samples are not supported, expected 8, 16 or 24",
+            bits_per_sample
+        )));
+    }
+    // Frames may not hold more samples than STREAMINFO announces, when it
+    // does, and samples are handed out as a single row
+    let max_samples = bytes
+        .len()
+        .saturating_mul(MAX_SAMPLES_PER_BYTE)
+        .min(u32::MAX as usize)
+        .min(info.samples.map_or(usize::MAX, |total| {
+            (total as usize).saturating_mul(channels).max(1)
+        }));
+    let mut block_sizes = Vec::new();
+    let mut samples: Vec<i32> = Vec::new();
+    let mut frames = reader.blocks();
+    let mut buffer = Vec::new();
+    while let Some(block) = frames.read_next_or_eof(buffer).map_err(|e| invalid(&e))? {
+        if block.channels() as usize != channels {
+            return Err(invalid(&"frame with a different number of channels"));
+        }
+        let len = block.duration() as usize * channels;
+        if samples.len() + len > max_samples {
+            return Err(invalid(&"more samples than the file can hold"));
+        }
+        samples
+            .try_reserve(len)
+            .map_err(|_| invalid(&"too many samples to decode"))?;
+        for sample in 0..block.duration() {
+            for channel in 0..block.channels() {
+                samples.push(block.sample(channel, sample));
+            }
+        }
+        block_sizes.push(block.duration() as usize);
+        buffer = block.into_buffer();
+    }
+
+    // Samples are handed out like those of WAV files: offset binary up to 16
+    // bits, and only the low 16 bits of 24-bit samples
+    let mut data = Vec::with_capacity(samples.len() * 2);
+    for sample in &samples {
+        match bits_per_sample {
+            8 => data.push((sample + 128) as u8),
+            16 => data.extend((*sample as u16 ^ 0x8000).to_be_bytes()),
+            _ => data.extend((*sample as u16).to_be_bytes()),
+        }
+    }
+    Ok(Image {
+        width: samples.len() as u32,
+        height: 1,
+        color_type: ColorType::Grayscale,
+        bit_depth: match bits_per_sample {
+            8 => BitDepth::Eight,
+            _ => BitDepth::Sixteen,
+        },
+        palette: None,
+        trns: None,
+        interlaced: false,
+        chunks: Vec::new(),
+        attachments: Vec::new(),
+        data,
+        frames: Vec::new(),
+        format: Format::Flac(Flac {
+            blocks,
+            channels,
+            bits_per_sample,
+            variable,
+            block_sizes,
+            samples,
+        }),
+    })
+}
+
+/// Encodes an image read from a FLAC file as a FLAC file
+///
+/// # Arguments:
+/// - **image**: `&Image`
+///   - image returned by [`read`], with changed samples
+/// - **flac**: `&Flac`
+///   - the original file, decoded
+/// # Returns:
+/// - `Vec<u8>` complete FLAC file contents
+pub(crate) fn write(image: &Image, flac: &Flac) -> Vec<u8> {
+    let samples: Vec<i32> = match flac.bits_per_sample {
+        8 => image
+            .data
+            .iter()
+            .map(|sample| *sample as i32 - 128)
+            .collect(),
+        16 => image
+            .data
+            .chunks_exact(2)
+            .map(|pair| (u16::from_be_bytes([pair[0], pair[1]]) ^ 0x8000) as i16 as i32)
+            .collect(),
+        _ => image
+            .data
+            .chunks_exact(2)
+            .zip(&flac.samples)
+            .map(|(pair, sample)| sample & !0xFFFF | u16::from_be_bytes([pair[0], pair[1]]) as i32)
+            .collect(),
+    };
+    encode(flac, &samples)
+}
+
+/// Encodes samples as a FLAC file, with the metadata and frame layout of another
+///
+/// # Arguments:
+/// - **flac**: `&Flac`
+///   - the original file, decoded
+/// - **samples**: `&[i32]`
+///   - samples of every channel, interleaved
+/// # Returns:
+/// - `Vec<u8>` complete FLAC file contents
+fn encode(flac: &Flac, samples: &[i32]) -> Vec<u8> {
+    let mut audio = Vec::new();
+    // First sample, offset and size of every frame, for the seek points
+    let mut frames = Vec::with_capacity(flac.block_sizes.len());
+    let (mut min_frame_len, mut max_frame_len) = (usize::MAX, 0);
+    let mut first = 0;
+    for (index, block_size) in flac.block_sizes.iter().enumerate() {
+        let start = audio.len();
+        frames.push((first as u64, start as u64, *block_size as u16));
+        let number = match flac.variable {
+            true => first,
+            false => index,
+        };
+        encode_frame(
+            &mut audio,
+            flac.variable,
+            number as u64,
+            &samples[first * flac.channels..(first + block_size) * flac.channels],
+            flac.channels,
+            flac.bits_per_sample,
+        );
+        min_frame_len = min_frame_len.min(audio.len() - start);
+        max_frame_len = max_frame_len.max(audio.len() - start);
+        first += block_size;
+    }
+    // The checksum covers the samples as little-endian bytes
+    let sample_len = flac.bits_per_sample as usize / 8;
+    let raw: Vec<u8> = samples
+        .iter()
+        .flat_map(|sample| sample.to_le_bytes().into_iter().take(sample_len))
+        .collect();
+    let md5 = Md5::digest(&raw);
+
+    let mut file = SIGNATURE.to_vec();
+    for (index, (kind, body)) in flac.blocks.iter().enumerate() {
+        let mut body = body.clone();
+        match *kind {
+            STREAMINFO => {
+                if !frames.is_empty() {
+                    body[4..7].copy_from_slice(&(min_frame_len as u32).to_be_bytes()[1..]);
+                    body[7..10].copy_from_slice(&(max_frame_len as u32).to_be_bytes()[1..]);
+                }
+                body[18..34].copy_from_slice(&md5);
+            }
+            SEEKTABLE => {
+                for point in body.chunks_exact_mut(18) {
+                    let sample = u64::from_be_bytes(point[..8].try_into().unwrap());
+                    if sample == PLACEHOLDER {
+                        continue;
+                    }
+                    // A seek point leads to the frame holding its sample
+                    let frame = frames
+                        .partition_point(|(first, ..)| *first <= sample)
+                        .saturating_sub(1);
+                    if let Some((first, offset, block_size)) = frames.get(frame) {
+                        point[..8].copy_from_slice(&first.to_be_bytes());
+                        point[8..16].copy_from_slice(&offset.to_be_bytes());
+                        point[16..].copy_from_slice(&block_size.to_be_bytes());
+                    }
+                }
+            }
+            _ => {}
+        }
+        let last = match index + 1 == flac.blocks.len() {
+            true => 0x80,
+            false => 0,
+        };
+        file.push(kind | last);
+        file.extend(&(body.len() as u32).to_be_bytes()[1..]);
+        file.extend(body);
+    }
+    file.extend(audio);
+    file
+}
+
+/// Writes bits most significant first, as FLAC frames are laid out
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    buffer: u64,
+    /// Number of bits in the buffer not written out yet
+    len: u32,
+}
+
+impl BitWriter {
+    /// Writes the low `bits` bits of a value, 32 at most
+    fn write(&mut self, value: u64, bits: u32) {
+        self.buffer = self.buffer << bits | value & ((1 << bits) - 1);
+        self.len += bits;
+        while self.len >= 8 {
+            self.len -= 8;
+            self.bytes.push((self.buffer >> self.len) as u8);
+        }
+    }
+
+    /// Writes a value as that many zeros followed by a one
+    fn write_unary(&mut self, mut value: u64) {
+        while value >= 32 {
+            self.write(0, 32);
+            value -= 32;
+        }
+        self.write(1, value as u32 + 1);
+    }
+
+    /// Pads the last byte with zeros
+    fn align(&mut self) {
+        if self.len > 0 {
+            self.write(0, 8 - self.len);
+        }
+    }
+}
+
+/// How a subframe is encoded, and its estimated size
+struct Subframe {
+    kind: Kind,
+    /// Low bits that are zero in every sample, left out of the encoding
+    wasted: u32,
+    /// Size in bits
+    len: u64,
+}
+
+enum Kind {
+    /// Every sample has the same value
+    Constant,
+    /// Samples stored as they are
+    Verbatim,
+    /// Fixed polynomial predictor of the given order, with the partition order
+    /// and Rice parameters of its residual
+    Fixed {
+        order: usize,
+        residual: Vec<i64>,
+        partition_order: u32,
+        parameters: Vec<u32>,
+    },
+}
+
+/// Picks the smallest encoding of the samples of one channel
+///
+/// # Arguments:
+/// - **samples**: `&[i64]`
+///   - samples of the channel in the frame
+/// - **bits**: `u32`
+///   - bits per sample, one more for a side channel
+/// # Returns:
+/// - `Subframe` the encoding to use
+fn plan_subframe(samples: &[i64], bits: u32) -> Subframe {
+    // Subframe header: padding bit, type and wasted bits flag
+    if samples.iter().all(|sample| *sample == samples[0]) {
+        return Subframe {
+            kind: Kind::Constant,
+            wasted: 0,
+            len: 8 + bits as u64,
+        };
+    }
+    // Wasted bits are counted in unary after the header
+    let wasted = samples
+        .iter()
+        .fold(0, |bits, sample| bits | sample)
+        .trailing_zeros();
+    let samples: Vec<i64> = samples.iter().map(|sample| sample >> wasted).collect();
+    let (bits, header) = (bits - wasted, 8 + wasted as u64);
+    let mut best = Subframe {
+        kind: Kind::Verbatim,
+        wasted,
+        len: header + samples.len() as u64 * bits as u64,
+    };
+    let mut residual = samples.to_vec();
+    for order in 0..samples.len().min(5) {
+        if order > 0 {
+            residual = residual.windows(2).map(|pair| pair[1] - pair[0]).collect();
+        }
+        let (partition_order, parameters, len) = plan_residual(&residual, samples.len(), order);
+        let len = header + (order as u64 * bits as u64) + len;
+        if len < best.len {
+            best = Subframe {
+                kind: Kind::Fixed {
+                    order,
+                    residual: residual.clone(),
+                    partition_order,
+                    parameters,
+                },
+                wasted,
+                len,
+            };
+        }
+    }
+    best
+}
+
+/// Picks the partition order and Rice parameters of a residual
+///
+/// # Arguments:
+/// - **residual**: `&[i64]`
+///   - prediction errors of every sample after the warm-up ones
+/// - **block_size**: `usize`
+///   - samples in the frame
+/// - **order**: `usize`
+///   - predictor order, the number of warm-up samples
+/// # Returns:
+/// - `(u32, Vec<u32>, u64)` partition order, parameter of every partition and
+///   estimated size in bits
+fn plan_residual(residual: &[i64], block_size: usize, order: usize) -> (u32, Vec<u32>, u64) {
+    let mut sums = vec![0];
+    for value in residual {
+        sums.push(sums.last().unwrap() + zigzag(*value));
+    }
+    let mut best = (0, Vec::new(), u64::MAX);
+    let mut partition_order = 0;
+    loop {
+        let partition_len = block_size >> partition_order;
+        let mut parameters = Vec::with_capacity(1 << partition_order);
+        let mut len = 0;
+        for partition in 0..1 << partition_order {
+            let start = (partition * partition_len).max(order) - order;
+            let end = (partition + 1) * partition_len - order;
+            let (count, sum) = ((end - start) as u64, sums[end] - sums[start]);
+            // Each value takes its quotient in unary, a stop bit and the remainder
+            let (parameter, partition_len) = (0..=30)
+                .map(|parameter| {
+                    (
+                        parameter,
+                        count * (parameter as u64 + 1) + (sum >> parameter),
+                    )
+                })
+                .min_by_key(|(_, len)| *len)
+                .unwrap();
+            parameters.push(parameter);
+            len += partition_len;
+        }
+        let parameter_len = match parameters.iter().all(|parameter| *parameter < 15) {
+            true => 4,
+            false => 5,
+        };
+        len += 6 + parameters.len() as u64 * parameter_len;
+        if len < best.2 {
+            best = (partition_order, parameters, len);
+        }
+        // Partitions must split the frame evenly and outnumber the warm-up samples
+        partition_order += 1;
+        if partition_order > MAX_PARTITION_ORDER
+            || !block_size.is_multiple_of(1 << partition_order)
+            || block_size >> partition_order <= order
+        {
+            return best;
+        }
+    }
+}
+
+/// Maps signed values to unsigned ones, 0, -1, 1, -2... to 0, 1, 2, 3...
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+/// Writes one subframe
+fn write_subframe(writer: &mut BitWriter, samples: &[i64], bits: u32, subframe: &Subframe) {
+    let wasted = subframe.wasted;
+    let samples: Vec<i64> = samples.iter().map(|sample| sample >> wasted).collect();
+    let bits = bits - wasted;
+    // The wasted bits flag ends the header byte
+    let flag = (wasted > 0) as u64;
+    match &subframe.kind {
+        Kind::Constant => {
+            writer.write(0b0000_0000, 8);
+            writer.write(samples[0] as u64, bits);
+        }
+        Kind::Verbatim => {
+            writer.write(0b0000_0010 | flag, 8);
+            if wasted > 0 {
+                writer.write_unary(wasted as u64 - 1);
+            }
+            for sample in &samples {
+                writer.write(*sample as u64, bits);
+            }
+        }
+        Kind::Fixed {
+            order,
+            residual,
+            partition_order,
+            parameters,
+        } => {
+            writer.write(0b0001_0000 | (*order as u64) << 1 | flag, 8);
+            if wasted > 0 {
+                writer.write_unary(wasted as u64 - 1);
+            }
+            for sample in &samples[..*order] {
+                writer.write(*sample as u64, bits);
+            }
+            // Parameters of 15 and up need the 5-bit coding method
+            let parameter_len = match parameters.iter().all(|parameter| *parameter < 15) {
+                true => 4,
+                false => 5,
+            };
+            writer.write(parameter_len as u64 - 4, 2);
+            writer.write(*partition_order as u64, 4);
+            let partition_len = samples.len() >> partition_order;
+            let mut values = residual.iter();
+            for (partition, parameter) in parameters.iter().enumerate() {
+                writer.write(*parameter as u64, parameter_len);
+                let count = match partition {
+                    0 => partition_len - order,
+                    _ => partition_len,
+                };
+                for value in values.by_ref().take(count) {
+                    let value = zigzag(*value);
+                    writer.write_unary(value >> parameter);
+                    writer.write(value, *parameter);
+                }
+            }
+        }
+    }
+}
+
+/// Encodes one frame, its samples taking their bit depth from STREAMINFO
+///
+/// # Arguments:
+/// - **output**: `&mut Vec<u8>`
+///   - encoded frames so far
+/// - **variable**: `bool`
+///   - whether the frame is numbered by its first sample
+/// - **number**: `u64`
+///   - frame index or first sample
+/// - **samples**: `&[i32]`
+///   - samples of every channel, interleaved
+/// - **channels**: `usize`
+///   - number of channels
+/// - **bits**: `u32`
+///   - bits per sample
+fn encode_frame(
+    output: &mut Vec<u8>,
+    variable: bool,
+    number: u64,
+    samples: &[i32],
+    channels: usize,
+    bits: u32,
+) {
+    let block_size = samples.len() / channels;
+    let mut subframes: Vec<(Vec<i64>, u32)> = (0..channels)
+        .map(|channel| {
+            let samples = samples.iter().skip(channel).step_by(channels);
+            (samples.map(|sample| *sample as i64).collect(), bits)
+        })
+        .collect();
+    let mut plans: Vec<Subframe> = subframes
+        .iter()
+        .map(|(samples, bits)| plan_subframe(samples, *bits))
+        .collect();
+    let mut assignment = channels as u64 - 1;
+    if channels == 2 {
+        // Side channels need one more bit, a mid channel does not
+        let (right, left) = (subframes.pop().unwrap(), subframes.pop().unwrap());
+        let (right_plan, left_plan) = (plans.pop().unwrap(), plans.pop().unwrap());
+        let pairs = left.0.iter().zip(&right.0);
+        let side = (
+            pairs.clone().map(|(l, r)| l - r).collect::<Vec<_>>(),
+            bits + 1,
+        );
+        let mid = (pairs.map(|(l, r)| (l + r) >> 1).collect::<Vec<_>>(), bits);
+        let (side_plan, mid_plan) = (plan_subframe(&side.0, side.1), plan_subframe(&mid.0, mid.1));
+        let costs = [
+            left_plan.len + right_plan.len,
+            left_plan.len + side_plan.len,
+            side_plan.len + right_plan.len,
+            mid_plan.len + side_plan.len,
+        ];
+        (subframes, plans, assignment) = match (0..4).min_by_key(|choice| costs[*choice]) {
+            Some(0) => (vec![left, right], vec![left_plan, right_plan], 1),
+            Some(1) => (vec![left, side], vec![left_plan, side_plan], 0b1000),
+            Some(2) => (vec![side, right], vec![side_plan, right_plan], 0b1001),
+            _ => (vec![mid, side], vec![mid_plan, side_plan], 0b1010),
+        };
+    }
+
+    let mut writer = BitWriter::default();
+    writer.write(0b11_1111_1111_1110, 14);
+    writer.write(0, 1);
+    writer.write(variable as u64, 1);
+    // The block size follows the frame number, the sample rate comes from
+    // STREAMINFO
+    writer.write(0b0111, 4);
+    writer.write(0, 4);
+    writer.write(assignment, 4);
+    let sample_size = match bits {
+        8 => 0b001,
+        16 => 0b100,
+        _ => 0b110,
+    };
+    writer.write(sample_size, 3);
+    writer.write(0, 1);
+    for byte in utf8(number) {
+        writer.write(byte as u64, 8);
+    }
+    writer.write(block_size as u64 - 1, 16);
+    let crc = crc8(&writer.bytes);
+    writer.write(crc as u64, 8);
+    for ((samples, bits), plan) in subframes.iter().zip(&plans) {
+        write_subframe(&mut writer, samples, *bits, plan);
+    }
+    writer.align();
+    let crc = crc16(&writer.bytes);
+    output.extend(writer.bytes);
+    output.extend(crc.to_be_bytes());
+}
+
+/// Codes a frame or sample number the way UTF-8 codes characters, up to 36 bits
+fn utf8(value: u64) -> Vec<u8> {
+    if value < 0x80 {
+        return vec![value as u8];
+    }
+    // Every byte added holds 5 more bits: 6 of its own, less one in the lead byte
+    let len = (2..7).find(|len| value < 1 << (5 * len + 1)).unwrap_or(7);
+    let mut bytes: Vec<u8> = (0..len)
+        .map(|index| 0x80 | (value >> (6 * (len - 1 - index))) as u8 & 0x3F)
+        .collect();
+    bytes[0] = (0xFF00u16 >> len) as u8 | (value >> (6 * (len - 1))) as u8;
+    bytes
+}
+
+/// CRC-8 of a frame header, polynomial x^8 + x^2 + x + 1
+fn crc8(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0, |crc, byte| {
+        (0..8).fold(crc ^ byte, |crc, _| match crc & 0x80 {
+            0 => crc << 1,
+            _ => crc << 1 ^ 0x07,
+        })
+    })
+}
+
+/// CRC-16 of a frame, polynomial x^16 + x^15 + x^2 + 1
+fn crc16(bytes: &[u8]) -> u16 {
+    bytes.iter().fold(0, |crc, byte| {
+        crc << 8 ^ CRC16_TABLE[((crc >> 8) as u8 ^ byte) as usize]
+    })
+}
+
+const CRC16_TABLE: [u16; 256] = {
+    let mut table = [0; 256];
+    let mut index = 0;
+    while index < 256 {
+        let mut crc = (index as u16) << 8;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = match crc & 0x8000 {
+                0 => crc << 1,
+                _ => crc << 1 ^ 0x8005,
+            };
+            bit += 1;
+        }
+        table[index] = crc;
+        index += 1;
+    }
+    table
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::{DecodeOptions, EncodeOptions};
+    use crate::{decode_bytes, decode_bytes_with, encode_bytes, encode_bytes_with};
+
+    // Mono 16-bit file written by libFLAC 1.3.2, with a seek table and a
+    // Vorbis comment, from the test samples of claxon
+    const LIBFLAC: &[u8] = include_bytes!("../testdata/libflac.flac");
+    // Stereo 24-bit file with order-20 LPC subframes, outside the subset
+    const LPC: &[u8] = include_bytes!("../testdata/lpc.flac");
+
+    // Layout of a FLAC file with the given frames and extra metadata blocks
+    fn flac(
+        channels: usize,
+        bits_per_sample: u32,
+        variable: bool,
+        block_sizes: Vec<usize>,
+        samples: Vec<i32>,
+        extra: Vec<(u8, Vec<u8>)>,
+    ) -> Flac {
+        let mut streaminfo = vec![0; 34];
+        let max_block_size = *block_sizes.iter().max().unwrap() as u16;
+        streaminfo[..2].copy_from_slice(&max_block_size.to_be_bytes());
+        streaminfo[2..4].copy_from_slice(&max_block_size.to_be_bytes());
+        let packed = 44100 << 44
+            | (channels as u64 - 1) << 41
+            | (bits_per_sample as u64 - 1) << 36
+            | (samples.len() / channels) as u64;
+        streaminfo[10..18].copy_from_slice(&packed.to_be_bytes());
+        let mut blocks = vec![(STREAMINFO, streaminfo)];
+        blocks.extend(extra);
+        Flac {
+            blocks,
+            channels,
+            bits_per_sample,
+            variable,
+            block_sizes,
+            samples,
+        }
+    }
+
+    // Wave with some noise, silent for a while, in the given number of bits
+    fn signal(channels: usize, bits_per_sample: u32, len: usize) -> Vec<i32> {
+        let amplitude = (1 << (bits_per_sample - 2)) as f64;
+        (0..len * channels)
+            .map(|i| match i / channels {
+                500..1000 => 0,
+                frame => {
+                    let wave = (frame as f64 * 0.03 + (i % channels) as f64).sin() * amplitude;
+                    wave as i32 + (i * 7919 % 13) as i32
+                }
+            })
+            .collect()
+    }
+
+    fn decoded(file: &[u8]) -> (claxon::metadata::StreamInfo, Vec<i32>) {
+        let mut reader = FlacReader::new(Cursor::new(file)).unwrap();
+        let samples = reader.samples().map(|sample| sample.unwrap()).collect();
+        (reader.streaminfo(), samples)
+    }
+
+    #[test]
+    fn test_utf8_numbers() {
+        assert_eq!(utf8(0x7F), vec![0x7F]);
+        assert_eq!(utf8(0x80), vec![0xC2, 0x80]);
+        assert_eq!(utf8(0x7FF), vec![0xDF, 0xBF]);
+        assert_eq!(utf8(0x10000), vec![0xF0, 0x90, 0x80, 0x80]);
+        assert_eq!(
+            utf8(0xF_FFFF_FFFF),
+            vec![0xFE, 0xBF, 0xBF, 0xBF, 0xBF, 0xBF, 0xBF]
+        );
+    }
+
+    #[test]
+    fn test_encode_decodes_to_samples() {
+        for (channels, bits_per_sample, variable) in
+            [(1, 8, false), (2, 16, false), (2, 24, true), (3, 16, true)]
+        {
+            let block_sizes = vec![1152, 4608, 16, 100];
+            let mut samples = signal(channels, bits_per_sample, block_sizes.iter().sum());
+            // Frames whose samples share low zero bits store them once
+            let wasted = 1152 * channels..(1152 + 4608) * channels;
+            samples[wasted]
+                .iter_mut()
+                .for_each(|sample| *sample &= !0b111);
+            let flac = flac(
+                channels,
+                bits_per_sample,
+                variable,
+                block_sizes,
+                samples,
+                vec![],
+            );
+
+            let file = encode(&flac, &flac.samples);
+            let (streaminfo, samples) = decoded(&file);
+            assert_eq!(samples, flac.samples);
+            let raw: Vec<u8> = samples
+                .iter()
+                .flat_map(|sample| {
+                    sample
+                        .to_le_bytes()
+                        .into_iter()
+                        .take(bits_per_sample as usize / 8)
+                })
+                .collect();
+            assert_eq!(streaminfo.md5sum[..], Md5::digest(&raw)[..]);
+            // Compressed well below the raw samples
+            assert!(file.len() < raw.len() * 3 / 4);
+        }
+    }
+
+    #[test]
+    fn test_libflac_metadata_survives_reencoding() {
+        // A picture block after STREAMINFO, as metaflac would add it
+        let mut picture = vec![0, 0, 0, 3, 0, 0, 0, 9];
+        picture.extend(b"image/png");
+        picture.extend([0; 20]);
+        picture.extend([0, 0, 0, 4, 0x89, b'P', b'N', b'G']);
+        let mut with_picture = LIBFLAC[..42].to_vec();
+        with_picture.extend([6, 0, 0, picture.len() as u8]);
+        with_picture.extend(&picture);
+        with_picture.extend(&LIBFLAC[42..]);
+
+        for carrier in [with_picture, LPC.to_vec()] {
+            let encoded = encode_bytes(&carrier, b"libFLAC").unwrap();
+            assert_eq!(decode_bytes(&encoded).unwrap(), b"libFLAC");
+
+            let Format::Flac(before) = read(&carrier).unwrap().format else {
+                unreachable!()
+            };
+            let Format::Flac(after) = read(&encoded).unwrap().format else {
+                unreachable!()
+            };
+            assert_eq!(after.block_sizes, before.block_sizes);
+            // Every block but STREAMINFO and the seek table comes through as it was
+            assert_eq!(after.blocks.len(), before.blocks.len());
+            for (before, after) in before.blocks.iter().zip(&after.blocks) {
+                assert_eq!(before.0, after.0);
+                if !matches!(before.0, STREAMINFO | SEEKTABLE) {
+                    assert_eq!(before.1, after.1);
+                }
+            }
+
+            // claxon decodes the output to samples matching its new checksum
+            let (streaminfo, samples) = decoded(&encoded);
+            assert_eq!(samples, after.samples);
+            let sample_len = after.bits_per_sample as usize / 8;
+            let raw: Vec<u8> = samples
+                .iter()
+                .flat_map(|sample| sample.to_le_bytes().into_iter().take(sample_len))
+                .collect();
+            assert_eq!(streaminfo.md5sum[..], Md5::digest(&raw)[..]);
+        }
+    }
+
+    #[test]
+    fn test_read_rejects_damaged_metadata_and_frames() {
+        let mut no_streaminfo = LIBFLAC.to_vec();
+        no_streaminfo[4] = 1;
+        let mut no_metadata_end = LIBFLAC[..42].to_vec();
+        no_metadata_end.extend([0x7F; 64]);
+        for refused in [
+            &LIBFLAC[..30],
+            &no_streaminfo,
+            &no_metadata_end,
+            &LIBFLAC[..LIBFLAC.len() - 100],
+        ] {
+            assert!(matches!(read(refused), Err(StegError::ImageDecode(_))));
+        }
+    }
+
+    #[test]
+    fn test_read_rejects_oversized_streams() {
+        // A single sample announced in STREAMINFO, in front of the frames
+        let mut understated = LIBFLAC.to_vec();
+        understated[21] &= 0xF0;
+        understated[22..26].copy_from_slice(&1u32.to_be_bytes());
+        // Frames of constant samples, each a few bytes long
+        let silence = flac(
+            8,
+            16,
+            false,
+            vec![65535; 16],
+            vec![0; 65535 * 16 * 8],
+            Vec::new(),
+        );
+        let silence = encode(&silence, &silence.samples);
+        assert!(silence.len() * MAX_SAMPLES_PER_BYTE < 65535 * 16 * 8);
+        for refused in [understated, silence] {
+            match read(&refused) {
+                Err(StegError::ImageDecode(reason)) => assert!(reason.contains("samples")),
+                _ => panic!("oversized FLAC accepted"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_seek_points_follow_the_new_frames() {
+        let comment = b"\x06\0\0\0vendor\x01\0\0\0\x0b\0\0\0TITLE=steg?".to_vec();
+        let picture = vec![
+            0, 0, 0, 3, 0, 0, 0, 9, b'i', b'm', b'a', b'g', b'e', b'/', b'p',
+        ];
+        let mut seektable = Vec::new();
+        for sample in [0, 4096, 9000, PLACEHOLDER] {
+            seektable.extend(sample.to_be_bytes());
+            seektable.extend([0; 10]);
+        }
+        // Vorbis comment, picture, seek table and padding blocks
+        let extra = vec![
+            (4, comment),
+            (6, picture),
+            (SEEKTABLE, seektable),
+            (1, vec![0; 64]),
+        ];
+        let block_sizes = vec![4096, 4096, 4096, 1000];
+        let samples = signal(2, 24, block_sizes.iter().sum());
+        let layout = flac(2, 24, false, block_sizes, samples, extra.clone());
+        let carrier = encode(&layout, &layout.samples);
+
+        let encode_options = EncodeOptions::default().with_passphrase("lossless");
+        let decode_options = DecodeOptions::default().with_passphrase("lossless");
+        let encoded = encode_bytes_with(&carrier, b"audio", &encode_options).unwrap();
+        assert!(encoded.starts_with(&SIGNATURE));
+        assert_eq!(
+            decode_bytes_with(&encoded, &decode_options).unwrap(),
+            b"audio"
+        );
+
+        let image = read(&encoded).unwrap();
+        let Format::Flac(encoded_flac) = &image.format else {
+            unreachable!()
+        };
+        let Format::Flac(carrier_flac) = &read(&carrier).unwrap().format else {
+            unreachable!()
+        };
+        // Metadata is copied over, the high bytes of the samples are untouched
+        assert_eq!(encoded_flac.blocks[1..3], carrier_flac.blocks[1..3]);
+        assert_eq!(encoded_flac.blocks[4], extra[3]);
+        assert_ne!(encoded_flac.samples, carrier_flac.samples);
+        for (old, new) in carrier_flac.samples.iter().zip(&encoded_flac.samples) {
+            assert_eq!(old >> 16, new >> 16);
+        }
+
+        // Seek points lead to the frames holding their sample
+        let audio = 4 + encoded_flac
+            .blocks
+            .iter()
+            .map(|(_, body)| 4 + body.len())
+            .sum::<usize>();
+        let points: Vec<&[u8]> = encoded_flac.blocks[3].1.chunks(18).collect();
+        for (point, first) in points.iter().zip([0, 4096, 8192]) {
+            assert_eq!(point[..8], (first as u64).to_be_bytes());
+            let offset = u64::from_be_bytes(point[8..16].try_into().unwrap()) as usize;
+            assert_eq!(encoded[audio + offset..audio + offset + 2], [0xFF, 0xF8]);
+            assert_eq!(point[16..], 4096u16.to_be_bytes());
+        }
+        assert_eq!(points[3][..8], PLACEHOLDER.to_be_bytes());
+    }
+}
@@ -0,0 +1,301 @@
+//! GIF reading and writing, animations included
+//!
+//! Like palette PNGs, GIFs carry the message in the parity of each pixel's
+//! rank in its sorted palette, see [`Palette`]. Frames may each have their own
+//! palette, so the ranks are taken per frame and only the pixels able to swap
+//! with a partner rank are handed out as samples, one bit each. The frames are
+//! encoded again with their palettes, position, delay, disposal method,
+//! transparency and interlacing, after the loop count of the animation.
+use crate::error::{Result, StegError};
+use crate::image::{Format, Frame, Image};
+use crate::palette::Palette;
+use gif::{ColorOutput, DecodeOptions, Encoder, Repeat};
+use png::{BitDepth, ColorType};
+use std::borrow::Cow;
+
+/// Signature every GIF file starts with, followed by "7a" or "9a"
+pub(crate) const SIGNATURE: [u8; 4] = *b"GIF8";
+
+/// A decoded GIF file, down to the palette indices of every frame
+pub(crate) struct Gif {
+    width: u16,
+    height: u16,
+    /// RGB triples of the global color table, empty if there is none
+    global_palette: Vec<u8>,
+    repeat: Repeat,
+    /// Frames in file order, deinterlaced, with their original settings
+    frames: Vec<gif::Frame<'static>>,
+}
+
+impl Gif {
+    /// Sorted palette of a frame, its transparent index ranked first and kept
+    /// out of the pairs
+    fn palette(&self, frame: &gif::Frame) -> Palette {
+        let plte = frame.palette.as_deref().unwrap_or(&self.global_palette);
+        let trns = frame.transparent.map(|transparent| {
+            let mut alpha = vec![255; plte.len() / 3];
+            if let Some(alpha) = alpha.get_mut(transparent as usize) {
+                *alpha = 0;
+            }
+            alpha
+        });
+        Palette::new(plte, trns.as_deref())
+    }
+}
+
+/// Decodes an in-memory GIF file
+///
+/// # Arguments:
+/// - **bytes**: `&[u8]`
+///   - complete GIF file contents
+/// # Returns:
+/// - `Result<Image>` palette ranks of the first frame, then of the others as
+///   frames, for the pixels able to carry a bit
+///   - `StegError::ImageDecode` for a malformed file
+pub(crate) fn read(bytes: &[u8]) -> Result<Image> {
+    let invalid =
+        |reason: &dyn std::fmt::Display| StegError::ImageDecode(format!("GIF: {}", reason));
+    // An LZW code of n bits stands for at most 2^n indices, so a byte of data
+    // decodes to fewer than 4096 of them, 12-bit codes being the widest
+    let max_indices = bytes.len().saturating_mul(4096);
+    let mut indices = 0usize;
+    let mut options = DecodeOptions::new();
+    options.set_color_output(ColorOutput::Indexed);
+    let mut decoder = options.read_info(bytes).map_err(|e| invalid(&e))?;
+    let global_palette = decoder.global_palette().unwrap_or_default().to_vec();
+
+    let mut frames = Vec::new();
+    while let Some(frame) = decoder.next_frame_info().map_err(|e| invalid(&e))? {
+        let mut frame = frame.clone();
+        if frame.palette.is_none() && global_palette.is_empty() {
+            return Err(invalid(&"frame without a color table"));
+        }
+        indices = indices.saturating_add(decoder.buffer_size());
+        if indices > max_indices {
+            return Err(invalid(&"frames too large for the file"));
+        }
+        let mut buffer = vec![0; decoder.buffer_size()];
+        decoder
+            .read_into_buffer(&mut buffer)
+            .map_err(|e| invalid(&e))?;
+        frame.buffer = Cow::Owned(buffer);
+        frames.push(frame);
+    }
+    if frames.is_empty() {
+        return Err(invalid(&"no image"));
+    }
+    let gif = Gif {
+        width: decoder.width(),
+        height: decoder.height(),
+        global_palette,
+        repeat: decoder.repeat(),
+        frames,
+    };
+
+    let mut ranks = gif
+        .frames
+        .iter()
+        .map(|frame| gif.palette(frame).ranks(&frame.buffer));
+    let data = ranks.next().unwrap_or_default();
+    let frames = gif.frames[1..]
+        .iter()
+        .zip(ranks)
+        .map(|(frame, data)| Frame {
+            width: frame.width as u32,
+            height: frame.height as u32,
+            data,
+        })
+        .collect();
+    Ok(Image {
+        width: gif.width as u32,
+        height: gif.height as u32,
+        color_type: ColorType::Grayscale,
+        bit_depth: BitDepth::Eight,
+        palette: None,
+        trns: None,
+        interlaced: false,
+        chunks: Vec::new(),
+        attachments: Vec::new(),
+        data,
+        frames,
+        format: Format::Gif(gif),
+    })
+}
+
+/// Encodes an image read from a GIF file as a GIF file
+///
+/// # Arguments:
+/// - **image**: `&Image`
+///   - image returned by [`read`], with changed samples
+/// - **gif**: `&Gif`
+///   - the original file, decoded
+/// # Returns:
+/// - `Vec<u8>` complete GIF file contents
+pub(crate) fn write(image: &Image, gif: &Gif) -> Vec<u8> {
+    let mut output = Vec::new();
+    // Writing to a Vec cannot fail, and the frames and palettes come from a
+    // decoded GIF, so they are valid for encoding
+    let mut encoder = Encoder::new(&mut output, gif.width, gif.height, &gif.global_palette)
+        .expect("in-memory write");
+    encoder.set_repeat(gif.repeat).expect("in-memory write");
+    let samples = std::iter::once(&image.data).chain(image.frames.iter().map(|frame| &frame.data));
+    for (frame, samples) in gif.frames.iter().zip(samples) {
+        let palette = gif.palette(frame);
+        let mut indices = frame.buffer.to_vec();
+        // Only the parity of a rank changes, so pixels stay within their pair
+        let ranks: Vec<u8> = palette
+            .ranks(&indices)
+            .iter()
+            .zip(samples)
+            .map(|(rank, sample)| rank & !1 | sample & 1)
+            .collect();
+        palette.apply(&mut indices, &ranks);
+        if frame.interlaced {
+            indices = interlace(&indices, frame.width as usize);
+        }
+        let mut frame = frame.clone();
+        frame.buffer = Cow::Owned(indices);
+        encoder.write_frame(&frame).expect("in-memory write");
+    }
+    drop(encoder);
+    output
+}
+
+/// Reorders rows into the four passes of an interlaced GIF
+fn interlace(indices: &[u8], width: usize) -> Vec<u8> {
+    let rows: Vec<&[u8]> = indices.chunks(width.max(1)).collect();
+    [(0, 8), (4, 8), (2, 4), (1, 2)]
+        .iter()
+        .flat_map(|(start, step)| rows.iter().skip(*start).step_by(*step))
+        .flat_map(|row| row.iter().copied())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{decode_bytes, encode_bytes};
+    use gif::DisposalMethod;
+
+    // 16x16 image with a 64-color table, from the test data of CPython's
+    // imghdr module
+    const PYTHON: &[u8] = include_bytes!("../testdata/python.gif");
+
+    // Gray ramp palette of 16 colors
+    fn ramp(step: u8) -> Vec<u8> {
+        (0..16).flat_map(|i| [i * step; 3]).collect()
+    }
+
+    // Animated GIF whose second frame has its own palette and is interlaced
+    fn animated_gif() -> Vec<u8> {
+        let mut output = Vec::new();
+        let mut encoder = Encoder::new(&mut output, 20, 12, &ramp(16)).unwrap();
+        encoder.set_repeat(Repeat::Finite(3)).unwrap();
+        let first = gif::Frame {
+            delay: 10,
+            width: 20,
+            height: 12,
+            buffer: Cow::Owned((0..240).map(|i| (i % 16) as u8).collect()),
+            ..gif::Frame::default()
+        };
+        let second = gif::Frame {
+            delay: 25,
+            dispose: DisposalMethod::Background,
+            transparent: Some(0),
+            left: 4,
+            top: 2,
+            width: 16,
+            height: 9,
+            interlaced: true,
+            palette: Some(ramp(15)),
+            buffer: Cow::Owned((0..144).map(|i| (i / 9 % 16) as u8).collect()),
+            ..gif::Frame::default()
+        };
+        encoder.write_frame(&first).unwrap();
+        encoder.write_frame(&second).unwrap();
+        drop(encoder);
+        output
+    }
+
+    fn frames(bytes: &[u8]) -> (Repeat, Vec<gif::Frame<'static>>) {
+        match read(bytes).unwrap().format {
+            Format::Gif(gif) => (gif.repeat, gif.frames),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_interlace_orders_passes() {
+        let indices: Vec<u8> = (0..10).collect();
+        assert_eq!(interlace(&indices, 1), vec![0, 8, 4, 2, 6, 1, 3, 5, 7, 9]);
+    }
+
+    #[test]
+    fn test_read_rejects_frames_larger_than_the_file() {
+        // Image descriptor of the fixture, after its color table and extensions
+        let descriptor = 0xEC;
+        assert_eq!(PYTHON[descriptor], 0x2C);
+        // 65535x65535 pixels announced for the screen and the frame
+        let mut oversized = PYTHON.to_vec();
+        oversized[6..10].copy_from_slice(&[0xFF; 4]);
+        oversized[descriptor + 5..descriptor + 9].copy_from_slice(&[0xFF; 4]);
+        match read(&oversized) {
+            Err(StegError::ImageDecode(reason)) => assert!(reason.contains("too large")),
+            _ => panic!("oversized GIF accepted"),
+        }
+        for refused in [&PYTHON[..PYTHON.len() - 4], &PYTHON[..12]] {
+            assert!(matches!(read(refused), Err(StegError::ImageDecode(_))));
+        }
+    }
+
+    #[test]
+    fn test_encoding_keeps_the_palette() {
+        let encoded = encode_bytes(PYTHON, b"py").unwrap();
+        assert_eq!(decode_bytes(&encoded).unwrap(), b"py");
+        let (_, before) = frames(PYTHON);
+        let (_, after) = frames(&encoded);
+        assert_eq!(before[0].palette, after[0].palette);
+        assert_ne!(before[0].buffer, after[0].buffer);
+    }
+
+    #[test]
+    fn test_animation_settings_survive() {
+        let carrier = animated_gif();
+        let image = read(&carrier).unwrap();
+        assert_eq!(image.frames.len(), 1);
+        // Every pixel of a 16-color frame has a partner rank, but for those
+        // of the transparent index and the darkest gray it would pair with
+        assert_eq!(image.data.len(), 240);
+        assert_eq!(image.frames[0].data.len(), 144 - 2 * 9);
+
+        let message = vec![b'g'; 25];
+        let encoded = encode_bytes(&carrier, &message).unwrap();
+        assert_eq!(decode_bytes(&encoded).unwrap(), message);
+
+        let (repeat, before) = frames(&carrier);
+        let (repeat_after, after) = frames(&encoded);
+        assert_eq!(repeat_after, repeat);
+        assert_eq!(repeat, Repeat::Finite(3));
+        assert!(before[1].interlaced);
+        for (before, after) in before.iter().zip(&after) {
+            assert_eq!(
+                (before.delay, before.dispose, before.transparent),
+                (after.delay, after.dispose, after.transparent)
+            );
+            assert_eq!(
+                (before.left, before.top, before.interlaced, &before.palette),
+                (after.left, after.top, after.interlaced, &after.palette)
+            );
+            // Pixels only move to a neighbouring gray, never onto or off the
+            // transparent index
+            for (old, new) in before.buffer.iter().zip(after.buffer.iter()) {
+                assert!(old.abs_diff(*new) <= 1);
+                assert_eq!(
+                    Some(*old) == before.transparent,
+                    Some(*new) == before.transparent
+                );
+            }
+        }
+        assert_ne!(before[1].buffer, after[1].buffer);
+    }
+}
@@ -0,0 +1,337 @@
+//! Versioned header written in front of every hidden message
+//!
+//! ## Layout:
+//! ```text
+//! offset  size  field
+//!      0     4  magic bytes "StgR"
+//!      4     1  format version
+//!      5     1  flags, see FLAG_ENCRYPTED
+//!      6     4  message length in bytes, big-endian
+//!     10     4  CRC32 of bytes 4..10, the layout, the cipher parameters and the message, big-endian
+//!     14     1  bits per sample and channel mask, see Layout
+//!     15    52  cipher parameters, only present when FLAG_ENCRYPTED is set
+//! ```
+//!
+//! The header itself is always embedded one bit per sample, so it can be read
+//! before the layout of the message body is known.
+use crate::crypto::{CIPHER_PARAMS_LEN, CipherParams};
+use crate::error::{Result, StegError};
+use crc32fast::Hasher;
+
+/// Marks the start of a message hidden by stegarst
+pub const MAGIC: [u8; 4] = *b"StgR";
+/// Format version written by this release
+pub const FORMAT_VERSION: u8 = 1;
+/// Size of the fixed part of the serialized header in bytes
+pub const HEADER_LEN: usize = 15;
+/// Largest number of low-order bits of a sample that may carry the message,
+/// only allowed in 16-bit images
+pub const MAX_BITS_PER_SAMPLE: u8 = 8;
+/// The message is encrypted and the header carries its cipher parameters
+pub const FLAG_ENCRYPTED: u8 = 0b0000_0001;
+/// Flag bits understood by this release
+const KNOWN_FLAGS: u8 = FLAG_ENCRYPTED;
+
+/// How the message body is spread over the carrier samples
+///
+/// Serialized as a single byte: the low nibble holds the number of bits per
+/// sample, the high nibble the channel mask.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Layout {
+    /// Number of low-order bits of each sample carrying the message body
+    pub bits_per_sample: u8,
+    /// Bit `i` set when the `i`-th sample of each pixel carries the message,
+    /// zero meaning every sample
+    pub channels: u8,
+}
+
+impl Default for Layout {
+    fn default() -> Layout {
+        Layout {
+            bits_per_sample: 1,
+            channels: 0,
+        }
+    }
+}
+
+impl Layout {
+    /// Serializes the layout
+    pub fn to_byte(&self) -> u8 {
+        self.channels << 4 | self.bits_per_sample
+    }
+
+    /// Deserializes the layout
+    ///
+    /// # Arguments:
+    /// - **byte**: `u8`
+    ///   - layout byte read from the header
+    /// # Returns:
+    /// - `Result<Layout>`
+    ///   - `StegError::CorruptHeader` for an unsupported bit depth
+    pub fn from_byte(byte: u8) -> Result<Layout> {
+        let bits_per_sample = byte & 0x0F;
+        if !(1..=MAX_BITS_PER_SAMPLE).contains(&bits_per_sample) {
+            return Err(StegError::CorruptHeader(format!(
+                "unsupported layout {:#010b}",
+                byte
+            )));
+        }
+        Ok(Layout {
+            bits_per_sample,
+            channels: byte >> 4,
+        })
+    }
+}
+
+/// Header describing the message that follows it in the carrier
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    /// Format version the message was written with
+    pub version: u8,
+    /// Processing applied to the message before embedding
+    pub flags: u8,
+    /// Length of the message in bytes
+    pub length: u32,
+    /// CRC32 over the header fields and the message
+    pub checksum: u32,
+    /// How the message is spread over the carrier samples
+    pub layout: Layout,
+    /// Key derivation and cipher inputs of an encrypted message
+    pub cipher: Option<CipherParams>,
+}
+
+impl Header {
+    /// Builds the header for a message
+    ///
+    /// # Arguments:
+    /// - **message**: `&[u8]`
+    ///   - message the header describes, already encrypted if `cipher` is set
+    /// - **cipher**: `Option<CipherParams>`
+    ///   - parameters the message was encrypted with
+    /// - **layout**: `Layout`
+    ///   - how the message is spread over the carrier samples
+    /// # Returns:
+    /// - `Result<Header>`
+    ///   - `StegError::InsufficientCapacity` if the message length does not fit in 32 bits
+    pub fn new(message: &[u8], cipher: Option<CipherParams>, layout: Layout) -> Result<Header> {
+        let length = u32::try_from(message.len()).map_err(|_| StegError::InsufficientCapacity {
+            required: message.len(),
+            available: u32::MAX as usize,
+        })?;
+        let mut header = Header {
+            version: FORMAT_VERSION,
+            flags: if cipher.is_some() { FLAG_ENCRYPTED } else { 0 },
+            length,
+            checksum: 0,
+            layout,
+            cipher,
+        };
+        header.checksum = header.compute_checksum(message);
+        Ok(header)
+    }
+
+    /// Size of the serialized header in bytes
+    pub fn encoded_len(&self) -> usize {
+        HEADER_LEN + self.cipher.map_or(0, |_| CIPHER_PARAMS_LEN)
+    }
+
+    /// Serializes the header
+    ///
+    /// # Returns:
+    /// - `Vec<u8>` header bytes
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.encoded_len());
+        bytes.extend_from_slice(&MAGIC);
+        bytes.push(self.version);
+        bytes.push(self.flags);
+        bytes.extend_from_slice(&self.length.to_be_bytes());
+        bytes.extend_from_slice(&self.checksum.to_be_bytes());
+        bytes.push(self.layout.to_byte());
+        if let Some(cipher) = &self.cipher {
+            bytes.extend_from_slice(&cipher.to_bytes());
+        }
+        bytes
+    }
+
+    /// Parses a header from a byte slice, see [`Header::read`]
+    pub fn parse(bytes: &[u8]) -> Result<Option<Header>> {
+        Self::read(&mut bytes.iter().copied())
+    }
+
+    /// Reads a header, telling stegarst messages apart from arbitrary data
+    ///
+    /// # Arguments:
+    /// - **bytes**: `&mut impl Iterator<Item = u8>`
+    ///   - bytes read from the carrier, left positioned after the header
+    /// # Returns:
+    /// - `Result<Option<Header>>`
+    ///   - `None` if the bytes do not start with the magic
+    ///   - `StegError::CorruptHeader` for an unknown version, unknown flags,
+    ///     an unknown layout or a truncated header
+    pub fn read(bytes: &mut impl Iterator<Item = u8>) -> Result<Option<Header>> {
+        let fixed: Vec<u8> = bytes.take(HEADER_LEN).collect();
+        if fixed.len() < HEADER_LEN || fixed[..4] != MAGIC {
+            return Ok(None);
+        }
+        let mut header = Header {
+            version: fixed[4],
+            flags: fixed[5],
+            length: u32::from_be_bytes([fixed[6], fixed[7], fixed[8], fixed[9]]),
+            checksum: u32::from_be_bytes([fixed[10], fixed[11], fixed[12], fixed[13]]),
+            layout: Layout::default(),
+            cipher: None,
+        };
+        if header.version == 0 || header.version > FORMAT_VERSION {
+            return Err(StegError::CorruptHeader(format!(
+                "unsupported format version {}",
+                header.version
+            )));
+        }
+        if header.flags & !KNOWN_FLAGS != 0 {
+            return Err(StegError::CorruptHeader(format!(
+                "unsupported flags {:#010b}",
+                header.flags
+            )));
+        }
+        header.layout = Layout::from_byte(fixed[14])?;
+        if header.flags & FLAG_ENCRYPTED != 0 {
+            let mut cipher = [0; CIPHER_PARAMS_LEN];
+            for byte in cipher.iter_mut() {
+                *byte = bytes.next().ok_or_else(|| {
+                    StegError::CorruptHeader("truncated cipher parameters".to_string())
+                })?;
+            }
+            header.cipher = Some(CipherParams::from_bytes(&cipher));
+        }
+        Ok(Some(header))
+    }
+
+    /// Checks the message against the stored checksum
+    ///
+    /// # Arguments:
+    /// - **message**: `&[u8]`
+    ///   - message read from the carrier
+    /// # Returns:
+    /// - `Result<()>`
+    ///   - `StegError::CorruptHeader` if the checksum does not match
+    pub fn verify(&self, message: &[u8]) -> Result<()> {
+        let actual = self.compute_checksum(message);
+        if actual != self.checksum {
+            return Err(StegError::CorruptHeader(format!(
+                "checksum mismatch: expected {:#010x}, found {:#010x}",
+                self.checksum, actual
+            )));
+        }
+        Ok(())
+    }
+
+    fn compute_checksum(&self, message: &[u8]) -> u32 {
+        let mut hasher = Hasher::new();
+        hasher.update(&[self.version, self.flags]);
+        hasher.update(&self.length.to_be_bytes());
+        hasher.update(&[self.layout.to_byte()]);
+        if let Some(cipher) = &self.cipher {
+            hasher.update(&cipher.to_bytes());
+        }
+        hasher.update(message);
+        hasher.finalize()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header_roundtrip() {
+        let header = Header::new(b"hello", None, Layout::default()).unwrap();
+        let bytes = header.to_bytes();
+        assert_eq!(&bytes[..4], b"StgR");
+        assert_eq!(Header::parse(&bytes).unwrap(), Some(header));
+        assert_eq!(header.length, 5);
+        assert!(header.verify(b"hello").is_ok());
+    }
+
+    #[test]
+    fn test_parse_without_magic() {
+        assert_eq!(Header::parse(&[0; HEADER_LEN]).unwrap(), None);
+        assert_eq!(Header::parse(b"StgR").unwrap(), None);
+    }
+
+    #[test]
+    fn test_parse_rejects_unknown_version_and_flags() {
+        let mut bytes = Header::new(b"", None, Layout::default())
+            .unwrap()
+            .to_bytes();
+        bytes[4] = FORMAT_VERSION + 1;
+        assert!(matches!(
+            Header::parse(&bytes),
+            Err(StegError::CorruptHeader(_))
+        ));
+
+        let mut bytes = Header::new(b"", None, Layout::default())
+            .unwrap()
+            .to_bytes();
+        bytes[5] = 0x80;
+        assert!(matches!(
+            Header::parse(&bytes),
+            Err(StegError::CorruptHeader(_))
+        ));
+    }
+
+    #[test]
+    fn test_verify_detects_corruption() {
+        let header = Header::new(b"hello", None, Layout::default()).unwrap();
+        assert!(matches!(
+            header.verify(b"hellp"),
+            Err(StegError::CorruptHeader(_))
+        ));
+
+        let mut bytes = header.to_bytes();
+        bytes[9] ^= 1;
+        let tampered = Header::parse(&bytes).unwrap().unwrap();
+        assert!(tampered.verify(b"hello").is_err());
+    }
+
+    #[test]
+    fn test_encrypted_header_roundtrip() {
+        let cipher = CipherParams::generate();
+        let header = Header::new(b"sealed", Some(cipher), Layout::default()).unwrap();
+        assert_eq!(header.flags, FLAG_ENCRYPTED);
+        assert_eq!(header.encoded_len(), HEADER_LEN + CIPHER_PARAMS_LEN);
+
+        let mut bytes = header.to_bytes();
+        bytes.extend_from_slice(b"sealed");
+        let mut stream = bytes.into_iter();
+        assert_eq!(Header::read(&mut stream).unwrap(), Some(header));
+        assert_eq!(stream.collect::<Vec<u8>>(), b"sealed");
+
+        let truncated = &header.to_bytes()[..HEADER_LEN + 10];
+        assert!(matches!(
+            Header::parse(truncated),
+            Err(StegError::CorruptHeader(_))
+        ));
+    }
+
+    #[test]
+    fn test_layout_roundtrip_and_validation() {
+        let layout = Layout {
+            bits_per_sample: 3,
+            channels: 0b0100,
+        };
+        let header = Header::new(b"hello", None, layout).unwrap();
+        let bytes = header.to_bytes();
+        assert_eq!(bytes.len(), HEADER_LEN);
+        assert_eq!(bytes[14], 0b0100_0011);
+        assert_eq!(Header::parse(&bytes).unwrap().unwrap().layout, layout);
+
+        for invalid in [0, 9, 0x10] {
+            let mut bytes = header.to_bytes();
+            bytes[14] = invalid;
+            assert!(matches!(
+                Header::parse(&bytes),
+                Err(StegError::CorruptHeader(_))
+            ));
+        }
+    }
+}
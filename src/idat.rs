@@ -0,0 +1,461 @@
+//! Filtering and compressing frames into PNG image data
+//!
+//! The `png` crate's encoder cannot write interlaced images, so the IDAT
+//! stream is produced here: rows are split into Adam7 passes when the carrier
+//! is interlaced, filtered one by one and zlib-compressed. The carrier's own
+//! stream is inspected too, so that its filters, compression level and IDAT
+//! chunk sizes can be reproduced. APNG frames stored in fdAT chunks are
+//! encoded the same way, at their own size.
+use crate::image::{Frame, Image, pack};
+use crate::options::Filter;
+use flate2::{Compression, read::ZlibDecoder, write::ZlibEncoder};
+use png::ColorType;
+use std::io::{Read, Write};
+
+/// Origin and spacing `(x, y, dx, dy)` of the seven Adam7 passes
+const ADAM7: [(usize, usize, usize, usize); 7] = [
+    (0, 0, 8, 8),
+    (4, 0, 8, 8),
+    (0, 4, 4, 8),
+    (2, 0, 4, 4),
+    (0, 2, 2, 4),
+    (1, 0, 2, 2),
+    (0, 1, 1, 2),
+];
+
+/// zlib level used when the carrier's cannot be estimated
+pub(crate) const DEFAULT_LEVEL: u8 = 6;
+
+/// Bytes of filtered scanlines compressed at every candidate level when
+/// estimating the carrier's
+const SAMPLE_LEN: usize = 64 * 1024;
+
+/// Pixels of one frame, in the format of the image it belongs to
+pub(crate) struct Raster<'a> {
+    pub image: &'a Image,
+    pub width: usize,
+    pub height: usize,
+    /// Unfiltered samples, see [`Image::data`]
+    pub data: &'a [u8],
+}
+
+impl<'a> Raster<'a> {
+    /// Frame stored in the IDAT chunks
+    pub fn main(image: &'a Image) -> Raster<'a> {
+        Raster {
+            image,
+            width: image.width as usize,
+            height: image.height as usize,
+            data: &image.data,
+        }
+    }
+
+    /// Animation frame stored in fdAT chunks
+    pub fn frame(image: &'a Image, frame: &'a Frame) -> Raster<'a> {
+        Raster {
+            image,
+            width: frame.width as usize,
+            height: frame.height as usize,
+            data: &frame.data,
+        }
+    }
+}
+
+/// How the image data of a carrier was written
+pub(crate) struct Settings {
+    /// Filter type of every scanline, in stream order
+    pub filters: Vec<u8>,
+    /// zlib level, among those the stream header allows, whose compression
+    /// ratio comes closest to the original stream's
+    pub level: u8,
+    /// Size of all but the last chunk, if the stream was split
+    pub chunk_size: Option<usize>,
+}
+
+impl Settings {
+    /// Inspects the original image data of a frame
+    ///
+    /// # Arguments:
+    /// - **raster**: `&Raster`
+    ///   - decoded frame
+    /// - **pieces**: `&[&[u8]]`
+    ///   - its compressed stream, as split over IDAT or fdAT chunks
+    /// # Returns:
+    /// - `Option<Settings>` settings of the original stream, `None` if it
+    ///   cannot be inflated, inflates past the filtered scanlines or holds
+    ///   unknown filter types
+    pub fn of(raster: &Raster, pieces: &[&[u8]]) -> Option<Settings> {
+        let passes = pass_sizes(raster);
+        let expected: usize = passes
+            .iter()
+            .map(|(width, height)| (row_len(raster.image, *width) + 1) * height)
+            .sum();
+        let compressed = pieces.concat();
+        let mut filtered = Vec::new();
+        ZlibDecoder::new(compressed.as_slice())
+            .take(expected as u64 + 1)
+            .read_to_end(&mut filtered)
+            .ok()?;
+        if filtered.len() > expected {
+            return None;
+        }
+
+        let mut filters = Vec::new();
+        let mut offset = 0;
+        for (width, height) in passes {
+            let row_len = row_len(raster.image, width);
+            for _ in 0..height {
+                filters.push(*filtered.get(offset).filter(|kind| **kind < 5)?);
+                offset += row_len + 1;
+            }
+        }
+
+        // FLEVEL, the top bits of the second header byte, narrows the levels
+        // down; only a prefix of the data is compressed to choose among them
+        let candidates = match compressed.get(1)? >> 6 {
+            0 => 0..=1,
+            1 => 2..=5,
+            2 => 6..=6,
+            _ => 7..=9,
+        };
+        let sample = &filtered[..filtered.len().min(SAMPLE_LEN)];
+        let expected = compressed.len() * sample.len() / filtered.len().max(1);
+        let level = candidates
+            .min_by_key(|level| compress(sample, *level).len().abs_diff(expected))
+            .unwrap_or(DEFAULT_LEVEL);
+        let chunk_size = (pieces.len() > 1).then(|| pieces[0].len());
+        Some(Settings {
+            filters,
+            level,
+            chunk_size,
+        })
+    }
+}
+
+/// Serializes the pixels of a frame into a compressed stream
+///
+/// # Arguments:
+/// - **raster**: `&Raster`
+///   - frame to serialize, interlaced if the carrier was
+/// - **filter**: `Filter`
+///   - filter type of every scanline, `Filter::Match` taking them from `original`
+/// - **original**: `&[u8]`
+///   - filter types of the carrier's scanlines, rows past its end are
+///     filtered adaptively
+/// - **level**: `u8`
+///   - zlib compression level, 0 to 9
+/// # Returns:
+/// - `Vec<u8>` zlib stream of filtered scanlines
+pub(crate) fn encode(raster: &Raster, filter: Filter, original: &[u8], level: u8) -> Vec<u8> {
+    let image = raster.image;
+    let bits_per_pixel = image.color_type.samples() * image.bit_depth as usize;
+    // Filters compare each byte with the matching byte of the previous pixel
+    let bpp = bits_per_pixel.div_ceil(8);
+    // Byte-oriented filters rarely help with palette indices or packed samples
+    let adaptive = image.color_type != ColorType::Indexed && bits_per_pixel >= 8;
+
+    let mut filtered = Vec::new();
+    let mut index = 0;
+    for pass in scanlines(raster) {
+        let mut previous = vec![0; pass.first().map_or(0, Vec::len)];
+        for row in pass {
+            let kind = match filter {
+                Filter::Match if index < original.len() => original[index],
+                Filter::Match | Filter::Adaptive if adaptive => (0..5)
+                    .min_by_key(|kind| {
+                        let mut candidate = Vec::with_capacity(row.len());
+                        filter_row(*kind, &row, &previous, bpp, &mut candidate);
+                        candidate
+                            .iter()
+                            .map(|byte| (*byte as i8).unsigned_abs() as u64)
+                            .sum::<u64>()
+                    })
+                    .unwrap_or(0),
+                Filter::Match | Filter::Adaptive | Filter::None => 0,
+                Filter::Sub => 1,
+                Filter::Up => 2,
+                Filter::Average => 3,
+                Filter::Paeth => 4,
+            };
+            filtered.push(kind);
+            filter_row(kind, &row, &previous, bpp, &mut filtered);
+            previous = row;
+            index += 1;
+        }
+    }
+    compress(&filtered, level)
+}
+
+/// Splits a zlib stream into chunk-sized pieces
+///
+/// # Arguments:
+/// - **stream**: `Vec<u8>`
+///   - compressed image data
+/// - **chunk_size**: `Option<usize>`
+///   - largest piece, `None` to keep the stream whole
+/// # Returns:
+/// - `Vec<Vec<u8>>` chunk data in file order
+pub(crate) fn split(stream: Vec<u8>, chunk_size: Option<usize>) -> Vec<Vec<u8>> {
+    match chunk_size.filter(|size| *size > 0 && *size < stream.len()) {
+        Some(size) => stream.chunks(size).map(<[u8]>::to_vec).collect(),
+        None => vec![stream],
+    }
+}
+
+// Compresses filtered scanlines at a zlib level
+fn compress(filtered: &[u8], level: u8) -> Vec<u8> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::new(level as u32));
+    // Writing to a Vec cannot fail
+    encoder.write_all(filtered).expect("in-memory write");
+    encoder.finish().expect("in-memory write")
+}
+
+/// Width and height of every non-empty pass, a single one when progressive
+fn pass_sizes(raster: &Raster) -> Vec<(usize, usize)> {
+    passes(raster.image)
+        .iter()
+        .map(|(x0, y0, dx, dy)| {
+            (
+                raster.width.saturating_sub(*x0).div_ceil(*dx),
+                raster.height.saturating_sub(*y0).div_ceil(*dy),
+            )
+        })
+        .filter(|(width, height)| *width > 0 && *height > 0)
+        .collect()
+}
+
+fn passes(image: &Image) -> &'static [(usize, usize, usize, usize)] {
+    if image.interlaced {
+        &ADAM7
+    } else {
+        &[(0, 0, 1, 1)]
+    }
+}
+
+/// Bytes in a scanline of `width` pixels, without the filter type
+fn row_len(image: &Image, width: usize) -> usize {
+    (width * image.color_type.samples() * image.bit_depth as usize).div_ceil(8)
+}
+
+/// Splits the pixels of a frame into unfiltered scanlines
+///
+/// # Returns:
+/// - `Vec<Vec<Vec<u8>>>` one list of rows for a progressive image, or one per
+///   non-empty Adam7 pass for an interlaced one, sub-byte samples packed
+fn scanlines(raster: &Raster) -> Vec<Vec<Vec<u8>>> {
+    let image = raster.image;
+    let (width, height) = (raster.width, raster.height);
+    let samples_per_pixel = image.color_type.samples();
+    let depth = image.bit_depth as u8;
+    // Expanded sub-byte samples take a byte each
+    let pixel_len = samples_per_pixel * (depth as usize).div_ceil(8);
+
+    passes(image)
+        .iter()
+        .map(|(x0, y0, dx, dy)| {
+            (*y0..height)
+                .step_by(*dy)
+                .map(|y| {
+                    let row: Vec<u8> = (*x0..width)
+                        .step_by(*dx)
+                        .flat_map(|x| {
+                            let start = (y * width + x) * pixel_len;
+                            raster.data[start..start + pixel_len].iter().copied()
+                        })
+                        .collect();
+                    match depth {
+                        1 | 2 | 4 if !row.is_empty() => pack(&row, row.len(), depth),
+                        _ => row,
+                    }
+                })
+                .filter(|row| !row.is_empty())
+                .collect::<Vec<_>>()
+        })
+        .filter(|pass| !pass.is_empty())
+        .collect()
+}
+
+/// Appends a scanline filtered with one of the five PNG filter types
+///
+/// # Arguments:
+/// - **kind**: `u8`
+///   - filter type: none, sub, up, average or Paeth
+/// - **row**: `&[u8]`
+///   - unfiltered scanline
+/// - **previous**: `&[u8]`
+///   - unfiltered previous scanline of the same pass, zeros for the first
+/// - **bpp**: `usize`
+///   - bytes per complete pixel, at least 1
+/// - **output**: `&mut Vec<u8>`
+///   - buffer receiving the filtered bytes
+fn filter_row(kind: u8, row: &[u8], previous: &[u8], bpp: usize, output: &mut Vec<u8>) {
+    for (i, byte) in row.iter().enumerate() {
+        let left = if i >= bpp { row[i - bpp] } else { 0 };
+        let up = previous[i];
+        let up_left = if i >= bpp { previous[i - bpp] } else { 0 };
+        let predicted = match kind {
+            1 => left,
+            2 => up,
+            3 => ((left as u16 + up as u16) / 2) as u8,
+            4 => paeth(left, up, up_left),
+            _ => 0,
+        };
+        output.push(byte.wrapping_sub(predicted));
+    }
+}
+
+/// Paeth predictor from the PNG specification
+fn paeth(left: u8, up: u8, up_left: u8) -> u8 {
+    let estimate = left as i16 + up as i16 - up_left as i16;
+    let distance = |value: u8| (estimate - value as i16).abs();
+    if distance(left) <= distance(up) && distance(left) <= distance(up_left) {
+        left
+    } else if distance(up) <= distance(up_left) {
+        up
+    } else {
+        up_left
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::{self, Chunk, IDAT, IEND, IHDR};
+    use crate::options::{Compression, EncodeOptions};
+    use png::BitDepth;
+
+    // Re-serializes an image with a new IHDR interlace flag and decodes it again
+    fn reinterlace(image: &mut Image, interlaced: bool) -> Image {
+        image.interlaced = interlaced;
+        let mut ihdr = image.chunks[0].data.clone();
+        ihdr[12] = interlaced as u8;
+        let png = chunk::serialize(&[
+            Chunk::new(IHDR, ihdr),
+            Chunk::new(IDAT, encode(&Raster::main(image), Filter::Adaptive, &[], 6)),
+            Chunk::new(IEND, Vec::new()),
+        ]);
+        Image::read(&png).unwrap()
+    }
+
+    #[test]
+    fn test_interlaced_encoding_decodes_to_the_same_pixels() {
+        for (color_type, bit_depth, width, height) in [
+            (ColorType::Rgb, BitDepth::Eight, 13, 9),
+            (ColorType::Rgba, BitDepth::Sixteen, 5, 3),
+            (ColorType::Grayscale, BitDepth::Two, 11, 7),
+            (ColorType::Grayscale, BitDepth::One, 1, 1),
+        ] {
+            let row_len = (width * color_type.samples() * bit_depth as usize).div_ceil(8);
+            let data: Vec<u8> = (0..row_len * height)
+                .map(|i| (i * 151 % 256) as u8)
+                .collect();
+            let mut png = Vec::new();
+            {
+                let mut encoder = png::Encoder::new(&mut png, width as u32, height as u32);
+                encoder.set_color(color_type);
+                encoder.set_depth(bit_depth);
+                let mut writer = encoder.write_header().unwrap();
+                writer.write_image_data(&data).unwrap();
+            }
+            let mut image = Image::read(&png).unwrap();
+
+            let interlaced = reinterlace(&mut image, true);
+            assert!(interlaced.interlaced);
+            assert_eq!(interlaced.data, image.data);
+            let progressive = reinterlace(&mut image, false);
+            assert_eq!(progressive.data, image.data);
+        }
+    }
+
+    fn main_settings(image: &Image) -> Settings {
+        Settings::of(&Raster::main(image), &image.streams()[0]).unwrap()
+    }
+
+    #[test]
+    fn test_write_reproduces_filters_level_and_chunk_sizes() {
+        let mut png = Vec::new();
+        {
+            let mut encoder = png::Encoder::new(&mut png, 24, 16);
+            encoder.set_color(ColorType::Rgb);
+            let mut writer = encoder.write_header().unwrap();
+            let data: Vec<u8> = (0..24 * 16 * 3).map(|i| (i * i / 7 % 256) as u8).collect();
+            writer.write_image_data(&data).unwrap();
+        }
+        let mut image = Image::read(&png).unwrap();
+        let stream = encode(&Raster::main(&image), Filter::Sub, &[], 9);
+        let idat = image
+            .chunks
+            .iter()
+            .position(|chunk| chunk.kind == IDAT)
+            .unwrap();
+        image.chunks.splice(
+            idat..idat + 1,
+            split(stream, Some(64))
+                .into_iter()
+                .map(|piece| Chunk::new(IDAT, piece)),
+        );
+        let carrier = chunk::serialize(&image.chunks);
+
+        let image = Image::read(&carrier).unwrap();
+        let settings = main_settings(&image);
+        assert_eq!(settings.filters, vec![1; 16]);
+        assert_eq!(settings.chunk_size, Some(64));
+
+        let written = image.write(&EncodeOptions::default());
+        assert_eq!(written.len(), carrier.len());
+        let rewritten = main_settings(&Image::read(&written).unwrap());
+        assert_eq!(rewritten.filters, settings.filters);
+        assert_eq!(rewritten.chunk_size, Some(64));
+
+        let stored = image.write(
+            &EncodeOptions::default()
+                .with_compression(Compression::Level(0))
+                .with_filter(Filter::None),
+        );
+        let stored = main_settings(&Image::read(&stored).unwrap());
+        assert_eq!(stored.filters, vec![0; 16]);
+        assert_eq!(stored.level, 0);
+    }
+
+    #[test]
+    fn test_inflating_stops_past_the_scanlines() {
+        let mut png = Vec::new();
+        {
+            let mut encoder = png::Encoder::new(&mut png, 24, 16);
+            encoder.set_color(ColorType::Rgb);
+            let mut writer = encoder.write_header().unwrap();
+            writer.write_image_data(&[7; 24 * 16 * 3]).unwrap();
+        }
+        let image = Image::read(&png).unwrap();
+        let raster = Raster::main(&image);
+        let stream = encode(&raster, Filter::None, &[], 6);
+        assert!(Settings::of(&raster, &[&stream]).is_some());
+
+        let mut filtered = vec![0; 16 * (24 * 3 + 1)];
+        filtered.extend(vec![0; 16 << 20]);
+        assert!(Settings::of(&raster, &[&compress(&filtered, 9)]).is_none());
+    }
+
+    #[test]
+    fn test_filters_and_paeth() {
+        let row = [10, 20, 30, 40];
+        let previous = [5, 5, 50, 50];
+        let mut output = Vec::new();
+        for kind in 0..5 {
+            filter_row(kind, &row, &previous, 2, &mut output);
+        }
+        assert_eq!(
+            output,
+            vec![
+                10, 20, 30, 40, // none
+                10, 20, 20, 20, // sub
+                5, 15, 236, 246, // up
+                8, 18, 0, 5, // average
+                5, 15, 236, 246, // Paeth
+            ]
+        );
+        assert_eq!(paeth(10, 20, 15), 15);
+        assert_eq!(paeth(10, 20, 5), 20);
+        assert_eq!(paeth(10, 20, 30), 10);
+    }
+}
@@ -0,0 +1,653 @@
+//! Image reading and writing shared by the encoder and decoder
+//!
+//! The file format is picked from the signature of the carrier: PNG files are
+//! handled here, other formats, WAV and FLAC audio included, in their own
+//! modules.
+use crate::attachment;
+use crate::bmp::{self, Bitmap};
+use crate::chunk::{self, Chunk, FCTL, FDAT, IDAT, IEND, TEXT_CHUNKS, TIME};
+use crate::error::Result;
+use crate::flac::{self, Flac};
+use crate::gif::{self, Gif};
+use crate::idat::{self, DEFAULT_LEVEL, Raster, Settings};
+use crate::jpeg::{self, Jpeg};
+use crate::netpbm::{self, Netpbm};
+use crate::options::{Carrier, Compression, EncodeOptions, Metadata};
+use crate::qoi;
+use crate::tiff::{self, Tiff};
+use crate::wav::{self, Wave};
+use crate::webp::{self, Webp};
+use ::qoi::ColorSpace;
+use png::{BitDepth, ColorType, Decoder, OutputInfo};
+use std::io::Cursor;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Pixel buffer of a decoded carrier, together with what is needed to write it back
+///
+/// Every file format is read into this buffer, so the encoder and decoder
+/// only ever deal with samples: a format describes its samples with the PNG
+/// color type and bit depth closest to them, and keeps whatever else it needs
+/// for writing in its [`Format`].
+///
+/// Packed 1-, 2- and 4-bit samples are expanded to one byte each on reading
+/// and packed again on writing, so every byte of `data` below 16 bits per
+/// sample is exactly one sample.
+///
+/// Writing a PNG only replaces the image data: every other chunk of the
+/// carrier, from color profiles to private chunks, is copied over as it was.
+pub(crate) struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub color_type: ColorType,
+    pub bit_depth: BitDepth,
+    /// RGB triples of the PLTE chunk, if any
+    pub palette: Option<Vec<u8>>,
+    /// Contents of the tRNS chunk, if any
+    pub trns: Option<Vec<u8>>,
+    /// Whether the frames are stored in Adam7 passes
+    pub interlaced: bool,
+    /// Chunks of the carrier file, in file order
+    pub chunks: Vec<Chunk>,
+    /// Chunks carrying a payload, added before IEND on writing
+    pub attachments: Vec<Chunk>,
+    /// Unfiltered samples of the IDAT frame, big-endian pairs at 16 bits
+    pub data: Vec<u8>,
+    /// APNG frames stored in fdAT chunks, further TIFF pages or GIF frames, in
+    /// file order
+    pub frames: Vec<Frame>,
+    /// Format the image is written back as
+    pub format: Format,
+}
+
+/// File format of a carrier, with whatever else is needed to write it back
+pub(crate) enum Format {
+    Png,
+    Bmp(Bitmap),
+    Jpeg(Jpeg),
+    Webp(Webp),
+    Netpbm(Netpbm),
+    Qoi(ColorSpace),
+    Tiff(Tiff),
+    Gif(Gif),
+    Wav(Wave),
+    Flac(Flac),
+}
+
+/// An APNG frame stored in fdAT chunks, a further TIFF page or a GIF frame
+///
+/// Frames are kept as raw sub-images: their position, timing, disposal and
+/// blending stay in the fcTL chunks, which are copied over.
+pub(crate) struct Frame {
+    pub width: u32,
+    pub height: u32,
+    /// Unfiltered samples, see [`Image::data`]
+    pub data: Vec<u8>,
+}
+
+impl Image {
+    /// Decodes an in-memory image file, PNG unless the signature says otherwise
+    ///
+    /// # Arguments:
+    /// - **bytes**: `&[u8]`
+    ///   - complete image file contents
+    /// # Returns:
+    /// - `Result<Image>` decoded frames
+    pub fn read(bytes: &[u8]) -> Result<Image> {
+        if bytes.starts_with(&bmp::SIGNATURE) {
+            return bmp::read(bytes);
+        }
+        if bytes.starts_with(&jpeg::SIGNATURE) {
+            return jpeg::read(bytes);
+        }
+        if webp::is_webp(bytes) {
+            return webp::read(bytes);
+        }
+        if wav::is_wav(bytes) {
+            return wav::read(bytes);
+        }
+        if bytes.starts_with(&flac::SIGNATURE) {
+            return flac::read(bytes);
+        }
+        if netpbm::is_netpbm(bytes) {
+            return netpbm::read(bytes);
+        }
+        if bytes.starts_with(&qoi::SIGNATURE) {
+            return qoi::read(bytes);
+        }
+        if tiff::SIGNATURES
+            .iter()
+            .any(|signature| bytes.starts_with(signature))
+        {
+            return tiff::read(bytes);
+        }
+        if bytes.starts_with(&gif::SIGNATURE) {
+            return gif::read(bytes);
+        }
+        Image::read_png(bytes)
+    }
+
+    /// Decodes every frame of an in-memory PNG file
+    fn read_png(bytes: &[u8]) -> Result<Image> {
+        let chunks = chunk::parse(bytes)?;
+        let decoder = Decoder::new(Cursor::new(bytes));
+        let mut reader = decoder.read_info()?;
+
+        let (color_type, bit_depth) = reader.output_color_type();
+        let capacity = reader
+            .output_buffer_size()
+            .ok_or(png::DecodingError::LimitsExceeded)?;
+        let mut buffer = vec![0; capacity];
+        let output = reader.next_frame(&mut buffer)?;
+        let data = frame_samples(&buffer, &output, output.line_size);
+
+        // Interlaced subframes are laid out with the stride of the full image
+        let stride = match reader.info().interlaced {
+            true => reader.output_line_size(reader.info().width),
+            false => None,
+        };
+        let mut frames = Vec::new();
+        for _ in 1..streams(&chunks).len() {
+            let output = reader.next_frame(&mut buffer)?;
+            frames.push(Frame {
+                width: output.width,
+                height: output.height,
+                data: frame_samples(&buffer, &output, stride.unwrap_or(output.line_size)),
+            });
+        }
+
+        let info = reader.info();
+        Ok(Image {
+            width: info.width,
+            height: info.height,
+            color_type,
+            bit_depth,
+            palette: info.palette.as_ref().map(|palette| palette.to_vec()),
+            trns: info.trns.as_ref().map(|trns| trns.to_vec()),
+            interlaced: info.interlaced,
+            chunks,
+            attachments: Vec::new(),
+            data,
+            frames,
+            format: Format::Png,
+        })
+    }
+
+    /// Samples of every frame, one frame after the other
+    pub fn samples(&self) -> Vec<u8> {
+        let mut samples = self.data.clone();
+        for frame in &self.frames {
+            samples.extend_from_slice(&frame.data);
+        }
+        samples
+    }
+
+    /// Replaces the samples of every frame, see [`Image::samples`]
+    pub fn set_samples(&mut self, samples: &[u8]) {
+        let (main, mut rest) = samples.split_at(self.data.len());
+        self.data.copy_from_slice(main);
+        for frame in &mut self.frames {
+            let (samples, next) = rest.split_at(frame.data.len());
+            frame.data.copy_from_slice(samples);
+            rest = next;
+        }
+    }
+
+    /// Compressed image data of every frame, as split over chunks
+    ///
+    /// # Returns:
+    /// - `Vec<Vec<&[u8]>>` the IDAT chunk data, then the fdAT chunk data of
+    ///   each frame without its sequence number
+    pub fn streams(&self) -> Vec<Vec<&[u8]>> {
+        streams(&self.chunks)
+    }
+
+    /// Encodes the image in the format it was read from
+    ///
+    /// # Arguments:
+    /// - **options**: `&EncodeOptions`
+    ///   - filter, compression, metadata and carrier settings
+    /// # Returns:
+    /// - `Vec<u8>` complete image file contents
+    pub fn write(&self, options: &EncodeOptions) -> Vec<u8> {
+        match &self.format {
+            Format::Png => self.write_png(options),
+            Format::Bmp(bitmap) => bmp::write(self, bitmap),
+            Format::Jpeg(jpeg) => jpeg::write(self, jpeg),
+            Format::Webp(webp) => webp::write(self, webp),
+            Format::Netpbm(netpbm) => netpbm::write(self, netpbm),
+            Format::Qoi(colorspace) => qoi::write(self, *colorspace),
+            Format::Tiff(tiff) => tiff::write(self, tiff),
+            Format::Gif(gif) => gif::write(self, gif),
+            Format::Wav(wave) => wav::write(self, wave),
+            Format::Flac(flac) => flac::write(self, flac),
+        }
+    }
+
+    /// Encodes the frames as a PNG file in memory
+    ///
+    /// The image data of the carrier is replaced and its other chunks are
+    /// kept in place, interlacing and animation included. Unless the options
+    /// say otherwise, the new data reuses the carrier's filters, compression
+    /// level and chunk sizes, frame by frame. With a chunk carrier the image
+    /// data is kept as it was and the attachments are added instead.
+    ///
+    /// # Arguments:
+    /// - **options**: `&EncodeOptions`
+    ///   - filter, compression, metadata and carrier settings
+    /// # Returns:
+    /// - `Vec<u8>` complete PNG file contents
+    fn write_png(&self, options: &EncodeOptions) -> Vec<u8> {
+        let rewrite = options.carrier == Carrier::Pixels;
+        let rasters = std::iter::once(Raster::main(self))
+            .chain(self.frames.iter().map(|frame| Raster::frame(self, frame)));
+        let mut streams: Vec<Option<Vec<Vec<u8>>>> = match rewrite {
+            true => rasters
+                .zip(self.streams())
+                .map(|(raster, pieces)| Some(reencode(&raster, &pieces, options)))
+                .collect(),
+            false => Vec::new(),
+        };
+
+        let stale = attachment::positions(&self.chunks);
+        let metadata = options.metadata;
+        let mut chunks = Vec::with_capacity(self.chunks.len());
+        // fcTL and fdAT chunks share a sequence, renumbered as fdAT chunks
+        // come and go
+        let mut sequence = 0u32;
+        let mut frame = 0;
+        for (index, chunk) in self.chunks.iter().enumerate() {
+            match chunk.kind {
+                // The new stream takes the place of the first IDAT, the others are dropped
+                IDAT if rewrite => {
+                    if let Some(pieces) = streams[0].take() {
+                        chunks.extend(pieces.into_iter().map(|piece| Chunk::new(IDAT, piece)));
+                    }
+                }
+                FCTL if rewrite => {
+                    if streams[0].is_none() {
+                        frame += 1;
+                    }
+                    let mut data = chunk.data.clone();
+                    data[..4].copy_from_slice(&sequence.to_be_bytes());
+                    sequence += 1;
+                    chunks.push(Chunk::new(FCTL, data));
+                }
+                FDAT if rewrite => {
+                    if let Some(pieces) = streams.get_mut(frame).and_then(Option::take) {
+                        for piece in pieces {
+                            let mut data = sequence.to_be_bytes().to_vec();
+                            data.extend(piece);
+                            sequence += 1;
+                            chunks.push(Chunk::new(FDAT, data));
+                        }
+                    }
+                }
+                // A payload left by an earlier encoding would shadow the new one
+                _ if stale.contains(&index) => {}
+                TIME if metadata != Metadata::Keep => {}
+                kind if TEXT_CHUNKS.contains(&kind) && metadata == Metadata::Strip => {}
+                IEND => {
+                    if metadata == Metadata::Touch {
+                        let now = SystemTime::now()
+                            .duration_since(UNIX_EPOCH)
+                            .map_or(0, |elapsed| elapsed.as_secs());
+                        chunks.push(time_chunk(now));
+                    }
+                    chunks.extend(self.attachments.iter().cloned());
+                    chunks.push(chunk.clone());
+                }
+                _ => chunks.push(chunk.clone()),
+            }
+        }
+        chunk::serialize(&chunks)
+    }
+}
+
+/// Encodes a frame like the carrier's original, unless the options say otherwise
+///
+/// # Arguments:
+/// - **raster**: `&Raster`
+///   - frame to encode
+/// - **pieces**: `&[&[u8]]`
+///   - original compressed data of the frame, as split over chunks
+/// - **options**: `&EncodeOptions`
+///   - filter and compression settings
+/// # Returns:
+/// - `Vec<Vec<u8>>` new compressed data, split the same way
+fn reencode(raster: &Raster, pieces: &[&[u8]], options: &EncodeOptions) -> Vec<Vec<u8>> {
+    let original = Settings::of(raster, pieces);
+    let (filters, level, chunk_size) = match &original {
+        Some(original) => (
+            original.filters.as_slice(),
+            original.level,
+            original.chunk_size,
+        ),
+        None => (&[][..], DEFAULT_LEVEL, None),
+    };
+    let level = match options.compression {
+        Compression::Match => level,
+        Compression::Level(level) => level,
+    };
+    idat::split(
+        idat::encode(raster, options.filter, filters, level),
+        chunk_size,
+    )
+}
+
+/// Groups the compressed image data of a PNG file by frame
+///
+/// The IDAT chunks come first; every fcTL after them starts a frame stored
+/// in fdAT chunks, whose data is given without its sequence number.
+fn streams(chunks: &[Chunk]) -> Vec<Vec<&[u8]>> {
+    let mut streams = vec![Vec::new()];
+    let mut after_idat = false;
+    for chunk in chunks {
+        match chunk.kind {
+            IDAT => {
+                after_idat = true;
+                streams[0].push(chunk.data.as_slice());
+            }
+            FCTL if after_idat => streams.push(Vec::new()),
+            FDAT if streams.len() > 1 => {
+                let last = streams.len() - 1;
+                streams[last].push(chunk.data.get(4..).unwrap_or_default());
+            }
+            _ => {}
+        }
+    }
+    streams
+}
+
+/// Rows of a decoded frame, without padding and with sub-byte samples expanded
+///
+/// # Arguments:
+/// - **buffer**: `&[u8]`
+///   - output of the PNG decoder
+/// - **output**: `&OutputInfo`
+///   - size and row length of the frame
+/// - **stride**: `usize`
+///   - distance between the starts of two rows in the buffer
+/// # Returns:
+/// - `Vec<u8>` samples of the frame, see [`Image::data`]
+fn frame_samples(buffer: &[u8], output: &OutputInfo, stride: usize) -> Vec<u8> {
+    let rows = buffer
+        .chunks(stride)
+        .take(output.height as usize)
+        .flat_map(|row| &row[..output.line_size]);
+    let depth = output.bit_depth as u8;
+    match depth {
+        1 | 2 | 4 => unpack(
+            &rows.copied().collect::<Vec<u8>>(),
+            output.width as usize * output.color_type.samples(),
+            depth,
+        ),
+        _ => rows.copied().collect(),
+    }
+}
+
+/// Builds a tIME chunk for a UTC timestamp
+///
+/// # Arguments:
+/// - **seconds**: `u64`
+///   - seconds since the Unix epoch
+/// # Returns:
+/// - `Chunk` tIME chunk: year, month, day, hour, minute and second
+fn time_chunk(seconds: u64) -> Chunk {
+    // Civil date from days since the epoch, after Howard Hinnant's algorithm
+    let days = (seconds / 86_400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+
+    let time = seconds % 86_400;
+    let mut data = (year as u16).to_be_bytes().to_vec();
+    data.extend([
+        month as u8,
+        day as u8,
+        (time / 3_600) as u8,
+        (time / 60 % 60) as u8,
+        (time % 60) as u8,
+    ]);
+    Chunk::new(TIME, data)
+}
+
+/// Expands packed sub-byte samples to one byte per sample
+///
+/// # Arguments:
+/// - **data**: `&[u8]`
+///   - packed rows, each padded to a whole byte
+/// - **row_samples**: `usize`
+///   - number of samples in each row
+/// - **depth**: `u8`
+///   - bits per sample, 1, 2 or 4
+/// # Returns:
+/// - `Vec<u8>` one sample per byte, without row padding
+fn unpack(data: &[u8], row_samples: usize, depth: u8) -> Vec<u8> {
+    let depth = depth as usize;
+    let mask = (1u8 << depth) - 1;
+    data.chunks((row_samples * depth).div_ceil(8))
+        .flat_map(|row| {
+            (0..row_samples).map(move |i| {
+                let bit = i * depth;
+                (row[bit / 8] >> (8 - depth - bit % 8)) & mask
+            })
+        })
+        .collect()
+}
+
+/// Packs one-byte samples back into sub-byte samples, see [`unpack`]
+pub(crate) fn pack(samples: &[u8], row_samples: usize, depth: u8) -> Vec<u8> {
+    let depth = depth as usize;
+    let mut data = Vec::with_capacity(samples.len() * depth / 8 + 1);
+    for row in samples.chunks(row_samples) {
+        let mut packed = vec![0u8; (row_samples * depth).div_ceil(8)];
+        for (i, sample) in row.iter().enumerate() {
+            let bit = i * depth;
+            packed[bit / 8] |= sample << (8 - depth - bit % 8);
+        }
+        data.extend(packed);
+    }
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unpack_and_pack_sub_byte_rows() {
+        // Two rows of three 2-bit samples, each row padded to a byte
+        let data = [0b01_10_11_00, 0b11_00_01_00];
+        let samples = unpack(&data, 3, 2);
+        assert_eq!(samples, vec![1, 2, 3, 3, 0, 1]);
+        assert_eq!(pack(&samples, 3, 2), data);
+
+        // Ten 1-bit samples spill into a padded second byte of the same row
+        let data = [0b1011_0000, 0b0100_0000];
+        assert_eq!(unpack(&data, 10, 1), vec![1, 0, 1, 1, 0, 0, 0, 0, 0, 1]);
+        assert_eq!(pack(&unpack(&data, 10, 1), 10, 1), data);
+    }
+
+    #[test]
+    fn test_sub_byte_image_roundtrip() {
+        let mut png = Vec::new();
+        {
+            let mut encoder = png::Encoder::new(&mut png, 5, 2);
+            encoder.set_color(ColorType::Grayscale);
+            encoder.set_depth(BitDepth::Four);
+            let mut writer = encoder.write_header().unwrap();
+            writer
+                .write_image_data(&[0x01, 0x23, 0x40, 0x56, 0x78, 0x90])
+                .unwrap();
+        }
+        let image = Image::read(&png).unwrap();
+        assert_eq!(image.data, vec![0, 1, 2, 3, 4, 5, 6, 7, 8, 9]);
+        assert_eq!(
+            Image::read(&image.write(&EncodeOptions::default()))
+                .unwrap()
+                .data,
+            image.data
+        );
+    }
+
+    // 4x4 RGB carrier with a color profile, density, comment and timestamp
+    fn annotated_png() -> Vec<u8> {
+        let mut png = Vec::new();
+        {
+            let mut encoder = png::Encoder::new(&mut png, 4, 4);
+            encoder.set_color(ColorType::Rgb);
+            encoder.set_depth(BitDepth::Eight);
+            let mut writer = encoder.write_header().unwrap();
+            writer
+                .write_chunk(png::chunk::gAMA, &45_455u32.to_be_bytes())
+                .unwrap();
+            writer
+                .write_chunk(png::chunk::pHYs, &[0, 0, 11, 19, 0, 0, 11, 19, 1])
+                .unwrap();
+            writer
+                .write_chunk(png::chunk::tEXt, b"Comment\0holiday")
+                .unwrap();
+            writer
+                .write_chunk(png::chunk::tIME, &time_chunk(0).data)
+                .unwrap();
+            writer.write_image_data(&[7; 48]).unwrap();
+        }
+        png
+    }
+
+    fn kinds(png: &[u8]) -> Vec<[u8; 4]> {
+        chunk::parse(png)
+            .unwrap()
+            .into_iter()
+            .map(|chunk| chunk.kind)
+            .collect()
+    }
+
+    #[test]
+    fn test_write_keeps_ancillary_chunks() {
+        let carrier = annotated_png();
+        let mut image = Image::read(&carrier).unwrap();
+        image.data[0] ^= 1;
+        let written = image.write(&EncodeOptions::default());
+        assert_eq!(kinds(&written), kinds(&carrier));
+        let original = chunk::parse(&carrier).unwrap();
+        for chunk in chunk::parse(&written).unwrap() {
+            if chunk.kind != IDAT {
+                assert!(original.contains(&chunk));
+            }
+        }
+        assert_eq!(Image::read(&written).unwrap().data, image.data);
+    }
+
+    #[test]
+    fn test_write_strips_or_touches_metadata() {
+        let image = Image::read(&annotated_png()).unwrap();
+        assert_eq!(
+            kinds(&image.write(&EncodeOptions::default().with_metadata(Metadata::Strip))),
+            vec![*b"IHDR", *b"gAMA", *b"pHYs", IDAT, IEND]
+        );
+
+        let touched =
+            chunk::parse(&image.write(&EncodeOptions::default().with_metadata(Metadata::Touch)))
+                .unwrap();
+        let times: Vec<&Chunk> = touched.iter().filter(|chunk| chunk.kind == TIME).collect();
+        assert_eq!(times.len(), 1);
+        assert_ne!(times[0].data, time_chunk(0).data);
+        assert_eq!(touched[touched.len() - 2].kind, TIME);
+        assert!(touched.iter().any(|chunk| chunk.kind == *b"tEXt"));
+    }
+
+    #[test]
+    fn test_time_chunk_dates() {
+        assert_eq!(time_chunk(0).data, vec![0x07, 0xB2, 1, 1, 0, 0, 0]);
+        // 2024-02-29 13:14:15 UTC
+        assert_eq!(
+            time_chunk(1_709_212_455).data,
+            vec![0x07, 0xE8, 2, 29, 13, 14, 15]
+        );
+    }
+
+    // 16x16 RGB animation of three frames, the last two smaller than the
+    // image, optionally after a default image outside the animation
+    fn animated_png(default_image: bool) -> Vec<u8> {
+        let mut png = Vec::new();
+        {
+            let mut encoder = png::Encoder::new(&mut png, 16, 16);
+            encoder.set_color(ColorType::Rgb);
+            encoder.set_animated(3, 0).unwrap();
+            encoder.set_sep_def_img(default_image).unwrap();
+            let mut writer = encoder.write_header().unwrap();
+            if default_image {
+                writer.write_image_data(&[9; 16 * 16 * 3]).unwrap();
+            }
+            writer.set_frame_delay(1, 10).unwrap();
+            writer.write_image_data(&[10; 16 * 16 * 3]).unwrap();
+            writer.set_frame_dimension(8, 8).unwrap();
+            writer.set_frame_position(4, 4).unwrap();
+            writer.set_frame_delay(3, 10).unwrap();
+            writer.write_image_data(&[20; 8 * 8 * 3]).unwrap();
+            writer.set_frame_position(0, 4).unwrap();
+            writer.set_frame_dimension(16, 4).unwrap();
+            writer.set_frame_position(0, 12).unwrap();
+            writer.write_image_data(&[30; 16 * 4 * 3]).unwrap();
+        }
+        png
+    }
+
+    #[test]
+    fn test_write_keeps_every_apng_frame() {
+        for default_image in [false, true] {
+            let carrier = animated_png(default_image);
+            let mut image = Image::read(&carrier).unwrap();
+            let sizes: Vec<(u32, u32)> = image
+                .frames
+                .iter()
+                .map(|frame| (frame.width, frame.height))
+                .collect();
+            let expected = [(16, 16), (8, 8), (16, 4)];
+            let fdat_frames = if default_image {
+                &expected[..]
+            } else {
+                &expected[1..]
+            };
+            assert_eq!(sizes, fdat_frames);
+
+            let mut samples = image.samples();
+            for (i, sample) in samples.iter_mut().enumerate() {
+                *sample ^= (i % 2) as u8;
+            }
+            image.set_samples(&samples);
+            let written = image.write(&EncodeOptions::default());
+            let reread = Image::read(&written).unwrap();
+            assert_eq!(reread.samples(), samples);
+
+            // Timing and placement survive, and the sequence is unbroken
+            let controls = |png: &[u8]| -> Vec<Vec<u8>> {
+                chunk::parse(png)
+                    .unwrap()
+                    .into_iter()
+                    .filter(|chunk| chunk.kind == FCTL)
+                    .map(|chunk| chunk.data[4..].to_vec())
+                    .collect()
+            };
+            assert_eq!(controls(&written), controls(&carrier));
+            let sequence: Vec<u32> = chunk::parse(&written)
+                .unwrap()
+                .into_iter()
+                .filter(|chunk| chunk.kind == FCTL || chunk.kind == FDAT)
+                .map(|chunk| u32::from_be_bytes(chunk.data[..4].try_into().unwrap()))
+                .collect();
+            assert_eq!(sequence, (0..sequence.len() as u32).collect::<Vec<_>>());
+        }
+    }
+}
@@ -0,0 +1,914 @@
+//! JPEG carriers: embedding in quantized DCT coefficients
+//!
+//! The pixels of a JPEG are recomputed from its DCT coefficients on every
+//! decode, so changing pixels would not survive saving the image again.
+//! Instead the entropy-coded scans are decoded to their quantized
+//! coefficients, the message goes into those, and the scans are encoded again
+//! with the original Huffman and quantization tables. Every other segment is
+//! copied over as it was.
+//!
+//! Following JSteg, only AC coefficients with a magnitude of at least 2 carry
+//! bits, in the LSB of their magnitude: flipping it never turns a coefficient
+//! into zero or moves it to another size category, so the zero runs and
+//! Huffman symbols stay the same, and the original tables can always encode
+//! the result. Baseline and extended sequential Huffman-coded JPEGs are
+//! supported; progressive, lossless and arithmetic-coded ones are not.
+use crate::error::{Result, StegError};
+use crate::image::{Format, Image};
+use png::{BitDepth, ColorType};
+
+/// Signature every JPEG file starts with: SOI, then the first marker
+pub(crate) const SIGNATURE: [u8; 3] = [0xFF, 0xD8, 0xFF];
+
+const SOF0: u8 = 0xC0;
+const SOF1: u8 = 0xC1;
+const DHT: u8 = 0xC4;
+const RST0: u8 = 0xD0;
+const RST7: u8 = 0xD7;
+const EOI: u8 = 0xD9;
+const SOS: u8 = 0xDA;
+const DRI: u8 = 0xDD;
+const TEM: u8 = 0x01;
+
+/// Most blocks a byte of JPEG file may hold. Every coded block takes a DC and
+/// an AC code of at least one bit each, so a frame announcing more blocks is
+/// larger than its file
+const MAX_BLOCKS_PER_BYTE: usize = 4;
+
+/// A decoded JPEG file, down to the quantized coefficients of every block
+#[derive(Clone)]
+pub(crate) struct Jpeg {
+    components: Vec<Component>,
+    /// MCUs per row and column in interleaved scans
+    mcus_x: usize,
+    mcus_y: usize,
+    /// Largest sampling factors of the frame
+    max_h: usize,
+    max_v: usize,
+    width: usize,
+    height: usize,
+    /// Segments in file order, after SOI
+    segments: Vec<Segment>,
+}
+
+/// A color component of the frame
+#[derive(Clone)]
+struct Component {
+    id: u8,
+    h: usize,
+    v: usize,
+    /// Blocks per row, padded to whole MCUs
+    blocks_per_line: usize,
+    /// Quantized coefficients of every block, in zigzag order
+    blocks: Vec<[i16; 64]>,
+}
+
+#[derive(Clone)]
+enum Segment {
+    /// Marker segment, or trailing bytes from EOI on, copied verbatim
+    Raw(Vec<u8>),
+    Scan(Scan),
+}
+
+/// A scan with the tables in force when it was read
+#[derive(Clone)]
+struct Scan {
+    /// SOS marker segment
+    header: Vec<u8>,
+    /// Component index with its DC and AC tables, in scan order
+    components: Vec<(usize, Huffman, Huffman)>,
+    /// MCUs between restart markers, 0 for none
+    restart: usize,
+}
+
+/// Huffman table from a DHT segment, for decoding and encoding
+#[derive(Clone)]
+struct Huffman {
+    values: Vec<u8>,
+    /// Smallest and largest code of each length, -1 when there is none
+    mincode: [i32; 17],
+    maxcode: [i32; 17],
+    /// Index in `values` of the first code of each length
+    valptr: [usize; 17],
+    /// Code and length of each symbol, length 0 when it has no code
+    codes: Vec<(u16, u8)>,
+}
+
+impl Huffman {
+    /// Builds the canonical codes of a table
+    ///
+    /// # Arguments:
+    /// - **counts**: `&[u8]`
+    ///   - number of codes of each length from 1 to 16
+    /// - **values**: `&[u8]`
+    ///   - symbols in code order
+    fn new(counts: &[u8], values: &[u8]) -> Huffman {
+        let mut table = Huffman {
+            values: values.to_vec(),
+            mincode: [-1; 17],
+            maxcode: [-1; 17],
+            valptr: [0; 17],
+            codes: vec![(0, 0); 256],
+        };
+        let mut code = 0i32;
+        let mut index = 0;
+        for length in 1..=16 {
+            let count = counts[length - 1] as usize;
+            if count > 0 {
+                table.valptr[length] = index;
+                table.mincode[length] = code;
+                for value in &values[index..index + count] {
+                    table.codes[*value as usize] = (code as u16, length as u8);
+                    code += 1;
+                }
+                index += count;
+                table.maxcode[length] = code - 1;
+            }
+            code <<= 1;
+        }
+        table
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> Option<u8> {
+        let mut code = 0i32;
+        for length in 1..=16 {
+            code = code << 1 | reader.bit() as i32;
+            if code <= self.maxcode[length] {
+                let index = self.valptr[length] + (code - self.mincode[length]) as usize;
+                return self.values.get(index).copied();
+            }
+        }
+        None
+    }
+
+    fn encode(&self, writer: &mut BitWriter, symbol: u8) -> Result<()> {
+        match self.codes[symbol as usize] {
+            (_, 0) => Err(StegError::ImageDecode(format!(
+                "JPEG: Huffman table has no code for symbol {:#04x}",
+                symbol
+            ))),
+            (code, length) => {
+                writer.put(code as u32, length);
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Reads the bits of one restart interval, with stuffed zero bytes removed
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+    bit: u8,
+}
+
+impl BitReader<'_> {
+    /// Next bit, or 1 past the end like the padding of the last byte
+    fn bit(&mut self) -> u8 {
+        let Some(byte) = self.data.get(self.position) else {
+            return 1;
+        };
+        let bit = byte >> (7 - self.bit) & 1;
+        self.bit += 1;
+        if self.bit == 8 {
+            self.bit = 0;
+            self.position += 1;
+        }
+        bit
+    }
+
+    /// Reads a coefficient or difference of `size` bits
+    fn receive_extend(&mut self, size: u8) -> i32 {
+        let mut value = 0i32;
+        for _ in 0..size {
+            value = value << 1 | self.bit() as i32;
+        }
+        // Values below half the range stand for negative numbers
+        if size > 0 && value < 1 << (size - 1) {
+            value - (1 << size) + 1
+        } else {
+            value
+        }
+    }
+}
+
+/// Writes entropy-coded bits, stuffing a zero after every 0xFF byte
+#[derive(Default)]
+struct BitWriter {
+    output: Vec<u8>,
+    bits: u32,
+    count: u8,
+}
+
+impl BitWriter {
+    fn put(&mut self, bits: u32, length: u8) {
+        self.bits = self.bits << length | bits & ((1 << length) - 1);
+        self.count += length;
+        while self.count >= 8 {
+            let byte = (self.bits >> (self.count - 8)) as u8;
+            self.output.push(byte);
+            if byte == 0xFF {
+                self.output.push(0);
+            }
+            self.count -= 8;
+        }
+        self.bits &= (1 << self.count) - 1;
+    }
+
+    /// Writes a coefficient or difference in `size` bits, see [`BitReader::receive_extend`]
+    fn put_value(&mut self, value: i32, size: u8) {
+        let bits = if value < 0 { value - 1 } else { value };
+        self.put(bits as u32, size);
+    }
+
+    /// Pads the last byte with ones
+    fn flush(&mut self) {
+        if self.count > 0 {
+            self.put(0xFF, 8 - self.count);
+        }
+    }
+}
+
+/// Number of bits needed for the magnitude of a value
+fn size_of(value: i32) -> u8 {
+    (32 - value.unsigned_abs().leading_zeros()) as u8
+}
+
+/// Decodes an in-memory JPEG file
+///
+/// # Arguments:
+/// - **bytes**: `&[u8]`
+///   - complete JPEG file contents
+/// # Returns:
+/// - `Result<Image>` one sample per carrying coefficient, its LSB being the
+///   LSB of the coefficient's magnitude
+///   - `StegError::ImageDecode` for a malformed or unsupported JPEG
+pub(crate) fn read(bytes: &[u8]) -> Result<Image> {
+    let jpeg = Jpeg::parse(bytes)?;
+    // Every symbol the scans need must have a code before anything is embedded
+    jpeg.serialize()?;
+    Ok(Image {
+        width: jpeg.width as u32,
+        height: jpeg.height as u32,
+        color_type: ColorType::Grayscale,
+        bit_depth: BitDepth::Eight,
+        palette: None,
+        trns: None,
+        interlaced: false,
+        chunks: Vec::new(),
+        attachments: Vec::new(),
+        data: jpeg.carriers(),
+        frames: Vec::new(),
+        format: Format::Jpeg(jpeg),
+    })
+}
+
+/// Encodes an image read from a JPEG file back into it
+///
+/// # Arguments:
+/// - **image**: `&Image`
+///   - image returned by [`read`], with changed samples
+/// - **jpeg**: `&Jpeg`
+///   - the original file, decoded
+/// # Returns:
+/// - `Vec<u8>` complete JPEG file contents
+pub(crate) fn write(image: &Image, jpeg: &Jpeg) -> Vec<u8> {
+    let mut jpeg = jpeg.clone();
+    jpeg.apply(&image.data);
+    jpeg.serialize()
+        .expect("symbols were checked when the JPEG was read")
+}
+
+impl Jpeg {
+    fn parse(bytes: &[u8]) -> Result<Jpeg> {
+        let invalid = |reason: &str| StegError::ImageDecode(format!("JPEG: {}", reason));
+        if !bytes.starts_with(&SIGNATURE[..2]) {
+            return Err(invalid("missing start of image"));
+        }
+
+        let mut jpeg: Option<Jpeg> = None;
+        let mut segments = Vec::new();
+        let mut dc_tables: [Option<Huffman>; 4] = Default::default();
+        let mut ac_tables: [Option<Huffman>; 4] = Default::default();
+        let mut restart = 0;
+        let mut position = 2;
+        loop {
+            if bytes.get(position) != Some(&0xFF) {
+                return Err(invalid("expected a marker"));
+            }
+            // Any number of fill bytes may precede a marker
+            while bytes.get(position + 1) == Some(&0xFF) {
+                position += 1;
+            }
+            let marker = *bytes
+                .get(position + 1)
+                .ok_or_else(|| invalid("missing end of image"))?;
+            if marker == EOI {
+                segments.push(Segment::Raw(bytes[position..].to_vec()));
+                break;
+            }
+            if marker == TEM || (RST0..=RST7).contains(&marker) {
+                segments.push(Segment::Raw(bytes[position..position + 2].to_vec()));
+                position += 2;
+                continue;
+            }
+
+            let length = bytes
+                .get(position + 2..position + 4)
+                .map(|length| u16::from_be_bytes([length[0], length[1]]) as usize)
+                .filter(|length| *length >= 2)
+                .ok_or_else(|| invalid("truncated segment"))?;
+            let end = position + 2 + length;
+            let data = bytes
+                .get(position + 4..end)
+                .ok_or_else(|| invalid("truncated segment"))?;
+            match marker {
+                SOF0 | SOF1 => jpeg = Some(Jpeg::frame(data, bytes.len() * MAX_BLOCKS_PER_BYTE)?),
+                0xC2 | 0xC3 | 0xC5..=0xCB | 0xCC..=0xCF => {
+                    return Err(invalid(
+                        "only baseline and extended sequential Huffman-coded JPEGs are supported",
+                    ));
+                }
+                DHT => {
+                    let mut rest = data;
+                    while let [class_id, tail @ ..] = rest {
+                        let counts = tail.get(..16).ok_or_else(|| invalid("bad Huffman table"))?;
+                        let total: usize = counts.iter().map(|count| *count as usize).sum();
+                        let values = tail
+                            .get(16..16 + total)
+                            .ok_or_else(|| invalid("bad Huffman table"))?;
+                        let table = Some(Huffman::new(counts, values));
+                        match (class_id >> 4, (class_id & 0x0F) as usize) {
+                            (0, id @ 0..4) => dc_tables[id] = table,
+                            (1, id @ 0..4) => ac_tables[id] = table,
+                            _ => return Err(invalid("bad Huffman table")),
+                        }
+                        rest = &tail[16 + total..];
+                    }
+                }
+                DRI => {
+                    restart = data
+                        .get(..2)
+                        .map(|interval| u16::from_be_bytes([interval[0], interval[1]]) as usize)
+                        .ok_or_else(|| invalid("bad restart interval"))?;
+                }
+                _ => {}
+            }
+            if marker != SOS {
+                segments.push(Segment::Raw(bytes[position..end].to_vec()));
+                position = end;
+                continue;
+            }
+
+            let frame = jpeg
+                .as_mut()
+                .ok_or_else(|| invalid("scan before the frame header"))?;
+            let count = *data.first().ok_or_else(|| invalid("bad scan header"))? as usize;
+            let (selectors, spectral) = data[1..]
+                .split_at_checked(count * 2)
+                .ok_or_else(|| invalid("bad scan header"))?;
+            if spectral != [0, 63, 0] {
+                return Err(invalid(
+                    "unexpected spectral selection in a sequential scan",
+                ));
+            }
+            let mut components = Vec::with_capacity(count);
+            for selector in selectors.chunks(2) {
+                let index = frame
+                    .components
+                    .iter()
+                    .position(|component| component.id == selector[0])
+                    .ok_or_else(|| invalid("scan of an unknown component"))?;
+                let (dc, ac) = ((selector[1] >> 4) as usize, (selector[1] & 0x0F) as usize);
+                let table = |tables: &[Option<Huffman>; 4], id: usize| {
+                    tables
+                        .get(id)
+                        .cloned()
+                        .flatten()
+                        .ok_or_else(|| invalid("scan uses an undefined Huffman table"))
+                };
+                components.push((index, table(&dc_tables, dc)?, table(&ac_tables, ac)?));
+            }
+            let scan = Scan {
+                header: bytes[position..end].to_vec(),
+                components,
+                restart,
+            };
+
+            // The entropy-coded data runs up to the first marker other than RSTn
+            let mut next = end;
+            while next < bytes.len() {
+                if bytes[next] == 0xFF
+                    && let Some(marker) = bytes.get(next + 1)
+                    && *marker != 0
+                    && !(RST0..=RST7).contains(marker)
+                {
+                    break;
+                }
+                next += 1;
+            }
+            frame.decode_scan(&scan, &bytes[end..next])?;
+            segments.push(Segment::Scan(scan));
+            position = next;
+        }
+
+        let mut jpeg = jpeg.ok_or_else(|| invalid("missing frame header"))?;
+        jpeg.segments = segments;
+        Ok(jpeg)
+    }
+
+    /// Reads a SOF0 or SOF1 frame header
+    ///
+    /// # Arguments:
+    /// - **data**: `&[u8]`
+    ///   - contents of the marker segment
+    /// - **max_blocks**: `usize`
+    ///   - most blocks the components may hold together
+    /// # Returns:
+    /// - `Result<Jpeg>` the frame, its blocks all zero
+    ///   - `StegError::ImageDecode` for a malformed header or too many blocks
+    fn frame(data: &[u8], max_blocks: usize) -> Result<Jpeg> {
+        let invalid = |reason: &str| StegError::ImageDecode(format!("JPEG: {}", reason));
+        let field = |offset: usize| {
+            data.get(offset)
+                .copied()
+                .ok_or_else(|| invalid("bad frame header"))
+        };
+        let height = u16::from_be_bytes([field(1)?, field(2)?]) as usize;
+        let width = u16::from_be_bytes([field(3)?, field(4)?]) as usize;
+        let count = field(5)? as usize;
+        let specs = data
+            .get(6..6 + count * 3)
+            .ok_or_else(|| invalid("bad frame header"))?;
+        let sampling: Vec<(usize, usize)> = specs
+            .chunks(3)
+            .map(|spec| ((spec[1] >> 4) as usize, (spec[1] & 0x0F) as usize))
+            .collect();
+        // A height of zero announces a DNL segment, which is not supported
+        if width == 0
+            || height == 0
+            || count == 0
+            || sampling
+                .iter()
+                .any(|(h, v)| !(1..=4).contains(h) || !(1..=4).contains(v))
+        {
+            return Err(invalid("bad frame header"));
+        }
+        let max_h = sampling.iter().map(|(h, _)| *h).max().unwrap_or(1);
+        let max_v = sampling.iter().map(|(_, v)| *v).max().unwrap_or(1);
+        let mcus_x = width.div_ceil(8 * max_h);
+        let mcus_y = height.div_ceil(8 * max_v);
+        let total: usize = sampling.iter().map(|(h, v)| mcus_x * h * mcus_y * v).sum();
+        if total > max_blocks {
+            return Err(invalid("image too large for its file"));
+        }
+
+        let mut components = Vec::with_capacity(count);
+        for (spec, (h, v)) in specs.chunks(3).zip(sampling) {
+            let len = mcus_x * h * mcus_y * v;
+            let mut blocks = Vec::new();
+            blocks
+                .try_reserve_exact(len)
+                .map_err(|_| invalid("image too large to decode"))?;
+            blocks.resize(len, [0; 64]);
+            components.push(Component {
+                id: spec[0],
+                h,
+                v,
+                blocks_per_line: mcus_x * h,
+                blocks,
+            });
+        }
+        Ok(Jpeg {
+            components,
+            mcus_x,
+            mcus_y,
+            max_h,
+            max_v,
+            width,
+            height,
+            segments: Vec::new(),
+        })
+    }
+
+    /// Blocks coded in each MCU of a scan, as (component, block) indices
+    ///
+    /// The iterators borrow nothing, so blocks can be changed while walking them
+    fn mcus(
+        &self,
+        scan: &Scan,
+    ) -> impl Iterator<Item = impl Iterator<Item = (usize, usize)> + use<>> + use<> {
+        // Blocks per MCU across and down, and per line, of at most 4 components
+        let mut layout = [(0, 0, 0, 0); 4];
+        for (entry, (index, ..)) in layout.iter_mut().zip(&scan.components) {
+            let component = &self.components[*index];
+            *entry = (*index, component.h, component.v, component.blocks_per_line);
+        }
+        let count = scan.components.len().min(4);
+        let (columns, rows) = match scan.components[..] {
+            // A scan of a single component codes its blocks one by one, and
+            // only those covering the component
+            [(index, ..)] => {
+                let component = &self.components[index];
+                layout[0].1 = 1;
+                layout[0].2 = 1;
+                (
+                    (self.width * component.h).div_ceil(self.max_h).div_ceil(8),
+                    (self.height * component.v).div_ceil(self.max_v).div_ceil(8),
+                )
+            }
+            _ => (self.mcus_x, self.mcus_y),
+        };
+        (0..rows).flat_map(move |mcu_y| {
+            (0..columns).map(move |mcu_x| {
+                layout
+                    .into_iter()
+                    .take(count)
+                    .flat_map(move |(index, h, v, blocks_per_line)| {
+                        (0..v).flat_map(move |y| {
+                            (0..h).map(move |x| {
+                                let (row, column) = (mcu_y * v + y, mcu_x * h + x);
+                                (index, row * blocks_per_line + column)
+                            })
+                        })
+                    })
+            })
+        })
+    }
+
+    fn decode_scan(&mut self, scan: &Scan, entropy: &[u8]) -> Result<()> {
+        let corrupt = || StegError::ImageDecode("JPEG: corrupt entropy-coded data".to_string());
+
+        // Split at the restart markers and remove the stuffed zero bytes
+        let mut intervals = vec![Vec::new()];
+        let mut bytes = entropy.iter();
+        while let Some(byte) = bytes.next() {
+            if *byte != 0xFF {
+                intervals.last_mut().unwrap().push(*byte);
+                continue;
+            }
+            match bytes.next() {
+                Some(0) => intervals.last_mut().unwrap().push(0xFF),
+                Some(_) => intervals.push(Vec::new()),
+                None => {}
+            }
+        }
+
+        let per_interval = match scan.restart {
+            0 => usize::MAX,
+            restart => restart,
+        };
+        let mut reader = BitReader {
+            data: &[],
+            position: 0,
+            bit: 0,
+        };
+        let mut predictions = vec![0i32; self.components.len()];
+        for (number, mcu) in self.mcus(scan).enumerate() {
+            // Each restart interval starts on a byte boundary, with fresh predictions
+            if number % per_interval == 0 {
+                let interval = number / per_interval;
+                reader = BitReader {
+                    data: intervals.get(interval).map_or(&[][..], Vec::as_slice),
+                    position: 0,
+                    bit: 0,
+                };
+                predictions.fill(0);
+            }
+            for (index, block) in mcu {
+                let (_, dc, ac) = scan
+                    .components
+                    .iter()
+                    .find(|(component, ..)| *component == index)
+                    .unwrap();
+                let coefficients = &mut self.components[index].blocks[block];
+
+                let size = dc.decode(&mut reader).ok_or_else(corrupt)?;
+                predictions[index] += reader.receive_extend(size);
+                coefficients[0] = predictions[index] as i16;
+                let mut k = 1;
+                while k < 64 {
+                    let symbol = ac.decode(&mut reader).ok_or_else(corrupt)?;
+                    let (run, size) = ((symbol >> 4) as usize, symbol & 0x0F);
+                    if size == 0 {
+                        if run != 15 {
+                            break;
+                        }
+                        k += 16;
+                        continue;
+                    }
+                    k += run;
+                    if k > 63 {
+                        return Err(corrupt());
+                    }
+                    coefficients[k] = reader.receive_extend(size) as i16;
+                    k += 1;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn encode_scan(&self, scan: &Scan) -> Result<Vec<u8>> {
+        let mut writer = BitWriter::default();
+        let mut predictions = vec![0i32; self.components.len()];
+        for (number, mcu) in self.mcus(scan).enumerate() {
+            if scan.restart > 0 && number > 0 && number % scan.restart == 0 {
+                writer.flush();
+                let marker = RST0 + ((number / scan.restart - 1) % 8) as u8;
+                writer.output.extend([0xFF, marker]);
+                predictions.fill(0);
+            }
+            for (index, block) in mcu {
+                let (_, dc, ac) = scan
+                    .components
+                    .iter()
+                    .find(|(component, ..)| *component == index)
+                    .unwrap();
+                let coefficients = &self.components[index].blocks[block];
+
+                let difference = coefficients[0] as i32 - predictions[index];
+                predictions[index] = coefficients[0] as i32;
+                let size = size_of(difference);
+                dc.encode(&mut writer, size)?;
+                writer.put_value(difference, size);
+                let mut run = 0;
+                for coefficient in &coefficients[1..] {
+                    if *coefficient == 0 {
+                        run += 1;
+                        continue;
+                    }
+                    while run > 15 {
+                        ac.encode(&mut writer, 0xF0)?;
+                        run -= 16;
+                    }
+                    let size = size_of(*coefficient as i32);
+                    ac.encode(&mut writer, (run << 4) as u8 | size)?;
+                    writer.put_value(*coefficient as i32, size);
+                    run = 0;
+                }
+                if run > 0 {
+                    ac.encode(&mut writer, 0x00)?;
+                }
+            }
+        }
+        writer.flush();
+        Ok(writer.output)
+    }
+
+    /// Serializes the file with freshly encoded scans
+    fn serialize(&self) -> Result<Vec<u8>> {
+        let mut output = SIGNATURE[..2].to_vec();
+        for segment in &self.segments {
+            match segment {
+                Segment::Raw(bytes) => output.extend_from_slice(bytes),
+                Segment::Scan(scan) => {
+                    output.extend_from_slice(&scan.header);
+                    output.extend(self.encode_scan(scan)?);
+                }
+            }
+        }
+        Ok(output)
+    }
+
+    /// Coefficients able to carry a bit: AC, with a magnitude of at least 2
+    fn carrying(&self) -> impl Iterator<Item = &i16> {
+        self.components
+            .iter()
+            .flat_map(|component| &component.blocks)
+            .flat_map(|block| &block[1..])
+            .filter(|coefficient| coefficient.unsigned_abs() >= 2)
+    }
+
+    /// LSBs of the magnitudes of the carrying coefficients, one per byte
+    fn carriers(&self) -> Vec<u8> {
+        self.carrying()
+            .map(|coefficient| (coefficient.unsigned_abs() & 1) as u8)
+            .collect()
+    }
+
+    /// Writes changed LSBs back into the carrying coefficients, see [`Jpeg::carriers`]
+    fn apply(&mut self, carriers: &[u8]) {
+        let coefficients = self
+            .components
+            .iter_mut()
+            .flat_map(|component| &mut component.blocks)
+            .flat_map(|block| &mut block[1..])
+            .filter(|coefficient| coefficient.unsigned_abs() >= 2);
+        for (coefficient, carrier) in coefficients.zip(carriers) {
+            let magnitude = (coefficient.unsigned_abs() & !1) as i16 | (carrier & 1) as i16;
+            *coefficient = magnitude * coefficient.signum();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{decode_bytes, encode_bytes};
+
+    const DC_COUNTS: [u8; 16] = [0, 1, 5, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0];
+    const AC_COUNTS: [u8; 16] = [0, 2, 1, 3, 3, 2, 4, 3, 5, 5, 4, 4, 0, 0, 1, 0x7D];
+    // Luminance AC table from annex K of the JPEG specification
+    const AC_VALUES: [u8; 162] = [
+        0x01, 0x02, 0x03, 0x00, 0x04, 0x11, 0x05, 0x12, 0x21, 0x31, 0x41, 0x06, 0x13, 0x51, 0x61,
+        0x07, 0x22, 0x71, 0x14, 0x32, 0x81, 0x91, 0xA1, 0x08, 0x23, 0x42, 0xB1, 0xC1, 0x15, 0x52,
+        0xD1, 0xF0, 0x24, 0x33, 0x62, 0x72, 0x82, 0x09, 0x0A, 0x16, 0x17, 0x18, 0x19, 0x1A, 0x25,
+        0x26, 0x27, 0x28, 0x29, 0x2A, 0x34, 0x35, 0x36, 0x37, 0x38, 0x39, 0x3A, 0x43, 0x44, 0x45,
+        0x46, 0x47, 0x48, 0x49, 0x4A, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59, 0x5A, 0x63, 0x64,
+        0x65, 0x66, 0x67, 0x68, 0x69, 0x6A, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7A, 0x83,
+        0x84, 0x85, 0x86, 0x87, 0x88, 0x89, 0x8A, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97, 0x98, 0x99,
+        0x9A, 0xA2, 0xA3, 0xA4, 0xA5, 0xA6, 0xA7, 0xA8, 0xA9, 0xAA, 0xB2, 0xB3, 0xB4, 0xB5, 0xB6,
+        0xB7, 0xB8, 0xB9, 0xBA, 0xC2, 0xC3, 0xC4, 0xC5, 0xC6, 0xC7, 0xC8, 0xC9, 0xCA, 0xD2, 0xD3,
+        0xD4, 0xD5, 0xD6, 0xD7, 0xD8, 0xD9, 0xDA, 0xE1, 0xE2, 0xE3, 0xE4, 0xE5, 0xE6, 0xE7, 0xE8,
+        0xE9, 0xEA, 0xF1, 0xF2, 0xF3, 0xF4, 0xF5, 0xF6, 0xF7, 0xF8, 0xF9, 0xFA,
+    ];
+
+    // 16x16 image written by libjpeg, with 4:2:0 chroma subsampling and
+    // optimized Huffman tables, from the test data of CPython's imghdr module
+    const LIBJPEG: &[u8] = include_bytes!("../testdata/python.jpg");
+
+    // Segment with a marker and a length
+    fn segment(marker: u8, data: &[u8]) -> Vec<u8> {
+        let mut segment = vec![0xFF, marker];
+        segment.extend(((data.len() + 2) as u16).to_be_bytes());
+        segment.extend(data);
+        segment
+    }
+
+    // Baseline JPEG whose blocks hold a pattern of coefficients, with one
+    // interleaved scan over components of the given sampling factors
+    fn jpeg_file(width: u16, height: u16, sampling: &[u8], restart: u16) -> Vec<u8> {
+        let mut file = vec![0xFF, 0xD8];
+        file.extend(segment(0xE0, b"JFIF\0\x01\x01\0\0\x01\0\x01\0\0"));
+        let mut quantization = vec![0];
+        quantization.extend([1; 64]);
+        file.extend(segment(0xDB, &quantization));
+        let mut frame = vec![8];
+        frame.extend(height.to_be_bytes());
+        frame.extend(width.to_be_bytes());
+        frame.push(sampling.len() as u8);
+        for (id, factors) in sampling.iter().enumerate() {
+            frame.extend([id as u8 + 1, *factors, 0]);
+        }
+        file.extend(segment(SOF0, &frame));
+        let mut tables = vec![0x00];
+        tables.extend(DC_COUNTS);
+        tables.extend(0..12);
+        tables.push(0x10);
+        tables.extend(AC_COUNTS);
+        tables.extend(AC_VALUES);
+        file.extend(segment(DHT, &tables));
+        if restart > 0 {
+            file.extend(segment(DRI, &restart.to_be_bytes()));
+        }
+        let mut scan = vec![sampling.len() as u8];
+        for id in 0..sampling.len() {
+            scan.extend([id as u8 + 1, 0x00]);
+        }
+        scan.extend([0, 63, 0]);
+        let header = segment(SOS, &scan);
+
+        // Fill in the coefficients, then let the encoder write the scan
+        let mut jpeg = Jpeg::frame(&frame, usize::MAX).unwrap();
+        let table = |counts: &[u8], values: &[u8]| Huffman::new(counts, values);
+        let dc = table(&DC_COUNTS, &(0..12).collect::<Vec<u8>>());
+        let ac = table(&AC_COUNTS, &AC_VALUES);
+        let scan = Scan {
+            header,
+            components: (0..sampling.len())
+                .map(|index| (index, dc.clone(), ac.clone()))
+                .collect(),
+            restart: restart as usize,
+        };
+        let mut seed = 7u32;
+        for component in &mut jpeg.components {
+            for block in &mut component.blocks {
+                for (k, coefficient) in block.iter_mut().enumerate() {
+                    seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                    *coefficient = match k {
+                        0 => (seed >> 16) as i16 % 200,
+                        1..=20 => (seed >> 16) as i16 % 9,
+                        _ if seed >> 28 == 0 => (seed >> 16) as i16 % 40,
+                        _ => 0,
+                    };
+                }
+            }
+        }
+        jpeg.segments = vec![Segment::Scan(scan), Segment::Raw(vec![0xFF, EOI])];
+        file.extend(&jpeg.serialize().unwrap()[2..]);
+        file
+    }
+
+    #[test]
+    fn test_bit_writer_and_reader_agree() {
+        let mut writer = BitWriter::default();
+        for value in [-1023, -3, -1, 1, 2, 255, 1023] {
+            writer.put_value(value, size_of(value));
+        }
+        writer.put(0xFF, 8);
+        writer.flush();
+        assert!(writer.output.windows(2).any(|pair| pair == [0xFF, 0]));
+
+        let unstuffed: Vec<u8> = writer
+            .output
+            .iter()
+            .enumerate()
+            .filter(|(i, byte)| !(**byte == 0 && *i > 0 && writer.output[i - 1] == 0xFF))
+            .map(|(_, byte)| *byte)
+            .collect();
+        let mut reader = BitReader {
+            data: &unstuffed,
+            position: 0,
+            bit: 0,
+        };
+        for value in [-1023, -3, -1, 1, 2, 255, 1023] {
+            assert_eq!(reader.receive_extend(size_of(value)), value);
+        }
+    }
+
+    #[test]
+    fn test_parse_and_serialize_roundtrip() {
+        for (sampling, restart) in [
+            (&[0x11][..], 0),
+            (&[0x22, 0x11, 0x11][..], 0),
+            (&[0x21, 0x11, 0x11][..], 3),
+        ] {
+            let file = jpeg_file(37, 21, sampling, restart);
+            let jpeg = Jpeg::parse(&file).unwrap();
+            assert_eq!(jpeg.serialize().unwrap(), file);
+            assert!(jpeg.carriers().len() > 100);
+        }
+
+        let mut progressive = jpeg_file(8, 8, &[0x11], 0);
+        let sof = progressive
+            .windows(2)
+            .position(|pair| pair == [0xFF, SOF0])
+            .unwrap();
+        progressive[sof + 1] = 0xC2;
+        assert!(matches!(
+            Jpeg::parse(&progressive),
+            Err(StegError::ImageDecode(_))
+        ));
+        let file = jpeg_file(8, 8, &[0x11], 0);
+        assert!(matches!(
+            Jpeg::parse(&file[..file.len() - 2]),
+            Err(StegError::ImageDecode(_))
+        ));
+    }
+
+    #[test]
+    fn test_real_encoder_file() {
+        let jpeg = Jpeg::parse(LIBJPEG).unwrap();
+        assert_eq!(jpeg.serialize().unwrap(), LIBJPEG);
+        assert_eq!(jpeg.components.len(), 3);
+
+        let encoded = encode_bytes(LIBJPEG, b"libjpeg").unwrap();
+        assert_ne!(encoded, LIBJPEG);
+        assert_eq!(decode_bytes(&encoded).unwrap(), b"libjpeg");
+        let encoded = Jpeg::parse(&encoded).unwrap();
+        for (old, new) in jpeg.components.iter().zip(&encoded.components) {
+            assert_eq!(old.blocks.len(), new.blocks.len());
+        }
+    }
+
+    #[test]
+    fn test_read_rejects_oversized_frames() {
+        // 65535x65535 pixels announced in front of the scans of smaller images
+        for file in [LIBJPEG.to_vec(), jpeg_file(8, 8, &[0x11], 0)] {
+            let mut oversized = file.clone();
+            let sof = file
+                .windows(2)
+                .position(|pair| pair == [0xFF, SOF0])
+                .unwrap();
+            oversized[sof + 5..sof + 9].copy_from_slice(&[0xFF; 4]);
+            match decode_bytes(&oversized) {
+                Err(StegError::ImageDecode(reason)) => assert!(reason.contains("too large")),
+                _ => panic!("oversized JPEG accepted"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_embedding_keeps_zeros_dc_and_sizes() {
+        let file = jpeg_file(64, 48, &[0x22, 0x11, 0x11], 4);
+        let before = Jpeg::parse(&file).unwrap();
+        let encoded = encode_bytes(&file, b"quantized coefficients").unwrap();
+        let after = Jpeg::parse(&encoded).unwrap();
+        assert_eq!(decode_bytes(&encoded).unwrap(), b"quantized coefficients");
+
+        let mut changed = 0;
+        for (old, new) in before.components.iter().zip(&after.components) {
+            for (old, new) in old.blocks.iter().zip(&new.blocks) {
+                assert_eq!(old[0], new[0]);
+                for (old, new) in old[1..].iter().zip(&new[1..]) {
+                    assert_eq!(size_of(*old as i32), size_of(*new as i32));
+                    assert_eq!(old.signum(), new.signum());
+                    assert!(old.abs_diff(*new) <= 1);
+                    changed += (old != new) as usize;
+                }
+            }
+        }
+        assert!(changed > 0);
+    }
+}
@@ -0,0 +1,48 @@
+//! Stegarst: A Rust library for steganography utilities.
+//!
+//! Hides arbitrary files inside PNG, BMP, GIF, JPEG, lossless WebP, Netpbm, QOI
+//! and TIFF images and WAV and FLAC audio using LSB steganography, or inside
+//! plain text in whitespace or zero-width characters, and retrieves them
+//! again.
+//!
+//! ## Example:
+//! ```no_run
+//! stegarst::encode("input.png", "message.txt", "output.png")?;
+//! stegarst::decode("output.png", "message_out.txt")?;
+//! # Ok::<(), stegarst::StegError>(())
+//! ```
+mod attachment;
+pub mod bit_utils;
+mod bmp;
+mod chunk;
+pub mod crypto;
+pub mod decode;
+mod embed;
+pub mod encode;
+pub mod error;
+mod flac;
+mod gif;
+pub mod header;
+mod idat;
+mod image;
+mod jpeg;
+mod netpbm;
+pub mod options;
+mod palette;
+mod qoi;
+mod text;
+mod tiff;
+pub mod walk;
+mod wav;
+mod webp;
+
+pub use decode::{
+    decode, decode_bytes, decode_bytes_with, decode_stream, decode_stream_with, decode_with,
+};
+pub use encode::{
+    encode, encode_bytes, encode_bytes_with, encode_stream, encode_stream_with, encode_with,
+};
+pub use error::{Result, StegError};
+pub use options::{
+    Carrier, Channels, Compression, DecodeOptions, Embedding, EncodeOptions, Filter, Metadata,
+};
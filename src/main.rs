@@ -1,46 +1,104 @@
-//! Stegarst CLI: A command-line tool for steganography in PNG images.
-mod stegarst;
+//! Stegarst CLI: A command-line tool for steganography in images, audio files and plain text.
+mod cli;
 
 use clap::Parser;
-use stegarst::{cli::Cli, decode::decode, encode::encode};
+use cli::Cli;
+use std::{fs, process::ExitCode};
+use stegarst::{decode_with, encode_with};
 
 macro_rules! info {
     ($($arg:tt)*) => {{
         println!("[INFO] {}", format_args!($($arg)*));
     }};
 }
+macro_rules! success {
+    ($($arg:tt)*) => {{
+        println!("[SUCCESS] {}", format_args!($($arg)*));
+    }};
+}
+macro_rules! error {
+    ($($arg:tt)*) => {{
+        eprintln!("[ERROR] {}", format_args!($($arg)*));
+    }};
+}
 
 /// Entry point for the stegarst CLI application
 /// - Parses command-line arguments and calls the appropriate functions
-///   to either encode or decode messages in PNG images.
+///   to either encode or decode messages in images, audio files and plain text.
 /// ## Options:
 /// ```bash
 /// -o, --option <OPTION>        Specify 'read' to extract a message or 'write' to hide a message
 /// -f, --file <FILE>           Path to the file to hide (required for 'write' option)
-/// -i, --image <IMAGE>         Path to the image file
+/// -i, --image <IMAGE>         Path to the image (or WAV, FLAC or text) file
 /// --output <OUTPUT>           Path to output the result (message file or image file)
+/// --passphrase <PASSPHRASE>   Passphrase to encrypt or decrypt the hidden message
+/// --key-file <KEY_FILE>       Path to a file whose contents are used as the passphrase
+/// --walk-key <WALK_KEY>       Key scattering the message pseudo-randomly over the image
+/// --embedding <EMBEDDING>     'replacement' (default) or 'matching' to use LSB matching
+/// --bits-per-sample <BITS>    Number of low-order bits of each sample carrying the message, 1 to 4 (8 for 16-bit images)
+/// --channels <CHANNELS>       'color' (default, skips alpha), 'all', or letters from 'rgbya'
+/// --metadata <METADATA>       'keep' (default), 'strip' or 'touch' the carrier's timestamp and text chunks
+/// --compression <LEVEL>       'match' (default) to reuse the carrier's zlib level, or a level from 0 to 9
+/// --filter <FILTER>           'match' (default), 'adaptive', 'none', 'sub', 'up', 'average' or 'paeth'
+/// --carrier <CARRIER>         'pixels' (default), 'chunk' for a private chunk, 'text' for comment chunks,
+///                             or 'whitespace' or 'zero-width' to hide it in a plain text
 /// ```
 /// ## Example:
 /// ```bash
 /// stegarst --option write --file message.txt --image input.png --output output.png
 /// stegarst --option read --image input.png --output message.txt
+/// stegarst --option write --file message.txt --image input.png --output output.png --passphrase hunter2
 /// ```
-fn main() {
+fn main() -> ExitCode {
     let args = Cli::parse();
 
-    match args.option.as_str() {
+    let result = match args.option.as_str() {
         "read" => {
             info!("Starting to read file {}", &args.image);
-            decode(&args.image, &args.output)
+            args.decode_options()
+                .map_err(Into::into)
+                .and_then(|options| decode_with(&args.image, &args.output, &options))
+                .map(|_| success!("Succesfully retrived message to {}", &args.output))
         }
-        "write" => match args.file {
+        "write" => match &args.file {
             Some(file) => {
                 info!("Starting to write file {}", &args.output);
-                encode(&args.image, &file, &args.output)
+                args.encode_options()
+                    .map_err(Into::into)
+                    .and_then(|options| encode_with(&args.image, file, &args.output, &options))
+                    .map(|_| {
+                        success!("Succesfully saved message on image {}", &args.output);
+                        report_size_delta(&args.image, &args.output);
+                    })
+            }
+            _none => {
+                error!("File not passed!");
+                return ExitCode::FAILURE;
             }
-            _none => eprintln!("ERROR: File not passed!"),
         },
         _ => panic!("No valid option given: please try to use --help to see the valid options"),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            error!("{}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Prints how much bigger or smaller the output image is than the carrier
+fn report_size_delta(carrier: &str, output: &str) {
+    if let (Ok(before), Ok(after)) = (fs::metadata(carrier), fs::metadata(output)) {
+        let (before, after) = (before.len(), after.len());
+        info!(
+            "Output is {} bytes, {:+} bytes ({:+.2}%) compared to the carrier's {}",
+            after,
+            after as i64 - before as i64,
+            (after as f64 - before as f64) * 100.0 / before.max(1) as f64,
+            before
+        );
     }
 }
 
@@ -52,6 +110,7 @@ mod tests {
     use std::fs::File;
     use std::io::Write;
     use std::io::{BufReader, BufWriter};
+    use stegarst::{decode, encode};
 
     // Helper function to create a test PNG image
     fn create_test_image(path: &str, width: u32, height: u32) {
@@ -78,6 +137,43 @@ mod tests {
         file.write_all(content).unwrap();
     }
 
+    #[test]
+    fn test_cli_reads_passphrase_from_key_file() {
+        let key_file = "test_cli_key_file.bin";
+        create_test_message(key_file, b"\x00binary key\xff");
+
+        let args = Cli::parse_from([
+            "stegarst",
+            "--option",
+            "read",
+            "--image",
+            "in.png",
+            "--output",
+            "out.txt",
+            "--key-file",
+            key_file,
+        ]);
+        let options = args.decode_options().unwrap();
+        assert_eq!(options.passphrase.unwrap(), b"\x00binary key\xff");
+
+        let both = Cli::try_parse_from([
+            "stegarst",
+            "--option",
+            "read",
+            "--image",
+            "in.png",
+            "--output",
+            "out.txt",
+            "--key-file",
+            key_file,
+            "--passphrase",
+            "hunter2",
+        ]);
+        assert!(both.is_err());
+
+        fs::remove_file(key_file).ok();
+    }
+
     #[test]
     fn test_encode_decode_roundtrip() {
         let test_image = "test_encode_image.png";
@@ -91,10 +187,10 @@ mod tests {
         create_test_message(test_message, message_content);
 
         // Encode
-        encode(test_image, test_message, encoded_image);
+        encode(test_image, test_message, encoded_image).unwrap();
 
         // Decode
-        decode(encoded_image, decoded_message);
+        decode(encoded_image, decoded_message).unwrap();
 
         // Verify
         let decoded_content = fs::read(decoded_message).unwrap();
@@ -119,10 +215,10 @@ mod tests {
         create_test_message(test_message, b"");
 
         // Encode empty message
-        encode(test_image, test_message, encoded_image);
+        encode(test_image, test_message, encoded_image).unwrap();
 
         // Decode
-        decode(encoded_image, decoded_message);
+        decode(encoded_image, decoded_message).unwrap();
 
         // Verify
         let decoded_content = fs::read(decoded_message).unwrap();
@@ -148,10 +244,10 @@ mod tests {
         create_test_message(test_message, &binary_data);
 
         // Encode
-        encode(test_image, test_message, encoded_image);
+        encode(test_image, test_message, encoded_image).unwrap();
 
         // Decode
-        decode(encoded_image, decoded_message);
+        decode(encoded_image, decoded_message).unwrap();
 
         // Verify
         let decoded_content = fs::read(decoded_message).unwrap();
@@ -175,7 +271,7 @@ mod tests {
         create_test_message(test_message, b"test");
 
         // Encode
-        encode(test_image, test_message, encoded_image);
+        encode(test_image, test_message, encoded_image).unwrap();
 
         // Verify dimensions
         let decoder = Decoder::new(BufReader::new(File::open(encoded_image).unwrap()));
@@ -203,10 +299,10 @@ mod tests {
         create_test_message(test_message, &large_message);
 
         // Encode
-        encode(test_image, test_message, encoded_image);
+        encode(test_image, test_message, encoded_image).unwrap();
 
         // Decode
-        decode(encoded_image, decoded_message);
+        decode(encoded_image, decoded_message).unwrap();
 
         // Verify
         let decoded_content = fs::read(decoded_message).unwrap();
@@ -232,10 +328,10 @@ mod tests {
         create_test_message(test_message, special_content);
 
         // Encode
-        encode(test_image, test_message, encoded_image);
+        encode(test_image, test_message, encoded_image).unwrap();
 
         // Decode
-        decode(encoded_image, decoded_message);
+        decode(encoded_image, decoded_message).unwrap();
 
         // Verify
         let decoded_content = fs::read(decoded_message).unwrap();
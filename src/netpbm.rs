@@ -0,0 +1,321 @@
+//! Netpbm reading and writing: PGM and PPM, binary and ASCII, and PAM
+//!
+//! Only the raster of the first image is replaced on writing; any images
+//! following it are copied over. ASCII rasters keep their original layout:
+//! each number is replaced in place, whatever whitespace surrounds it.
+use crate::error::{Result, StegError};
+use crate::image::{Format, Image};
+use png::{BitDepth, ColorType};
+use std::ops::Range;
+
+/// Whether a file starts with a Netpbm magic number
+pub(crate) fn is_netpbm(bytes: &[u8]) -> bool {
+    matches!(bytes, [b'P', b'1'..=b'7', next, ..] if next.is_ascii_whitespace())
+}
+
+/// Where the raster of a Netpbm file lies, so it can be written back in place
+pub(crate) struct Netpbm {
+    /// Complete file contents, as read
+    file: Vec<u8>,
+    /// Bytes of the raster of the first image
+    raster: Range<usize>,
+    /// Whether samples are written as decimal numbers (P2 and P3)
+    ascii: bool,
+}
+
+/// Reads the header fields separated by whitespace and `#` comments
+struct Tokens<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Tokens<'a> {
+    fn next(&mut self) -> Option<&'a [u8]> {
+        loop {
+            match self.bytes.get(self.position)? {
+                b'#' => {
+                    while self
+                        .bytes
+                        .get(self.position)
+                        .is_some_and(|byte| *byte != b'\n')
+                    {
+                        self.position += 1;
+                    }
+                }
+                byte if byte.is_ascii_whitespace() => self.position += 1,
+                _ => break,
+            }
+        }
+        let start = self.position;
+        while self
+            .bytes
+            .get(self.position)
+            .is_some_and(|byte| !byte.is_ascii_whitespace())
+        {
+            self.position += 1;
+        }
+        Some(&self.bytes[start..self.position])
+    }
+
+    fn number(&mut self) -> Option<usize> {
+        std::str::from_utf8(self.next()?).ok()?.parse().ok()
+    }
+}
+
+/// Decodes an in-memory Netpbm file
+///
+/// # Arguments:
+/// - **bytes**: `&[u8]`
+///   - complete Netpbm file contents
+/// # Returns:
+/// - `Result<Image>` samples of the first image, big-endian pairs at 16 bits
+///   - `StegError::ImageDecode` for a truncated, malformed or unsupported file
+pub(crate) fn read(bytes: &[u8]) -> Result<Image> {
+    let invalid = |reason: &str| StegError::ImageDecode(format!("Netpbm: {}", reason));
+    let malformed = || invalid("malformed header");
+    let mut tokens = Tokens { bytes, position: 2 };
+
+    let (width, height, channels, maxval) = match bytes[1] {
+        b'1' | b'4' => return Err(invalid("PBM bitmaps are not supported")),
+        b'2' | b'5' => {
+            let (width, height) = (tokens.number(), tokens.number());
+            (width, height, Some(1), tokens.number())
+        }
+        b'3' | b'6' => {
+            let (width, height) = (tokens.number(), tokens.number());
+            (width, height, Some(3), tokens.number())
+        }
+        _ => {
+            let (mut width, mut height, mut depth, mut maxval) = (None, None, None, None);
+            loop {
+                match tokens.next().ok_or_else(malformed)? {
+                    b"WIDTH" => width = tokens.number(),
+                    b"HEIGHT" => height = tokens.number(),
+                    b"DEPTH" => depth = tokens.number(),
+                    b"MAXVAL" => maxval = tokens.number(),
+                    // Channels are told apart by their number alone
+                    b"TUPLTYPE" => {
+                        tokens.next();
+                    }
+                    b"ENDHDR" => break,
+                    _ => return Err(malformed()),
+                }
+            }
+            (width, height, depth, maxval)
+        }
+    };
+    let (Some(width), Some(height), Some(channels), Some(maxval)) =
+        (width, height, channels, maxval)
+    else {
+        return Err(malformed());
+    };
+    if width == 0 || height == 0 {
+        return Err(invalid("empty image"));
+    }
+    let color_type = match channels {
+        1 => ColorType::Grayscale,
+        2 => ColorType::GrayscaleAlpha,
+        3 => ColorType::Rgb,
+        4 => ColorType::Rgba,
+        _ => return Err(invalid(&format!("{} channels are not supported", channels))),
+    };
+    // Other maximums would let a changed sample overflow them
+    let bit_depth = match maxval {
+        255 => BitDepth::Eight,
+        65535 => BitDepth::Sixteen,
+        _ => {
+            return Err(invalid(&format!(
+                "maximum sample value {} is not supported, expected 255 or 65535",
+                maxval
+            )));
+        }
+    };
+    // A single whitespace character separates the header from the raster
+    let start = tokens.position + 1;
+    let sample_len = bit_depth as usize / 8;
+    let raster_len = [width, height, channels, sample_len]
+        .into_iter()
+        .try_fold(1usize, usize::checked_mul)
+        .filter(|_| width <= u32::MAX as usize && height <= u32::MAX as usize)
+        .ok_or_else(|| invalid("image dimensions are too large"))?;
+    let samples = raster_len / sample_len;
+
+    let ascii = matches!(bytes[1], b'2' | b'3');
+    let (raster, data) = if ascii {
+        let mut tokens = Tokens {
+            bytes,
+            position: start,
+        };
+        // Every number takes at least two bytes, so the file bounds the raster
+        let mut data = Vec::with_capacity(raster_len.min(bytes.len()));
+        for _ in 0..samples {
+            let sample = tokens
+                .number()
+                .filter(|sample| *sample <= maxval)
+                .ok_or_else(|| invalid("truncated or malformed raster"))?;
+            data.extend(&(sample as u16).to_be_bytes()[2 - sample_len..]);
+        }
+        (start..tokens.position, data)
+    } else {
+        if raster_len > bytes.len().saturating_sub(start) {
+            return Err(invalid("truncated raster"));
+        }
+        let raster = start..start + raster_len;
+        let data = bytes
+            .get(raster.clone())
+            .ok_or_else(|| invalid("truncated raster"))?;
+        (raster, data.to_vec())
+    };
+
+    Ok(Image {
+        width: width as u32,
+        height: height as u32,
+        color_type,
+        bit_depth,
+        palette: None,
+        trns: None,
+        interlaced: false,
+        chunks: Vec::new(),
+        attachments: Vec::new(),
+        data,
+        frames: Vec::new(),
+        format: Format::Netpbm(Netpbm {
+            file: bytes.to_vec(),
+            raster,
+            ascii,
+        }),
+    })
+}
+
+/// Encodes an image read from a Netpbm file back into it
+///
+/// # Arguments:
+/// - **image**: `&Image`
+///   - image returned by [`read`], with changed samples
+/// - **netpbm**: `&Netpbm`
+///   - layout of the original file
+/// # Returns:
+/// - `Vec<u8>` complete Netpbm file contents
+pub(crate) fn write(image: &Image, netpbm: &Netpbm) -> Vec<u8> {
+    let mut file = netpbm.file[..netpbm.raster.start].to_vec();
+    if netpbm.ascii {
+        let samples: Vec<u16> = match image.bit_depth {
+            BitDepth::Sixteen => image
+                .data
+                .chunks(2)
+                .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                .collect(),
+            _ => image.data.iter().map(|sample| *sample as u16).collect(),
+        };
+        let mut samples = samples.into_iter();
+        let raster = &netpbm.file[netpbm.raster.clone()];
+        let mut position = 0;
+        while position < raster.len() {
+            if raster[position] == b'#' {
+                // Comments are copied up to the end of their line, digits included
+                while raster.get(position).is_some_and(|byte| *byte != b'\n') {
+                    file.push(raster[position]);
+                    position += 1;
+                }
+            } else if raster[position].is_ascii_digit() {
+                while raster.get(position).is_some_and(u8::is_ascii_digit) {
+                    position += 1;
+                }
+                let sample = samples.next().expect("one sample per number");
+                file.extend(sample.to_string().bytes());
+            } else {
+                file.push(raster[position]);
+                position += 1;
+            }
+        }
+    } else {
+        file.extend(&image.data);
+    }
+    file.extend(&netpbm.file[netpbm.raster.end..]);
+    file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{decode_bytes, encode_bytes};
+
+    fn netpbm(image: &Image) -> &Netpbm {
+        match &image.format {
+            Format::Netpbm(netpbm) => netpbm,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_read_formats() {
+        let image = read(b"P3\n# two pixels\n2 1\n255\n1 2 3\n 4 5 255\n").unwrap();
+        assert_eq!(image.color_type, ColorType::Rgb);
+        assert_eq!(image.data, vec![1, 2, 3, 4, 5, 255]);
+
+        let image = read(b"P5 1 2 65535\n\x01\x02\x03\x04trailing").unwrap();
+        assert_eq!(image.color_type, ColorType::Grayscale);
+        assert_eq!(image.bit_depth, BitDepth::Sixteen);
+        assert_eq!(image.data, vec![1, 2, 3, 4]);
+
+        let pam = b"P7\nWIDTH 1\nHEIGHT 1\nDEPTH 4\nMAXVAL 255\nTUPLTYPE RGB_ALPHA\nENDHDR\n\x01\x02\x03\x04";
+        let image = read(pam).unwrap();
+        assert_eq!(image.color_type, ColorType::Rgba);
+        assert_eq!(image.data, vec![1, 2, 3, 4]);
+
+        for refused in [
+            &b"P2 1 1 15\n3\n"[..],
+            b"P1 1 1\n1\n",
+            b"P6 2 2 255\n\x01\x02",
+            b"P2 2 1 255\n3 256\n",
+        ] {
+            assert!(matches!(read(refused), Err(StegError::ImageDecode(_))));
+        }
+    }
+
+    #[test]
+    fn test_read_rejects_oversized_rasters() {
+        for refused in [
+            &b"P5\n4294967296 4294967296\n255\n"[..],
+            b"P7\nWIDTH 4294967296\nHEIGHT 4294967296\nDEPTH 1\nMAXVAL 255\nENDHDR\n",
+            b"P2\n4000000000 4000000000\n255\n",
+            b"P6\n4000000000 4000000000\n65535\n\x01\x02",
+            b"P5 70000 70000 255\n\x01\x02\x03",
+        ] {
+            assert!(matches!(read(refused), Err(StegError::ImageDecode(_))));
+            assert!(matches!(
+                encode_bytes(refused, b"netpbm"),
+                Err(StegError::ImageDecode(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_write_keeps_layout() {
+        let file = b"P2\n# comment\n3 2\n255\n10  9 200\n\t0 1 2\nnext image";
+        let mut image = read(file).unwrap();
+        assert_eq!(write(&image, netpbm(&image)), file);
+
+        image.data[0] = 9;
+        image.data[1] = 10;
+        assert_eq!(
+            write(&image, netpbm(&image)),
+            b"P2\n# comment\n3 2\n255\n9  10 200\n\t0 1 2\nnext image"
+        );
+    }
+
+    #[test]
+    fn test_netpbm_carriers_roundtrip() {
+        let mut ascii = b"P3\n24 16\n255\n".to_vec();
+        for i in 0..24 * 16 * 3 {
+            ascii.extend(format!("{}{}", i % 256, if i % 12 == 11 { '\n' } else { ' ' }).bytes());
+        }
+        let mut binary = b"P7\nWIDTH 24\nHEIGHT 16\nDEPTH 2\nMAXVAL 65535\nENDHDR\n".to_vec();
+        binary.extend((0..24 * 16 * 2 * 2).map(|i| (i * 3 % 256) as u8));
+        for carrier in [ascii, binary] {
+            let encoded = encode_bytes(&carrier, b"netpbm").unwrap();
+            assert!(is_netpbm(&encoded));
+            assert_eq!(decode_bytes(&encoded).unwrap(), b"netpbm");
+        }
+    }
+}
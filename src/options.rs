@@ -0,0 +1,579 @@
+//! Options controlling how messages are hidden and retrieved
+use png::ColorType;
+use std::{fmt, ops::BitOr, str::FromStr};
+
+/// How a sample is changed when its LSB must carry a different bit
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Embedding {
+    /// Overwrites the LSB, moving even values up and odd values down
+    #[default]
+    Replacement,
+    /// Randomly adds or subtracts one (LSB matching), which avoids the
+    /// pairs-of-values asymmetry that chi-square and RS steganalysis detect.
+    /// With several bits per sample, moves to the closest value carrying them
+    Matching,
+}
+
+/// What happens to the carrier's timestamp and text chunks on encoding
+///
+/// Every other chunk, such as gAMA, cHRM, sRGB, iCCP or pHYs, is always kept.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Metadata {
+    /// Copies tIME, tEXt, zTXt and iTXt unchanged
+    #[default]
+    Keep,
+    /// Drops tIME, tEXt, zTXt and iTXt
+    Strip,
+    /// Sets tIME to the time of encoding, as an image editor saving the file
+    /// would, and keeps the text chunks
+    Touch,
+}
+
+/// Where in the carrier the message is hidden
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Carrier {
+    /// Low-order bits of the pixel samples
+    #[default]
+    Pixels,
+    /// A private ancillary chunk holding the message verbatim. Holds messages
+    /// of any size and survives optimizers that keep unknown chunks, but is
+    /// plain to see for anyone listing the chunks
+    Chunk,
+    /// Base64 text split over compressed comment chunks, which even more
+    /// tools keep
+    Text,
+    /// Spaces and tabs at the ends of the lines of a plain text cover, one
+    /// bit each
+    Whitespace,
+    /// A run of zero-width characters after the first word of a plain text
+    /// cover, two bits each
+    ZeroWidth,
+}
+
+/// zlib compression level of the re-encoded image data
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Compression {
+    /// Estimates the carrier's level from the size of its image data
+    #[default]
+    Match,
+    /// Fixed level from 0 (stored) to 9 (smallest)
+    Level(u8),
+}
+
+/// Filter type applied to each scanline of the re-encoded image
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Filter {
+    /// Reuses the filter type of the carrier's scanline at the same position
+    #[default]
+    Match,
+    /// Picks the filter with the smallest sum of absolute differences per
+    /// scanline, as libpng does
+    Adaptive,
+    // The same filter type for every scanline
+    None,
+    Sub,
+    Up,
+    Average,
+    Paeth,
+}
+
+/// Set of pixel channels allowed to carry the message
+///
+/// Channels missing from an image are ignored, so the same selection can be
+/// used for grayscale and color images alike.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Channels(u8);
+
+impl Channels {
+    pub const RED: Channels = Channels(0b0_0001);
+    pub const GREEN: Channels = Channels(0b0_0010);
+    pub const BLUE: Channels = Channels(0b0_0100);
+    pub const GRAY: Channels = Channels(0b0_1000);
+    pub const ALPHA: Channels = Channels(0b1_0000);
+    /// Every channel except alpha
+    pub const COLOR: Channels = Channels(0b0_1111);
+    /// Every channel, alpha included
+    pub const ALL: Channels = Channels(0b1_1111);
+
+    /// Checks whether every channel of `other` is selected
+    pub fn contains(self, other: Channels) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Channels stored in each pixel of an image, in sample order
+    ///
+    /// # Arguments:
+    /// - **color_type**: `ColorType`
+    ///   - color type reported by the PNG decoder
+    /// # Returns:
+    /// - `Vec<Channels>` one entry per sample of a pixel
+    pub fn of(color_type: ColorType) -> Vec<Channels> {
+        match color_type {
+            ColorType::Grayscale | ColorType::Indexed => vec![Channels::GRAY],
+            ColorType::GrayscaleAlpha => vec![Channels::GRAY, Channels::ALPHA],
+            ColorType::Rgb => vec![Channels::RED, Channels::GREEN, Channels::BLUE],
+            ColorType::Rgba => vec![
+                Channels::RED,
+                Channels::GREEN,
+                Channels::BLUE,
+                Channels::ALPHA,
+            ],
+        }
+    }
+
+    /// Positions of the selected channels within a pixel, as a bit mask
+    ///
+    /// # Arguments:
+    /// - **color_type**: `ColorType`
+    ///   - color type reported by the PNG decoder
+    /// # Returns:
+    /// - `u8` bit `i` set when the `i`-th sample of a pixel is selected
+    pub fn mask(self, color_type: ColorType) -> u8 {
+        Channels::of(color_type)
+            .into_iter()
+            .enumerate()
+            .filter(|(_, channel)| self.contains(*channel))
+            .fold(0, |mask, (index, _)| mask | 1 << index)
+    }
+}
+
+impl Default for Channels {
+    fn default() -> Channels {
+        Channels::COLOR
+    }
+}
+
+impl BitOr for Channels {
+    type Output = Channels;
+
+    fn bitor(self, other: Channels) -> Channels {
+        Channels(self.0 | other.0)
+    }
+}
+
+/// Settings for hiding a message
+#[derive(Debug, Clone)]
+pub struct EncodeOptions {
+    /// Encrypts the message with a key derived from this secret
+    pub passphrase: Option<Vec<u8>>,
+    /// Scatters the message over the image in an order derived from this key
+    pub walk_key: Option<Vec<u8>>,
+    /// How samples are changed to carry the message
+    pub embedding: Embedding,
+    /// Number of low-order bits of each sample carrying the message, 1 to 4,
+    /// or up to 8 in 16-bit images
+    pub bits_per_sample: u8,
+    /// Channels carrying the message, alpha is left untouched by default
+    pub channels: Channels,
+    /// What happens to the timestamp and text chunks of the carrier
+    pub metadata: Metadata,
+    /// zlib level of the re-encoded image data
+    pub compression: Compression,
+    /// Filter type of the re-encoded scanlines
+    pub filter: Filter,
+    /// Where the message is hidden, the decoder finds it on its own
+    pub carrier: Carrier,
+}
+
+impl Default for EncodeOptions {
+    fn default() -> EncodeOptions {
+        EncodeOptions {
+            passphrase: None,
+            walk_key: None,
+            embedding: Embedding::default(),
+            bits_per_sample: 1,
+            channels: Channels::default(),
+            metadata: Metadata::default(),
+            compression: Compression::default(),
+            filter: Filter::default(),
+            carrier: Carrier::default(),
+        }
+    }
+}
+
+/// Settings for retrieving a message
+#[derive(Debug, Clone, Default)]
+pub struct DecodeOptions {
+    /// Secret the message was encrypted with, if any
+    pub passphrase: Option<Vec<u8>>,
+    /// Key the message was scattered with, if any
+    pub walk_key: Option<Vec<u8>>,
+}
+
+impl EncodeOptions {
+    /// Sets the secret the message is encrypted with
+    ///
+    /// # Arguments:
+    /// - **passphrase**: `impl AsRef<[u8]>`
+    ///   - passphrase or key file contents
+    /// # Returns:
+    /// - `EncodeOptions` updated options
+    pub fn with_passphrase(mut self, passphrase: impl AsRef<[u8]>) -> Self {
+        self.passphrase = Some(passphrase.as_ref().to_vec());
+        self
+    }
+
+    /// Sets the key the message is scattered over the image with
+    ///
+    /// # Arguments:
+    /// - **walk_key**: `impl AsRef<[u8]>`
+    ///   - secret seeding the sample order
+    /// # Returns:
+    /// - `EncodeOptions` updated options
+    pub fn with_walk_key(mut self, walk_key: impl AsRef<[u8]>) -> Self {
+        self.walk_key = Some(walk_key.as_ref().to_vec());
+        self
+    }
+
+    /// Sets how samples are changed to carry the message
+    ///
+    /// # Arguments:
+    /// - **embedding**: `Embedding`
+    ///   - LSB replacement or LSB matching
+    /// # Returns:
+    /// - `EncodeOptions` updated options
+    pub fn with_embedding(mut self, embedding: Embedding) -> Self {
+        self.embedding = embedding;
+        self
+    }
+
+    /// Sets how many low-order bits of each sample carry the message
+    ///
+    /// More bits per sample raise the capacity but make the changes easier to
+    /// detect. The depth is recorded in the header, so decoding needs no setting.
+    ///
+    /// # Arguments:
+    /// - **bits_per_sample**: `u8`
+    ///   - 1 to 4 bits, or up to 8 in 16-bit images
+    /// # Returns:
+    /// - `EncodeOptions` updated options
+    pub fn with_bits_per_sample(mut self, bits_per_sample: u8) -> Self {
+        self.bits_per_sample = bits_per_sample;
+        self
+    }
+
+    /// Sets which channels carry the message
+    ///
+    /// The selection is recorded in the header, so decoding needs no setting.
+    ///
+    /// # Arguments:
+    /// - **channels**: `Channels`
+    ///   - e.g. `Channels::BLUE` or `Channels::RED | Channels::GREEN`
+    /// # Returns:
+    /// - `EncodeOptions` updated options
+    pub fn with_channels(mut self, channels: Channels) -> Self {
+        self.channels = channels;
+        self
+    }
+
+    /// Sets what happens to the timestamp and text chunks of the carrier
+    ///
+    /// # Arguments:
+    /// - **metadata**: `Metadata`
+    ///   - keep, strip or touch
+    /// # Returns:
+    /// - `EncodeOptions` updated options
+    pub fn with_metadata(mut self, metadata: Metadata) -> Self {
+        self.metadata = metadata;
+        self
+    }
+
+    /// Sets the zlib level the image data is compressed with
+    ///
+    /// # Arguments:
+    /// - **compression**: `Compression`
+    ///   - the carrier's estimated level, or a level from 0 to 9
+    /// # Returns:
+    /// - `EncodeOptions` updated options
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Sets the filter type applied to the scanlines
+    ///
+    /// # Arguments:
+    /// - **filter**: `Filter`
+    ///   - the carrier's filters, adaptive selection or a fixed type
+    /// # Returns:
+    /// - `EncodeOptions` updated options
+    pub fn with_filter(mut self, filter: Filter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Sets where in the carrier the message is hidden
+    ///
+    /// With a chunk carrier the pixels are left untouched, and the embedding
+    /// settings are ignored. The whitespace and zero-width carriers take a
+    /// plain text cover instead of an image.
+    ///
+    /// # Arguments:
+    /// - **carrier**: `Carrier`
+    ///   - pixels, a private chunk, text chunks, trailing whitespace or
+    ///     zero-width characters
+    /// # Returns:
+    /// - `EncodeOptions` updated options
+    pub fn with_carrier(mut self, carrier: Carrier) -> Self {
+        self.carrier = carrier;
+        self
+    }
+}
+
+impl DecodeOptions {
+    /// Sets the secret the message was encrypted with
+    ///
+    /// # Arguments:
+    /// - **passphrase**: `impl AsRef<[u8]>`
+    ///   - passphrase or key file contents
+    /// # Returns:
+    /// - `DecodeOptions` updated options
+    pub fn with_passphrase(mut self, passphrase: impl AsRef<[u8]>) -> Self {
+        self.passphrase = Some(passphrase.as_ref().to_vec());
+        self
+    }
+
+    /// Sets the key the message was scattered over the image with
+    ///
+    /// # Arguments:
+    /// - **walk_key**: `impl AsRef<[u8]>`
+    ///   - secret seeding the sample order
+    /// # Returns:
+    /// - `DecodeOptions` updated options
+    pub fn with_walk_key(mut self, walk_key: impl AsRef<[u8]>) -> Self {
+        self.walk_key = Some(walk_key.as_ref().to_vec());
+        self
+    }
+}
+
+impl FromStr for Embedding {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "replacement" => Ok(Embedding::Replacement),
+            "matching" => Ok(Embedding::Matching),
+            _ => Err(format!(
+                "unknown embedding '{}': expected 'replacement' or 'matching'",
+                name
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Embedding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Embedding::Replacement => write!(f, "replacement"),
+            Embedding::Matching => write!(f, "matching"),
+        }
+    }
+}
+
+impl FromStr for Metadata {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "keep" => Ok(Metadata::Keep),
+            "strip" => Ok(Metadata::Strip),
+            "touch" => Ok(Metadata::Touch),
+            _ => Err(format!(
+                "unknown metadata handling '{}': expected 'keep', 'strip' or 'touch'",
+                name
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Metadata {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Metadata::Keep => write!(f, "keep"),
+            Metadata::Strip => write!(f, "strip"),
+            Metadata::Touch => write!(f, "touch"),
+        }
+    }
+}
+
+impl FromStr for Carrier {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "pixels" => Ok(Carrier::Pixels),
+            "chunk" => Ok(Carrier::Chunk),
+            "text" => Ok(Carrier::Text),
+            "whitespace" => Ok(Carrier::Whitespace),
+            "zero-width" => Ok(Carrier::ZeroWidth),
+            _ => Err(format!(
+                "unknown carrier '{}': expected 'pixels', 'chunk', 'text', 'whitespace' or 'zero-width'",
+                name
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Carrier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Carrier::Pixels => write!(f, "pixels"),
+            Carrier::Chunk => write!(f, "chunk"),
+            Carrier::Text => write!(f, "text"),
+            Carrier::Whitespace => write!(f, "whitespace"),
+            Carrier::ZeroWidth => write!(f, "zero-width"),
+        }
+    }
+}
+
+impl FromStr for Compression {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "match" => Ok(Compression::Match),
+            _ => name
+                .parse()
+                .ok()
+                .filter(|level| *level <= 9)
+                .map(Compression::Level)
+                .ok_or_else(|| {
+                    format!(
+                        "unknown compression '{}': expected 'match' or a level from 0 to 9",
+                        name
+                    )
+                }),
+        }
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Compression::Match => write!(f, "match"),
+            Compression::Level(level) => write!(f, "{}", level),
+        }
+    }
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "match" => Ok(Filter::Match),
+            "adaptive" => Ok(Filter::Adaptive),
+            "none" => Ok(Filter::None),
+            "sub" => Ok(Filter::Sub),
+            "up" => Ok(Filter::Up),
+            "average" => Ok(Filter::Average),
+            "paeth" => Ok(Filter::Paeth),
+            _ => Err(format!(
+                "unknown filter '{}': expected 'match', 'adaptive', 'none', 'sub', 'up', 'average' or 'paeth'",
+                name
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Filter::Match => write!(f, "match"),
+            Filter::Adaptive => write!(f, "adaptive"),
+            Filter::None => write!(f, "none"),
+            Filter::Sub => write!(f, "sub"),
+            Filter::Up => write!(f, "up"),
+            Filter::Average => write!(f, "average"),
+            Filter::Paeth => write!(f, "paeth"),
+        }
+    }
+}
+
+impl FromStr for Channels {
+    type Err = String;
+
+    /// Parses 'all', 'color', or a combination of the letters r, g, b, y
+    /// (gray) and a, e.g. 'rgb' or 'b'
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "all" => Ok(Channels::ALL),
+            "color" => Ok(Channels::COLOR),
+            "" => Err("no channels given".to_string()),
+            letters => letters.chars().try_fold(Channels(0), |channels, letter| {
+                let channel = match letter {
+                    'r' => Channels::RED,
+                    'g' => Channels::GREEN,
+                    'b' => Channels::BLUE,
+                    'y' => Channels::GRAY,
+                    'a' => Channels::ALPHA,
+                    _ => {
+                        return Err(format!(
+                            "unknown channel '{}': expected 'all', 'color' or letters from 'rgbya'",
+                            letter
+                        ));
+                    }
+                };
+                Ok(channels | channel)
+            }),
+        }
+    }
+}
+
+impl fmt::Display for Channels {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Channels::ALL => write!(f, "all"),
+            Channels::COLOR => write!(f, "color"),
+            channels => [
+                (Channels::RED, 'r'),
+                (Channels::GREEN, 'g'),
+                (Channels::BLUE, 'b'),
+                (Channels::GRAY, 'y'),
+                (Channels::ALPHA, 'a'),
+            ]
+            .into_iter()
+            .filter(|(channel, _)| channels.contains(*channel))
+            .try_for_each(|(_, letter)| write!(f, "{}", letter)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_channels_parse_and_display() {
+        assert_eq!("all".parse::<Channels>().unwrap(), Channels::ALL);
+        assert_eq!("color".parse::<Channels>().unwrap(), Channels::COLOR);
+        assert_eq!(
+            "rb".parse::<Channels>().unwrap(),
+            Channels::RED | Channels::BLUE
+        );
+        assert!("rx".parse::<Channels>().is_err());
+        assert!("".parse::<Channels>().is_err());
+        assert_eq!((Channels::BLUE | Channels::ALPHA).to_string(), "ba");
+        assert_eq!(Channels::default().to_string(), "color");
+    }
+
+    #[test]
+    fn test_compression_and_filter_parse_and_display() {
+        assert_eq!("match".parse::<Compression>().unwrap(), Compression::Match);
+        assert_eq!("9".parse::<Compression>().unwrap(), Compression::Level(9));
+        assert!("10".parse::<Compression>().is_err());
+        assert_eq!(Compression::Level(0).to_string(), "0");
+        assert_eq!("paeth".parse::<Filter>().unwrap(), Filter::Paeth);
+        assert!("best".parse::<Filter>().is_err());
+        assert_eq!(Filter::default().to_string(), "match");
+    }
+
+    #[test]
+    fn test_channels_mask_follows_color_type() {
+        assert_eq!(Channels::COLOR.mask(ColorType::Rgba), 0b0111);
+        assert_eq!(Channels::ALL.mask(ColorType::Rgba), 0b1111);
+        assert_eq!(Channels::COLOR.mask(ColorType::GrayscaleAlpha), 0b01);
+        assert_eq!(Channels::BLUE.mask(ColorType::Rgb), 0b100);
+        assert_eq!(Channels::BLUE.mask(ColorType::Grayscale), 0);
+    }
+}
@@ -0,0 +1,131 @@
+//! EzStego-style embedding in palette images
+//!
+//! Palette indices are labels rather than intensities, so flipping their LSB
+//! swaps a pixel for an unrelated color. Instead the palette is sorted by
+//! opacity and luminance, and the message is carried by the parity of each
+//! pixel's rank in that order: changing a bit moves the pixel to its
+//! neighbouring, visually closest color. Colors are only paired with one of
+//! the same opacity, so no pixel turns transparent or opaque. The PLTE and
+//! tRNS chunks themselves are left untouched.
+
+/// Palette sorted so that neighbouring ranks hold similar colors
+pub(crate) struct Palette {
+    /// Rank of each palette index
+    ranks: Vec<u8>,
+    /// Palette index holding each rank
+    indices: Vec<u8>,
+    /// Alpha of the color holding each rank
+    alphas: Vec<u8>,
+}
+
+impl Palette {
+    /// Sorts a palette by opacity, then luminance
+    ///
+    /// # Arguments:
+    /// - **plte**: `&[u8]`
+    ///   - RGB triples of the PLTE chunk
+    /// - **trns**: `Option<&[u8]>`
+    ///   - alpha of the first palette entries, from the tRNS chunk
+    /// # Returns:
+    /// - `Palette` ranked palette
+    pub fn new(plte: &[u8], trns: Option<&[u8]>) -> Palette {
+        let alpha = |index: usize| trns.and_then(|trns| trns.get(index)).map_or(255, |a| *a);
+        let luminance =
+            |rgb: &[u8]| 299 * rgb[0] as u32 + 587 * rgb[1] as u32 + 114 * rgb[2] as u32;
+
+        let mut indices: Vec<u8> = (0..plte.len() / 3).map(|index| index as u8).collect();
+        indices.sort_by_key(|index| {
+            let index = *index as usize;
+            (
+                alpha(index),
+                luminance(&plte[index * 3..index * 3 + 3]),
+                index,
+            )
+        });
+        let mut ranks = vec![0; indices.len()];
+        for (rank, index) in indices.iter().enumerate() {
+            ranks[*index as usize] = rank as u8;
+        }
+        let alphas = indices.iter().map(|index| alpha(*index as usize)).collect();
+        Palette {
+            ranks,
+            indices,
+            alphas,
+        }
+    }
+
+    /// Rank of a pixel, if it has a partner rank to swap with
+    ///
+    /// With an odd number of colors the last rank is unpaired, as is a rank
+    /// whose partner differs in opacity, and indices past the end of the
+    /// palette have no rank at all; such pixels never carry message bits.
+    fn rank(&self, index: u8) -> Option<u8> {
+        self.ranks.get(index as usize).copied().filter(|rank| {
+            self.alphas
+                .get((*rank ^ 1) as usize)
+                .is_some_and(|alpha| *alpha == self.alphas[*rank as usize])
+        })
+    }
+
+    /// Ranks of the pixels able to carry message bits, in image order
+    ///
+    /// # Arguments:
+    /// - **data**: `&[u8]`
+    ///   - palette indices of the image, one byte per pixel
+    /// # Returns:
+    /// - `Vec<u8>` ranks whose LSB carries the message
+    pub fn ranks(&self, data: &[u8]) -> Vec<u8> {
+        data.iter().filter_map(|index| self.rank(*index)).collect()
+    }
+
+    /// Writes changed ranks back as palette indices, see [`Palette::ranks`]
+    ///
+    /// # Arguments:
+    /// - **data**: `&mut [u8]`
+    ///   - palette indices of the image, one byte per pixel
+    /// - **ranks**: `&[u8]`
+    ///   - ranks returned by [`Palette::ranks`], with only their LSB changed
+    pub fn apply(&self, data: &mut [u8], ranks: &[u8]) {
+        let mut ranks = ranks.iter();
+        for index in data.iter_mut() {
+            if self.rank(*index).is_some()
+                && let Some(rank) = ranks.next()
+            {
+                *index = self.indices[*rank as usize];
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_palette_pairs_similar_colors() {
+        // white, black, dark gray, light gray, fully transparent black
+        let plte = [255, 255, 255, 0, 0, 0, 64, 64, 64, 192, 192, 192, 0, 0, 0];
+        let trns = [255, 255, 255, 255, 0];
+        let palette = Palette::new(&plte, Some(&trns));
+        assert_eq!(palette.indices, vec![4, 1, 2, 3, 0]);
+
+        // The transparent entry is left unpaired rather than swapped with
+        // black, which loses its partner; dark gray pairs with light gray, and
+        // white is left unpaired
+        assert_eq!(palette.ranks(&[0, 1, 2, 3, 4, 7]), vec![2, 3]);
+        let mut data = vec![4, 1, 2, 3];
+        palette.apply(&mut data, &[3, 2]);
+        assert_eq!(data, vec![4, 1, 3, 2]);
+    }
+
+    #[test]
+    fn test_palette_apply_swaps_within_pairs() {
+        let plte = [0, 0, 0, 255, 255, 255, 10, 10, 10, 250, 250, 250];
+        let palette = Palette::new(&plte, None);
+        let mut data = vec![0, 1, 2, 3, 9];
+        let ranks: Vec<u8> = palette.ranks(&data).iter().map(|rank| rank ^ 1).collect();
+        palette.apply(&mut data, &ranks);
+        // black <-> dark gray, light gray <-> white, out-of-range index untouched
+        assert_eq!(data, vec![2, 3, 0, 1, 9]);
+    }
+}
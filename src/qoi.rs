@@ -0,0 +1,111 @@
+//! QOI reading and writing
+//!
+//! QOI images are lossless RGB or RGBA at 8 bits per channel and are simply
+//! decoded and encoded again, keeping the color space noted in the header.
+use crate::error::{Result, StegError};
+use crate::image::{Format, Image};
+use ::qoi::consts::{QOI_HEADER_SIZE, QOI_PADDING_SIZE};
+use ::qoi::{ColorSpace, Encoder};
+use png::{BitDepth, ColorType};
+
+/// Signature every QOI file starts with
+pub(crate) const SIGNATURE: [u8; 4] = *b"qoif";
+
+/// Most pixels a single byte of QOI data can stand for, with a run
+const MAX_RUN: usize = 62;
+
+/// Decodes an in-memory QOI file
+///
+/// # Arguments:
+/// - **bytes**: `&[u8]`
+///   - complete QOI file contents
+/// # Returns:
+/// - `Result<Image>` pixels in RGB(A) order
+///   - `StegError::ImageDecode` for a malformed file
+pub(crate) fn read(bytes: &[u8]) -> Result<Image> {
+    let invalid =
+        |reason: &dyn std::fmt::Display| StegError::ImageDecode(format!("QOI: {}", reason));
+    // The decoder allocates every pixel the header claims before decoding
+    let header = ::qoi::decode_header(bytes).map_err(|e| invalid(&e))?;
+    let data_len = bytes
+        .len()
+        .saturating_sub(QOI_HEADER_SIZE + QOI_PADDING_SIZE);
+    if header.n_pixels() > data_len * MAX_RUN {
+        return Err(invalid(&"image dimensions exceed the data"));
+    }
+    let (header, data) = ::qoi::decode_to_vec(bytes).map_err(|e| invalid(&e))?;
+    Ok(Image {
+        width: header.width,
+        height: header.height,
+        color_type: match header.channels.is_rgb() {
+            true => ColorType::Rgb,
+            false => ColorType::Rgba,
+        },
+        bit_depth: BitDepth::Eight,
+        palette: None,
+        trns: None,
+        interlaced: false,
+        chunks: Vec::new(),
+        attachments: Vec::new(),
+        data,
+        frames: Vec::new(),
+        format: Format::Qoi(header.colorspace),
+    })
+}
+
+/// Encodes an image read from a QOI file as a QOI file
+///
+/// # Arguments:
+/// - **image**: `&Image`
+///   - image returned by [`read`], with changed pixels
+/// - **colorspace**: `ColorSpace`
+///   - color space of the original file
+/// # Returns:
+/// - `Vec<u8>` complete QOI file contents
+pub(crate) fn write(image: &Image, colorspace: ColorSpace) -> Vec<u8> {
+    // The dimensions and channels come from a decoded QOI file, so they are valid
+    Encoder::new(&image.data, image.width, image.height)
+        .and_then(|encoder| encoder.with_colorspace(colorspace).encode_to_vec())
+        .expect("in-memory write")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{decode_bytes, encode_bytes};
+
+    #[test]
+    fn test_encoding_keeps_the_color_space() {
+        let rgb: Vec<u8> = (0..24 * 16 * 3).map(|i| (i * 7 % 256) as u8).collect();
+        let rgba: Vec<u8> = (0..24 * 16 * 4).map(|i| (i / 40) as u8).collect();
+        for data in [rgb, rgba] {
+            let carrier = Encoder::new(&data, 24, 16)
+                .unwrap()
+                .with_colorspace(ColorSpace::Linear)
+                .encode_to_vec()
+                .unwrap();
+            let encoded = encode_bytes(&carrier, b"quite ok").unwrap();
+            assert!(encoded.starts_with(&SIGNATURE));
+            assert_eq!(decode_bytes(&encoded).unwrap(), b"quite ok");
+
+            let image = read(&encoded).unwrap();
+            assert_eq!(image.data.len(), data.len());
+            assert!(matches!(image.format, Format::Qoi(ColorSpace::Linear)));
+        }
+    }
+
+    #[test]
+    fn test_read_rejects_more_pixels_than_runs_can_hold() {
+        let data = vec![0x80; 24 * 16 * 4];
+        let carrier = Encoder::new(&data, 24, 16)
+            .unwrap()
+            .encode_to_vec()
+            .unwrap();
+        // 20000x20000 pixels announced in front of a single run
+        let mut oversized = carrier.clone();
+        oversized[4..12].copy_from_slice(&[0, 0, 0x4E, 0x20, 0, 0, 0x4E, 0x20]);
+        for refused in [&b"qoif\0\0"[..], &oversized, &carrier[..carrier.len() - 9]] {
+            assert!(matches!(read(refused), Err(StegError::ImageDecode(_))));
+        }
+    }
+}
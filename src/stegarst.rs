@@ -1,5 +0,0 @@
-//! Stegarst: A Rust library for steganography utilities.
-pub mod bit_utils;
-pub mod cli;
-pub mod decode;
-pub mod encode;
@@ -0,0 +1,229 @@
+//! Plain text carriers
+//!
+//! When the only channel is plain text, such as a chat or a commit message,
+//! the payload is written into a cover text as characters that do not show.
+//! The whitespace carrier spreads its bits over the ends of the lines, a space
+//! for a 0 and a tab for a 1; the zero-width carrier inserts a run of
+//! zero-width characters, two bits each, after the first word. Decoding needs
+//! no setting: [`payloads`] collects whatever either carrier may have hidden.
+use crate::bit_utils::BitUtils;
+use crate::options::Carrier;
+
+/// Zero-width characters standing for the bit pairs 00, 01, 10 and 11
+const ZERO_WIDTH: [char; 4] = ['\u{200B}', '\u{200C}', '\u{200D}', '\u{2060}'];
+
+/// Zero-width characters in a row that make a payload, one byte's worth.
+/// Text uses them alone, such as a joiner between two emoji
+const MIN_RUN: usize = 4;
+
+/// Hides a payload in a cover text
+///
+/// Trailing whitespace of the cover is removed for the whitespace carrier,
+/// and runs of zero-width characters left by an earlier payload for the
+/// zero-width carrier, so that only the new payload is found.
+///
+/// # Arguments:
+/// - **cover**: `&str`
+///   - text to hide the payload in
+/// - **payload**: `&[u8]`
+///   - header followed by the message body
+/// - **carrier**: `Carrier`
+///   - `Carrier::Whitespace` or `Carrier::ZeroWidth`
+/// # Returns:
+/// - `String` the cover text with the payload hidden in it
+pub(crate) fn hide(cover: &str, payload: &[u8], carrier: Carrier) -> String {
+    match carrier {
+        Carrier::ZeroWidth => {
+            let cover = zero_width_runs(cover).iter().rev().fold(
+                cover.to_string(),
+                |mut cover, (start, run)| {
+                    cover.replace_range(*start..*start + run.len(), "");
+                    cover
+                },
+            );
+            let hidden: String = payload
+                .iter()
+                .flat_map(|byte| (0..4).rev().map(move |pair| byte >> (2 * pair) & 0b11))
+                .map(|pair| ZERO_WIDTH[pair as usize])
+                .collect();
+            // After the first word, where trimming or wrapping leaves it alone
+            let start = cover.len() - cover.trim_start().len();
+            let position = cover[start..]
+                .find(char::is_whitespace)
+                .map_or(cover.len(), |end| start + end);
+            format!("{}{}{}", &cover[..position], hidden, &cover[position..])
+        }
+        _ => {
+            let mut lines: Vec<&str> = cover.split_inclusive('\n').collect();
+            if lines.is_empty() {
+                lines.push("");
+            }
+            let bits = BitUtils::make_bits(payload.to_vec());
+            let per_line = bits.len().div_ceil(lines.len()).max(1);
+            let mut bits = bits.chunks(per_line);
+            let mut text = String::with_capacity(cover.len() + payload.len() * 8);
+            for line in lines {
+                let (content, ending) = split_ending(line);
+                text.push_str(content.trim_end_matches([' ', '\t']));
+                for bit in bits.next().unwrap_or_default() {
+                    text.push(if *bit == 1 { '\t' } else { ' ' });
+                }
+                text.push_str(ending);
+            }
+            text
+        }
+    }
+}
+
+/// Collects the payloads either text carrier may have hidden in a file
+///
+/// # Arguments:
+/// - **bytes**: `&[u8]`
+///   - complete file contents
+/// # Returns:
+/// - `Vec<Vec<u8>>` the bytes of every run of zero-width characters, then
+///   the bits of the trailing whitespace, none if the file is not UTF-8 text
+pub(crate) fn payloads(bytes: &[u8]) -> Vec<Vec<u8>> {
+    let Ok(text) = std::str::from_utf8(bytes) else {
+        return Vec::new();
+    };
+    let mut payloads: Vec<Vec<u8>> = zero_width_runs(text)
+        .iter()
+        .map(|(_, run)| {
+            let pairs: Vec<u8> = run
+                .chars()
+                .filter_map(|c| ZERO_WIDTH.iter().position(|zero_width| *zero_width == c))
+                .map(|pair| pair as u8)
+                .collect();
+            pairs
+                .chunks_exact(4)
+                .map(|byte| byte.iter().fold(0, |acc, pair| acc << 2 | pair))
+                .collect()
+        })
+        .collect();
+
+    let mut bits = Vec::new();
+    for line in text.split_inclusive('\n') {
+        let content = split_ending(line).0;
+        let trailing = &content[content.trim_end_matches([' ', '\t']).len()..];
+        bits.extend(trailing.bytes().map(|byte| (byte == b'\t') as u8));
+    }
+    bits.truncate(bits.len() / 8 * 8);
+    if !bits.is_empty() {
+        payloads.push(BitUtils::bits_to_bytes(bits));
+    }
+    payloads
+}
+
+/// Splits a line from its line ending, if any
+fn split_ending(line: &str) -> (&str, &str) {
+    let content = line
+        .strip_suffix('\n')
+        .map_or(line, |line| line.strip_suffix('\r').unwrap_or(line));
+    line.split_at(content.len())
+}
+
+/// Runs of zero-width characters long enough to be a payload, with their
+/// byte offset
+fn zero_width_runs(text: &str) -> Vec<(usize, &str)> {
+    let mut runs = Vec::new();
+    let mut start = None;
+    for (offset, c) in text.char_indices().chain([(text.len(), '\0')]) {
+        match (ZERO_WIDTH.contains(&c), start) {
+            (true, None) => start = Some(offset),
+            (false, Some(begin)) => {
+                if text[begin..offset].chars().count() >= MIN_RUN {
+                    runs.push((begin, &text[begin..offset]));
+                }
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    runs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::StegError;
+    use crate::header::MAGIC;
+    use crate::options::{DecodeOptions, EncodeOptions};
+    use crate::{decode_bytes, decode_bytes_with, encode_bytes, encode_bytes_with};
+
+    const COVER: &str = "Fix the build on Windows  \r\n\nThe path separator was hardcoded.\n";
+
+    #[test]
+    fn test_whitespace_keeps_visible_text() {
+        let text = hide(COVER, &[0b1010_0000, 0xFF], Carrier::Whitespace);
+        assert_eq!(
+            text,
+            "Fix the build on Windows\t \t   \r\n  \t\t\t\t\nThe path separator was hardcoded.\t\t\t\t\n"
+        );
+        assert_eq!(payloads(text.as_bytes()), vec![vec![0b1010_0000, 0xFF]]);
+    }
+
+    #[test]
+    fn test_zero_width_after_first_word() {
+        let cover = "  Family: \u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467} done";
+        let text = hide(cover, &[0b00_01_10_11, 0x42], Carrier::ZeroWidth);
+        assert!(text.starts_with("  Family:\u{200B}\u{200C}\u{200D}\u{2060}"));
+        // The joiners of the emoji are too short to be a payload
+        assert_eq!(payloads(text.as_bytes()), vec![vec![0b00_01_10_11, 0x42]]);
+
+        // Hiding again replaces the earlier payload
+        let again = hide(&text, &[0x24], Carrier::ZeroWidth);
+        assert_eq!(again.replace(ZERO_WIDTH, ""), cover.replace(ZERO_WIDTH, ""));
+        assert_eq!(payloads(again.as_bytes()), vec![vec![0x24]]);
+    }
+
+    #[test]
+    fn test_damaged_runs_do_not_stop_decoding() {
+        // A run with the magic but an unknown version, left behind by an edit
+        let mut damaged = MAGIC.to_vec();
+        damaged.extend([99; 11]);
+        let damaged = hide("", &damaged, Carrier::ZeroWidth);
+        assert!(matches!(
+            decode_bytes(damaged.as_bytes()),
+            Err(StegError::CorruptHeader(_))
+        ));
+
+        for carrier in [Carrier::Whitespace, Carrier::ZeroWidth] {
+            let options = EncodeOptions::default().with_carrier(carrier);
+            let encoded = encode_bytes_with(COVER.as_bytes(), b"still here", &options).unwrap();
+            let text = format!("{}{}", damaged, String::from_utf8(encoded).unwrap());
+            assert_eq!(decode_bytes(text.as_bytes()).unwrap(), b"still here");
+        }
+
+        // Nor an ASCII image carrying a message in its samples
+        let mut pgm = b"P2\n24 16\n255\n".to_vec();
+        pgm.extend((0..24 * 16).flat_map(|i| format!("{}\n", i % 256).into_bytes()));
+        let encoded = encode_bytes(&pgm, b"in the pixels").unwrap();
+        let mut commented = format!("P2\n# {}\n", damaged).into_bytes();
+        commented.extend(&encoded[3..]);
+        assert_eq!(decode_bytes(&commented).unwrap(), b"in the pixels");
+    }
+
+    #[test]
+    fn test_text_carriers_roundtrip() {
+        let encode_options = EncodeOptions::default().with_passphrase("plain sight");
+        let decode_options = DecodeOptions::default().with_passphrase("plain sight");
+        for carrier in [Carrier::Whitespace, Carrier::ZeroWidth] {
+            let options = encode_options.clone().with_carrier(carrier);
+            let encoded = encode_bytes_with(COVER.as_bytes(), b"meet at noon", &options).unwrap();
+            assert_eq!(
+                decode_bytes_with(&encoded, &decode_options).unwrap(),
+                b"meet at noon"
+            );
+            assert!(matches!(
+                decode_bytes(&encoded),
+                Err(StegError::PassphraseRequired)
+            ));
+        }
+        let options = EncodeOptions::default().with_carrier(Carrier::ZeroWidth);
+        assert!(matches!(
+            encode_bytes_with(&[0xFF, 0xFE], b"binary", &options),
+            Err(StegError::InvalidOptions(_))
+        ));
+    }
+}
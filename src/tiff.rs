@@ -0,0 +1,773 @@
+//! TIFF reading and writing
+//!
+//! Gray and RGB images at 8 or 16 bits per sample, with or without alpha,
+//! stored in uncompressed, LZW or Deflate strips are supported. Uncompressed
+//! strips are overwritten in place; compressed ones are compressed again and
+//! written over the old strip when they fit, or appended to the file with
+//! their offset and byte count updated.
+//!
+//! Every page (IFD) of a multi-page file with the same color type and bit
+//! depth as the first one carries part of the message, as extra frames of the
+//! image; other pages are left untouched.
+use crate::error::{Result, StegError};
+use crate::image::{Format, Frame, Image};
+use flate2::{Compression, read::ZlibDecoder, write::ZlibEncoder};
+use png::{BitDepth, ColorType};
+use std::io::{Read, Write};
+use std::ops::Range;
+use weezl::{BitOrder, LzwStatus, decode::Decoder, encode::Encoder};
+
+/// Signatures of little- and big-endian TIFF files
+pub(crate) const SIGNATURES: [[u8; 4]; 2] = [*b"II*\0", *b"MM\0*"];
+
+const IMAGE_WIDTH: u16 = 256;
+const IMAGE_LENGTH: u16 = 257;
+const BITS_PER_SAMPLE: u16 = 258;
+const COMPRESSION: u16 = 259;
+const PHOTOMETRIC: u16 = 262;
+const STRIP_OFFSETS: u16 = 273;
+const SAMPLES_PER_PIXEL: u16 = 277;
+const ROWS_PER_STRIP: u16 = 278;
+const STRIP_BYTE_COUNTS: u16 = 279;
+const PLANAR_CONFIGURATION: u16 = 284;
+const PREDICTOR: u16 = 317;
+const TILE_WIDTH: u16 = 322;
+const SAMPLE_FORMAT: u16 = 339;
+
+/// Field types of the values read and written here
+const SHORT: u16 = 3;
+const LONG: u16 = 4;
+
+/// Compression schemes
+const UNCOMPRESSED: u16 = 1;
+const LZW: u16 = 5;
+const DEFLATE: u16 = 8;
+const OLD_DEFLATE: u16 = 32946;
+
+/// Where the strips of every carrying page lie, so they can be written back
+pub(crate) struct Tiff {
+    /// Complete file contents, as read
+    file: Vec<u8>,
+    big_endian: bool,
+    /// Pages carrying the message: the first page, then the matching others
+    pages: Vec<Page>,
+}
+
+/// A page stored in strips
+struct Page {
+    width: usize,
+    height: usize,
+    color_type: ColorType,
+    bit_depth: BitDepth,
+    compression: u16,
+    /// Whether rows are stored as differences to the previous pixel
+    predictor: bool,
+    rows_per_strip: usize,
+    /// Bytes of every strip, as read
+    strips: Vec<Range<usize>>,
+    /// Positions of the StripOffsets and StripByteCounts directory entries
+    offsets_entry: usize,
+    counts_entry: usize,
+}
+
+/// Directory entry of a page
+struct Entry {
+    position: usize,
+    tag: u16,
+}
+
+/// Reads and writes numbers in the byte order of the file
+struct Order {
+    big_endian: bool,
+}
+
+impl Order {
+    fn u16_at(&self, bytes: &[u8], offset: usize) -> Option<u16> {
+        let field = bytes.get(offset..offset + 2)?;
+        let field = [field[0], field[1]];
+        Some(match self.big_endian {
+            true => u16::from_be_bytes(field),
+            false => u16::from_le_bytes(field),
+        })
+    }
+
+    fn u32_at(&self, bytes: &[u8], offset: usize) -> Option<u32> {
+        let field = bytes.get(offset..offset + 4)?;
+        let field = [field[0], field[1], field[2], field[3]];
+        Some(match self.big_endian {
+            true => u32::from_be_bytes(field),
+            false => u32::from_le_bytes(field),
+        })
+    }
+
+    fn put_u16(&self, bytes: &mut [u8], offset: usize, value: u16) {
+        bytes[offset..offset + 2].copy_from_slice(&match self.big_endian {
+            true => value.to_be_bytes(),
+            false => value.to_le_bytes(),
+        });
+    }
+
+    fn put_u32(&self, bytes: &mut [u8], offset: usize, value: u32) {
+        bytes[offset..offset + 4].copy_from_slice(&match self.big_endian {
+            true => value.to_be_bytes(),
+            false => value.to_le_bytes(),
+        });
+    }
+
+    /// SHORT or LONG values of a directory entry
+    fn values(&self, bytes: &[u8], entry: usize) -> Option<Vec<u32>> {
+        let kind = self.u16_at(bytes, entry + 2)?;
+        let count = self.u32_at(bytes, entry + 4)? as usize;
+        let size = match kind {
+            SHORT => 2,
+            LONG => 4,
+            _ => return None,
+        };
+        let start = match count * size {
+            0..=4 => entry + 8,
+            _ => self.u32_at(bytes, entry + 8)? as usize,
+        };
+        (0..count)
+            .map(|i| match kind {
+                SHORT => self.u16_at(bytes, start + i * 2).map(u32::from),
+                _ => self.u32_at(bytes, start + i * 4),
+            })
+            .collect()
+    }
+
+    /// Overwrites the values of a SHORT or LONG directory entry
+    ///
+    /// SHORT values that no longer fit are turned into LONG ones, stored at
+    /// the end of the file when they do not fit in the entry.
+    fn set_values(&self, file: &mut Vec<u8>, entry: usize, values: &[u32]) {
+        let mut kind = self.u16_at(file, entry + 2).unwrap_or(LONG);
+        let fits = values.iter().all(|value| *value <= u16::MAX as u32);
+        let start = if kind == SHORT && !fits {
+            kind = LONG;
+            self.put_u16(file, entry + 2, LONG);
+            if values.len() == 1 {
+                entry + 8
+            } else {
+                file.resize(file.len().next_multiple_of(2), 0);
+                let start = file.len();
+                file.resize(start + values.len() * 4, 0);
+                self.put_u32(file, entry + 8, start as u32);
+                start
+            }
+        } else {
+            let size = if kind == SHORT { 2 } else { 4 };
+            match values.len() * size {
+                0..=4 => entry + 8,
+                _ => self.u32_at(file, entry + 8).unwrap_or_default() as usize,
+            }
+        };
+        for (i, value) in values.iter().enumerate() {
+            match kind {
+                SHORT => self.put_u16(file, start + i * 2, *value as u16),
+                _ => self.put_u32(file, start + i * 4, *value),
+            }
+        }
+    }
+}
+
+/// Decodes an in-memory TIFF file
+///
+/// # Arguments:
+/// - **bytes**: `&[u8]`
+///   - complete TIFF file contents
+/// # Returns:
+/// - `Result<Image>` samples of the first page, then of the other carrying
+///   pages as frames, big-endian pairs at 16 bits
+///   - `StegError::ImageDecode` for a malformed file or an unsupported first page
+pub(crate) fn read(bytes: &[u8]) -> Result<Image> {
+    let invalid = |reason: &str| StegError::ImageDecode(format!("TIFF: {}", reason));
+    let order = Order {
+        big_endian: bytes.starts_with(&SIGNATURES[1]),
+    };
+
+    // Walk the chain of directories, guarding against loops
+    let mut directories = Vec::new();
+    let mut next = order
+        .u32_at(bytes, 4)
+        .ok_or_else(|| invalid("truncated header"))? as usize;
+    while next != 0 && !directories.contains(&next) {
+        let count = order
+            .u16_at(bytes, next)
+            .ok_or_else(|| invalid("truncated directory"))? as usize;
+        directories.push(next);
+        next = order
+            .u32_at(bytes, next + 2 + count * 12)
+            .ok_or_else(|| invalid("truncated directory"))? as usize;
+    }
+    let (first, others) = directories
+        .split_first()
+        .ok_or_else(|| invalid("no image"))?;
+
+    let first = read_page(bytes, &order, *first)?;
+    let mut data = vec![first.decode(bytes, &order)?];
+    let mut pages = vec![first];
+    for directory in others {
+        // Pages unlike the first one are left out rather than refused
+        let Ok(page) = read_page(bytes, &order, *directory) else {
+            continue;
+        };
+        if (page.color_type, page.bit_depth) != (pages[0].color_type, pages[0].bit_depth) {
+            continue;
+        }
+        if let Ok(samples) = page.decode(bytes, &order) {
+            data.push(samples);
+            pages.push(page);
+        }
+    }
+
+    let frames = pages[1..]
+        .iter()
+        .zip(data.drain(1..))
+        .map(|(page, data)| Frame {
+            width: page.width as u32,
+            height: page.height as u32,
+            data,
+        })
+        .collect();
+    Ok(Image {
+        width: pages[0].width as u32,
+        height: pages[0].height as u32,
+        color_type: pages[0].color_type,
+        bit_depth: pages[0].bit_depth,
+        palette: None,
+        trns: None,
+        interlaced: false,
+        chunks: Vec::new(),
+        attachments: Vec::new(),
+        data: data.remove(0),
+        frames,
+        format: Format::Tiff(Tiff {
+            file: bytes.to_vec(),
+            big_endian: order.big_endian,
+            pages,
+        }),
+    })
+}
+
+/// Reads the directory of a page, refusing anything but supported strips
+fn read_page(bytes: &[u8], order: &Order, directory: usize) -> Result<Page> {
+    let invalid = |reason: &str| StegError::ImageDecode(format!("TIFF: {}", reason));
+    let count = order.u16_at(bytes, directory).unwrap_or_default() as usize;
+    let entries: Vec<Entry> = (0..count)
+        .map(|i| directory + 2 + i * 12)
+        .filter_map(|position| {
+            let tag = order.u16_at(bytes, position)?;
+            Some(Entry { position, tag })
+        })
+        .collect();
+    let find = |tag: u16| entries.iter().find(|entry| entry.tag == tag);
+    let values = |tag: u16| find(tag).and_then(|entry| order.values(bytes, entry.position));
+    let value = |tag: u16| values(tag).and_then(|values| values.first().copied());
+
+    if find(TILE_WIDTH).is_some() {
+        return Err(invalid("tiled images are not supported"));
+    }
+    let width = value(IMAGE_WIDTH).ok_or_else(|| invalid("missing image width"))? as usize;
+    let height = value(IMAGE_LENGTH).ok_or_else(|| invalid("missing image length"))? as usize;
+    let samples_per_pixel = value(SAMPLES_PER_PIXEL).unwrap_or(1);
+    let color_type = match (value(PHOTOMETRIC), samples_per_pixel) {
+        (Some(0 | 1), 1) => ColorType::Grayscale,
+        (Some(0 | 1), 2) => ColorType::GrayscaleAlpha,
+        (Some(2), 3) => ColorType::Rgb,
+        (Some(2), 4) => ColorType::Rgba,
+        _ => return Err(invalid("only gray and RGB images are supported")),
+    };
+    let bits = values(BITS_PER_SAMPLE).unwrap_or_else(|| vec![1]);
+    let bit_depth = match bits[..] {
+        [8, ..] if bits.iter().all(|bits| *bits == 8) => BitDepth::Eight,
+        [16, ..] if bits.iter().all(|bits| *bits == 16) => BitDepth::Sixteen,
+        _ => return Err(invalid("only 8 and 16 bits per sample are supported")),
+    };
+    if value(SAMPLE_FORMAT).is_some_and(|format| format == 3) {
+        return Err(invalid("floating-point samples are not supported"));
+    }
+    if value(PLANAR_CONFIGURATION).unwrap_or(1) != 1 {
+        return Err(invalid("planar images are not supported"));
+    }
+    let compression = match value(COMPRESSION).unwrap_or(1) as u16 {
+        compression @ (UNCOMPRESSED | LZW | DEFLATE | OLD_DEFLATE) => compression,
+        compression => {
+            return Err(invalid(&format!(
+                "compression scheme {} is not supported",
+                compression
+            )));
+        }
+    };
+    let predictor = match value(PREDICTOR).unwrap_or(1) {
+        1 => false,
+        2 => true,
+        _ => return Err(invalid("floating-point prediction is not supported")),
+    };
+
+    let (Some(offsets_entry), Some(counts_entry)) = (find(STRIP_OFFSETS), find(STRIP_BYTE_COUNTS))
+    else {
+        return Err(invalid("missing strips"));
+    };
+    let (Some(offsets), Some(counts)) = (
+        order.values(bytes, offsets_entry.position),
+        order.values(bytes, counts_entry.position),
+    ) else {
+        return Err(invalid("malformed strips"));
+    };
+    let rows_per_strip =
+        (value(ROWS_PER_STRIP).unwrap_or(u32::MAX) as usize).clamp(1, height.max(1));
+    if width == 0 || height == 0 || offsets.len() != height.div_ceil(rows_per_strip) {
+        return Err(invalid("malformed strips"));
+    }
+    [width, color_type.samples(), bit_depth as usize / 8, height]
+        .into_iter()
+        .try_fold(1usize, usize::checked_mul)
+        .filter(|len| *len <= isize::MAX as usize)
+        .ok_or_else(|| invalid("image dimensions are too large"))?;
+    let strips: Vec<Range<usize>> = offsets
+        .iter()
+        .zip(&counts)
+        .map(|(offset, count)| *offset as usize..*offset as usize + *count as usize)
+        .collect();
+    if strips.iter().any(|strip| strip.end > bytes.len()) {
+        return Err(invalid("truncated strips"));
+    }
+    Ok(Page {
+        width,
+        height,
+        color_type,
+        bit_depth,
+        compression,
+        predictor,
+        rows_per_strip,
+        strips,
+        offsets_entry: offsets_entry.position,
+        counts_entry: counts_entry.position,
+    })
+}
+
+impl Page {
+    /// Bytes per row of samples
+    fn row_len(&self) -> usize {
+        self.width * self.color_type.samples() * self.bit_depth as usize / 8
+    }
+
+    /// Decompresses the strips to samples, big-endian pairs at 16 bits
+    fn decode(&self, bytes: &[u8], order: &Order) -> Result<Vec<u8>> {
+        let row_len = self.row_len();
+        // The dimensions are only trusted as far as the strips back them up
+        let mut data = Vec::with_capacity((row_len * self.height).min(bytes.len()));
+        for (index, strip) in self.strips.iter().enumerate() {
+            let rows = self
+                .rows_per_strip
+                .min(self.height - index * self.rows_per_strip);
+            let mut samples = decompress(self.compression, &bytes[strip.clone()], rows * row_len)
+                .filter(|samples| samples.len() >= rows * row_len)
+                .ok_or_else(|| StegError::ImageDecode("TIFF: corrupt strip".to_string()))?;
+            samples.truncate(rows * row_len);
+            if !order.big_endian && self.bit_depth == BitDepth::Sixteen {
+                swap_pairs(&mut samples);
+            }
+            if self.predictor {
+                for row in samples.chunks_mut(row_len) {
+                    self.unpredict(row);
+                }
+            }
+            data.extend(samples);
+        }
+        Ok(data)
+    }
+
+    /// Bytes between a sample and the same sample of the previous pixel
+    fn pixel_len(&self) -> usize {
+        self.color_type.samples() * self.bit_depth as usize / 8
+    }
+
+    /// Undoes horizontal differencing on a row of big-endian samples
+    fn unpredict(&self, row: &mut [u8]) {
+        let pixel_len = self.pixel_len();
+        match self.bit_depth {
+            BitDepth::Sixteen => {
+                for i in (pixel_len..row.len()).step_by(2) {
+                    let previous = u16::from_be_bytes([row[i - pixel_len], row[i - pixel_len + 1]]);
+                    let sample = u16::from_be_bytes([row[i], row[i + 1]]).wrapping_add(previous);
+                    row[i..i + 2].copy_from_slice(&sample.to_be_bytes());
+                }
+            }
+            _ => {
+                for i in pixel_len..row.len() {
+                    row[i] = row[i].wrapping_add(row[i - pixel_len]);
+                }
+            }
+        }
+    }
+
+    /// Applies horizontal differencing to a row of big-endian samples
+    fn predict(&self, row: &mut [u8]) {
+        let pixel_len = self.pixel_len();
+        match self.bit_depth {
+            BitDepth::Sixteen => {
+                for i in (pixel_len..row.len()).step_by(2).rev() {
+                    let previous = u16::from_be_bytes([row[i - pixel_len], row[i - pixel_len + 1]]);
+                    let sample = u16::from_be_bytes([row[i], row[i + 1]]).wrapping_sub(previous);
+                    row[i..i + 2].copy_from_slice(&sample.to_be_bytes());
+                }
+            }
+            _ => {
+                for i in (pixel_len..row.len()).rev() {
+                    row[i] = row[i].wrapping_sub(row[i - pixel_len]);
+                }
+            }
+        }
+    }
+}
+
+/// Encodes an image read from a TIFF file back into it
+///
+/// # Arguments:
+/// - **image**: `&Image`
+///   - image returned by [`read`], with changed samples
+/// - **tiff**: `&Tiff`
+///   - layout of the original file
+/// # Returns:
+/// - `Vec<u8>` complete TIFF file contents
+pub(crate) fn write(image: &Image, tiff: &Tiff) -> Vec<u8> {
+    let order = Order {
+        big_endian: tiff.big_endian,
+    };
+    let mut file = tiff.file.clone();
+    let pages = std::iter::once(&image.data).chain(image.frames.iter().map(|frame| &frame.data));
+    for (page, data) in tiff.pages.iter().zip(pages) {
+        let mut offsets = Vec::with_capacity(page.strips.len());
+        let mut counts = Vec::with_capacity(page.strips.len());
+        for (strip, samples) in page
+            .strips
+            .iter()
+            .zip(data.chunks(page.rows_per_strip * page.row_len()))
+        {
+            let mut samples = samples.to_vec();
+            if page.predictor {
+                for row in samples.chunks_mut(page.row_len()) {
+                    page.predict(row);
+                }
+            }
+            if !tiff.big_endian && page.bit_depth == BitDepth::Sixteen {
+                swap_pairs(&mut samples);
+            }
+            let stored = compress(page.compression, &samples);
+            let start = if stored.len() <= strip.len() {
+                strip.start
+            } else {
+                // Strips start on a word boundary
+                file.resize(file.len().next_multiple_of(2), 0);
+                file.resize(file.len() + stored.len(), 0);
+                file.len() - stored.len()
+            };
+            file[start..start + stored.len()].copy_from_slice(&stored);
+            offsets.push(start as u32);
+            counts.push(stored.len() as u32);
+        }
+        order.set_values(&mut file, page.offsets_entry, &offsets);
+        order.set_values(&mut file, page.counts_entry, &counts);
+    }
+    file
+}
+
+/// Swaps the bytes of 16-bit samples between little- and big-endian order
+fn swap_pairs(samples: &mut [u8]) {
+    for pair in samples.chunks_exact_mut(2) {
+        pair.swap(0, 1);
+    }
+}
+
+/// Decompresses a strip, `None` if it is corrupt
+///
+/// Decompression stops once `len` bytes are produced, so that a small strip
+/// cannot inflate to more than the page needs.
+fn decompress(compression: u16, strip: &[u8], len: usize) -> Option<Vec<u8>> {
+    match compression {
+        LZW => {
+            let mut decoder = Decoder::with_tiff_size_switch(BitOrder::Msb, 8);
+            let mut samples = Vec::new();
+            let mut buffer = [0; 4096];
+            let mut input = strip;
+            while samples.len() < len {
+                let result = decoder.decode_bytes(input, &mut buffer);
+                let status = result.status.ok()?;
+                input = &input[result.consumed_in..];
+                samples.extend_from_slice(&buffer[..result.consumed_out]);
+                // Some writers leave out the end code, so none is required
+                if !matches!(status, LzwStatus::Ok) {
+                    break;
+                }
+            }
+            Some(samples)
+        }
+        DEFLATE | OLD_DEFLATE => {
+            let mut samples = Vec::new();
+            ZlibDecoder::new(strip)
+                .take(len as u64)
+                .read_to_end(&mut samples)
+                .ok()?;
+            Some(samples)
+        }
+        _ => Some(strip.to_vec()),
+    }
+}
+
+/// Compresses a strip with the scheme it was read with
+fn compress(compression: u16, samples: &[u8]) -> Vec<u8> {
+    match compression {
+        LZW => Encoder::with_tiff_size_switch(BitOrder::Msb, 8)
+            .encode(samples)
+            .expect("in-memory write"),
+        DEFLATE | OLD_DEFLATE => {
+            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+            // Writing to a Vec cannot fail
+            encoder.write_all(samples).expect("in-memory write");
+            encoder.finish().expect("in-memory write")
+        }
+        _ => samples.to_vec(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{decode_bytes, encode_bytes};
+
+    // Page of a test file: width, height, samples per pixel, bits per sample
+    // and samples in big-endian order
+    struct TestPage {
+        width: u32,
+        height: u32,
+        samples_per_pixel: u16,
+        bits: u16,
+        data: Vec<u8>,
+    }
+
+    // TIFF file with two-row strips and a private tag on every page
+    fn tiff_file(
+        big_endian: bool,
+        compression: u16,
+        predictor: bool,
+        pages: &[TestPage],
+    ) -> Vec<u8> {
+        let order = Order { big_endian };
+        let mut file = SIGNATURES[big_endian as usize].to_vec();
+        file.extend([0; 4]);
+        let mut link = 4;
+        for page in pages {
+            let row_len =
+                (page.width * page.samples_per_pixel as u32 * page.bits as u32 / 8) as usize;
+            let mut offsets = Vec::new();
+            let mut counts = Vec::new();
+            for rows in page.data.chunks(2 * row_len) {
+                let mut samples = rows.to_vec();
+                if predictor {
+                    let test = Page {
+                        width: page.width as usize,
+                        height: 2,
+                        color_type: if page.samples_per_pixel == 1 {
+                            ColorType::Grayscale
+                        } else {
+                            ColorType::Rgb
+                        },
+                        bit_depth: if page.bits == 8 {
+                            BitDepth::Eight
+                        } else {
+                            BitDepth::Sixteen
+                        },
+                        compression,
+                        predictor,
+                        rows_per_strip: 2,
+                        strips: Vec::new(),
+                        offsets_entry: 0,
+                        counts_entry: 0,
+                    };
+                    for row in samples.chunks_mut(row_len) {
+                        test.predict(row);
+                    }
+                }
+                if !big_endian && page.bits == 16 {
+                    swap_pairs(&mut samples);
+                }
+                offsets.push(file.len() as u32);
+                let stored = compress(compression, &samples);
+                counts.push(stored.len() as u32);
+                file.extend(stored);
+            }
+            file.resize(file.len().next_multiple_of(2), 0);
+            let arrays = file.len();
+            for value in offsets.iter().chain(&counts) {
+                file.extend([0; 4]);
+                let end = file.len();
+                order.put_u32(&mut file, end - 4, *value);
+            }
+
+            let photometric = if page.samples_per_pixel == 1 { 1 } else { 2 };
+            let strips = offsets.len() as u32;
+            let entries: Vec<(u16, u16, u32, u32)> = vec![
+                (IMAGE_WIDTH, LONG, 1, page.width),
+                (IMAGE_LENGTH, LONG, 1, page.height),
+                (BITS_PER_SAMPLE, SHORT, 1, page.bits as u32),
+                (COMPRESSION, SHORT, 1, compression as u32),
+                (PHOTOMETRIC, SHORT, 1, photometric),
+                (
+                    STRIP_OFFSETS,
+                    LONG,
+                    strips,
+                    if strips == 1 {
+                        offsets[0]
+                    } else {
+                        arrays as u32
+                    },
+                ),
+                (SAMPLES_PER_PIXEL, SHORT, 1, page.samples_per_pixel as u32),
+                (ROWS_PER_STRIP, SHORT, 1, 2),
+                (
+                    STRIP_BYTE_COUNTS,
+                    LONG,
+                    strips,
+                    if strips == 1 {
+                        counts[0]
+                    } else {
+                        arrays as u32 + strips * 4
+                    },
+                ),
+                (PREDICTOR, SHORT, 1, if predictor { 2 } else { 1 }),
+                (65000, SHORT, 1, 0x5354),
+            ];
+            let directory = file.len();
+            order.put_u32(&mut file, link, directory as u32);
+            file.resize(directory + 2 + entries.len() * 12 + 4, 0);
+            order.put_u16(&mut file, directory, entries.len() as u16);
+            for (i, (tag, kind, count, value)) in entries.into_iter().enumerate() {
+                let entry = directory + 2 + i * 12;
+                order.put_u16(&mut file, entry, tag);
+                order.put_u16(&mut file, entry + 2, kind);
+                order.put_u32(&mut file, entry + 4, count);
+                match kind {
+                    SHORT => order.put_u16(&mut file, entry + 8, value as u16),
+                    _ => order.put_u32(&mut file, entry + 8, value),
+                }
+            }
+            link = directory + 2 + 11 * 12;
+        }
+        file
+    }
+
+    fn test_page(
+        width: u32,
+        height: u32,
+        samples_per_pixel: u16,
+        bits: u16,
+        seed: usize,
+    ) -> TestPage {
+        let len = (width * height * samples_per_pixel as u32 * bits as u32 / 8) as usize;
+        TestPage {
+            width,
+            height,
+            samples_per_pixel,
+            bits,
+            data: (0..len).map(|i| ((i * seed) / 7 % 256) as u8).collect(),
+        }
+    }
+
+    #[test]
+    fn test_read_decodes_every_scheme() {
+        for (big_endian, compression, predictor, bits) in [
+            (false, UNCOMPRESSED, false, 8),
+            (true, UNCOMPRESSED, false, 16),
+            (false, LZW, true, 16),
+            (true, LZW, false, 8),
+            (false, DEFLATE, true, 8),
+        ] {
+            let page = test_page(5, 5, 3, bits, 3);
+            let file = tiff_file(big_endian, compression, predictor, &[page]);
+            let image = read(&file).unwrap();
+            assert_eq!(image.color_type, ColorType::Rgb);
+            assert_eq!(image.data, test_page(5, 5, 3, bits, 3).data);
+
+            let written = match &image.format {
+                Format::Tiff(tiff) => write(&image, tiff),
+                _ => unreachable!(),
+            };
+            if compression == UNCOMPRESSED {
+                assert_eq!(written, file);
+            }
+            assert_eq!(read(&written).unwrap().data, image.data);
+        }
+    }
+
+    #[test]
+    fn test_read_rejects_oversized_pages() {
+        let file = tiff_file(false, UNCOMPRESSED, false, &[test_page(2, 2, 3, 16, 1)]);
+        let order = Order { big_endian: false };
+        let directory = order.u32_at(&file, 4).unwrap() as usize;
+        // Width, length and rows per strip are the first, second and eighth entries
+        let mut oversized = file.clone();
+        for entry in [0, 1, 7] {
+            let position = directory + 2 + entry * 12;
+            order.put_u16(&mut oversized, position + 2, LONG);
+            order.put_u32(&mut oversized, position + 8, u32::MAX);
+        }
+        for refused in [&oversized, &file[..file.len() - 20]] {
+            assert!(matches!(read(refused), Err(StegError::ImageDecode(_))));
+        }
+    }
+
+    #[test]
+    fn test_decompress_stops_at_the_page_size() {
+        let bomb = vec![0; 4 << 20];
+        for compression in [LZW, DEFLATE] {
+            let samples = decompress(compression, &compress(compression, &bomb), 256).unwrap();
+            assert!((256..256 + 4096).contains(&samples.len()));
+        }
+    }
+
+    #[test]
+    fn test_set_values_widens_shorts() {
+        let order = Order { big_endian: false };
+        let mut file = vec![0; 12];
+        order.put_u16(&mut file, 2, SHORT);
+        order.put_u32(&mut file, 4, 2);
+        order.set_values(&mut file, 0, &[7, 8]);
+        assert_eq!(order.values(&file, 0).unwrap(), vec![7, 8]);
+        assert_eq!(file.len(), 12);
+
+        order.set_values(&mut file, 0, &[7, 70_000]);
+        assert_eq!(order.u16_at(&file, 2), Some(LONG));
+        assert_eq!(order.values(&file, 0).unwrap(), vec![7, 70_000]);
+    }
+
+    #[test]
+    fn test_tiff_carriers_spread_over_pages() {
+        let pages = [
+            test_page(4, 4, 1, 8, 5),
+            test_page(6, 3, 3, 8, 7),
+            test_page(20, 20, 1, 8, 9),
+        ];
+        for compression in [UNCOMPRESSED, LZW, DEFLATE] {
+            let carrier = tiff_file(true, compression, false, &pages);
+            let image = read(&carrier).unwrap();
+            // The RGB page does not match the first one and is left out
+            assert_eq!(image.frames.len(), 1);
+
+            let message = vec![b'p'; 30];
+            let encoded = encode_bytes(&carrier, &message).unwrap();
+            assert_eq!(decode_bytes(&encoded).unwrap(), message);
+            let encoded_image = read(&encoded).unwrap();
+            assert_ne!(encoded_image.frames[0].data, image.frames[0].data);
+
+            // The private tag comes five entries after the strip offsets
+            let Format::Tiff(tiff) = &image.format else {
+                unreachable!()
+            };
+            for page in &tiff.pages {
+                let private = page.offsets_entry + 5 * 12..page.offsets_entry + 6 * 12;
+                assert_eq!(encoded[private.clone()], carrier[private]);
+            }
+        }
+    }
+}
@@ -0,0 +1,168 @@
+//! Order in which carrier samples are visited while embedding
+//!
+//! Without a key the walk simply runs through the samples in order. With a
+//! key, SHA-256 of the key seeds a ChaCha20 CSPRNG that drives a lazy
+//! Fisher-Yates shuffle, so the message is spread uniformly over the whole
+//! image and its positions cannot be reproduced without the key.
+use rand_chacha::{
+    ChaCha20Rng,
+    rand_core::{RngCore, SeedableRng},
+};
+use sha2::{Digest, Sha256};
+use std::{collections::HashMap, ops::Range};
+
+/// Domain separation prefix mixed into the key before seeding the CSPRNG
+const WALK_CONTEXT: &[u8] = b"stegarst walk v1";
+
+/// Iterator over the sample positions of a carrier
+pub struct Walk {
+    order: Order,
+}
+
+enum Order {
+    Sequential(Range<usize>),
+    Keyed {
+        rng: Box<ChaCha20Rng>,
+        len: usize,
+        next: usize,
+        // Entries moved by the shuffle so far, keyed by their slot
+        swapped: HashMap<usize, usize>,
+    },
+}
+
+impl Walk {
+    /// Visits the positions `0..len` in order
+    ///
+    /// # Arguments:
+    /// - **len**: `usize`
+    ///   - number of samples in the carrier
+    /// # Returns:
+    /// - `Walk` sequential walk
+    pub fn sequential(len: usize) -> Walk {
+        Walk {
+            order: Order::Sequential(0..len),
+        }
+    }
+
+    /// Visits the positions `0..len` in a key-dependent pseudo-random order
+    ///
+    /// # Arguments:
+    /// - **len**: `usize`
+    ///   - number of samples in the carrier
+    /// - **key**: `&[u8]`
+    ///   - secret the permutation is derived from
+    /// # Returns:
+    /// - `Walk` shuffled walk, identical for identical keys and lengths
+    pub fn keyed(len: usize, key: &[u8]) -> Walk {
+        let seed: [u8; 32] = Sha256::new()
+            .chain_update(WALK_CONTEXT)
+            .chain_update(key)
+            .finalize()
+            .into();
+        Walk {
+            order: Order::Keyed {
+                rng: Box::new(ChaCha20Rng::from_seed(seed)),
+                len,
+                next: 0,
+                swapped: HashMap::new(),
+            },
+        }
+    }
+
+    /// Builds a keyed walk when a key is given and a sequential one otherwise
+    pub fn new(len: usize, key: Option<&[u8]>) -> Walk {
+        match key {
+            Some(key) => Walk::keyed(len, key),
+            None => Walk::sequential(len),
+        }
+    }
+}
+
+impl Iterator for Walk {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        match &mut self.order {
+            Order::Sequential(range) => range.next(),
+            Order::Keyed {
+                rng,
+                len,
+                next,
+                swapped,
+            } => {
+                if *next >= *len {
+                    return None;
+                }
+                // One forward Fisher-Yates step, only remembering moved entries
+                let i = *next;
+                let j = i + below(rng, *len - i);
+                let at_i = swapped.remove(&i).unwrap_or(i);
+                let at_j = if j == i {
+                    at_i
+                } else {
+                    swapped.insert(j, at_i).unwrap_or(j)
+                };
+                *next += 1;
+                Some(at_j)
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = match &self.order {
+            Order::Sequential(range) => range.len(),
+            Order::Keyed { len, next, .. } => len - next,
+        };
+        (remaining, Some(remaining))
+    }
+}
+
+/// Draws a uniformly distributed number in `0..bound` by rejection sampling
+fn below(rng: &mut ChaCha20Rng, bound: usize) -> usize {
+    let bound = bound as u64;
+    let zone = u64::MAX - (u64::MAX - bound + 1) % bound;
+    loop {
+        let value = rng.next_u64();
+        if value <= zone {
+            return (value % bound) as usize;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sequential_walk() {
+        assert_eq!(Walk::sequential(4).collect::<Vec<_>>(), vec![0, 1, 2, 3]);
+        assert_eq!(Walk::new(0, None).count(), 0);
+    }
+
+    #[test]
+    fn test_keyed_walk_is_a_permutation() {
+        let mut positions: Vec<usize> = Walk::keyed(1000, b"key").collect();
+        assert_ne!(positions, (0..1000).collect::<Vec<_>>());
+        positions.sort();
+        assert_eq!(positions, (0..1000).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_keyed_walk_depends_on_key() {
+        let first: Vec<usize> = Walk::keyed(1000, b"key").take(50).collect();
+        let again: Vec<usize> = Walk::keyed(1000, b"key").take(50).collect();
+        let other: Vec<usize> = Walk::keyed(1000, b"other key").take(50).collect();
+        assert_eq!(first, again);
+        assert_ne!(first, other);
+    }
+
+    #[test]
+    fn test_keyed_walk_spreads_over_the_image() {
+        // The first tenth of the walk should touch every tenth of the image
+        let mut buckets = [0; 10];
+        for position in Walk::keyed(10_000, b"key").take(1000) {
+            buckets[position / 1000] += 1;
+        }
+        assert!(buckets.iter().all(|count| *count > 50), "{:?}", buckets);
+    }
+}
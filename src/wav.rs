@@ -0,0 +1,302 @@
+//! WAV reading and writing
+//!
+//! PCM audio is read into the same buffer as images: the interleaved samples
+//! of every channel make up a single row of gray samples. 8-bit samples are
+//! handed out as they are, 16- and 24-bit samples as their low 16 bits, so
+//! changing their LSBs never carries over into the higher bytes. Signed
+//! 16-bit samples are handed out in offset binary, like 8-bit ones, so that
+//! LSB matching cannot wrap a loud sample around to the opposite one. On
+//! writing, the new samples go over those of the data chunk.
+use crate::error::{Result, StegError};
+use crate::image::{Format, Image};
+use png::{BitDepth, ColorType};
+use std::ops::Range;
+
+/// Signature of a WAV file: a RIFF container of form type WAVE
+pub(crate) fn is_wav(bytes: &[u8]) -> bool {
+    bytes.starts_with(b"RIFF") && bytes.get(8..12) == Some(b"WAVE")
+}
+
+/// WAVE_FORMAT_PCM: integer samples
+const WAVE_FORMAT_PCM: u16 = 1;
+/// WAVE_FORMAT_EXTENSIBLE: the actual format is the GUID of the fmt chunk
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+/// KSDATAFORMAT_SUBTYPE_PCM, as stored in the fmt chunk
+const SUBTYPE_PCM: [u8; 16] = [
+    0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xAA, 0x00, 0x38, 0x9B, 0x71,
+];
+
+/// Where the samples of a WAV file lie, so they can be written back in place
+pub(crate) struct Wave {
+    /// Complete file contents, as read
+    file: Vec<u8>,
+    /// Bytes of the samples in the data chunk
+    samples: Range<usize>,
+    /// Bytes per sample: 1, 2 or 3
+    sample_len: usize,
+}
+
+/// Bits flipped in the most significant byte handed out for a sample, turning
+/// signed 16-bit samples into offset binary
+fn sign_bit(sample_len: usize) -> u8 {
+    match sample_len {
+        2 => 0x80,
+        _ => 0,
+    }
+}
+
+/// Decodes an in-memory WAV file
+///
+/// # Arguments:
+/// - **bytes**: `&[u8]`
+///   - complete WAV file contents
+/// # Returns:
+/// - `Result<Image>` samples of every channel, interleaved, big-endian pairs
+///   above 8 bits
+///   - `StegError::ImageDecode` for a truncated, malformed or non-PCM file
+pub(crate) fn read(bytes: &[u8]) -> Result<Image> {
+    let invalid = |reason: &str| StegError::ImageDecode(format!("WAV: {}", reason));
+    let u16_at = |offset: usize| u16::from_le_bytes([bytes[offset], bytes[offset + 1]]);
+
+    let mut sample_len = None;
+    let mut samples = None;
+    let mut position = 12;
+    while samples.is_none() && position + 8 <= bytes.len() {
+        let id = &bytes[position..position + 4];
+        let len = u32::from_le_bytes(bytes[position + 4..position + 8].try_into().unwrap());
+        let body = position + 8..position + 8 + len as usize;
+        match id {
+            b"fmt " => {
+                if body.len() < 16 || body.end > bytes.len() {
+                    return Err(invalid("truncated fmt chunk"));
+                }
+                let format = match u16_at(body.start) {
+                    WAVE_FORMAT_EXTENSIBLE if body.len() >= 40 => {
+                        match bytes[body.start + 24..body.start + 40] == SUBTYPE_PCM {
+                            true => WAVE_FORMAT_PCM,
+                            false => WAVE_FORMAT_EXTENSIBLE,
+                        }
+                    }
+                    format => format,
+                };
+                if format != WAVE_FORMAT_PCM {
+                    return Err(invalid("only PCM audio is supported"));
+                }
+                let channels = u16_at(body.start + 2) as usize;
+                let block_align = u16_at(body.start + 12) as usize;
+                let bits = u16_at(body.start + 14);
+                if channels == 0 {
+                    return Err(invalid("no channels"));
+                }
+                // Samples padded to a wider container are not supported either
+                if !matches!(bits, 8 | 16 | 24) || block_align != channels * bits as usize / 8 {
+                    return Err(invalid(&format!(
+                        "{}-bit samples are not supported, expected 8, 16 or 24",
+                        bits
+                    )));
+                }
+                sample_len = Some(bits as usize / 8);
+            }
+            b"data" => {
+                let Some(sample_len) = sample_len else {
+                    return Err(invalid("data chunk before the fmt chunk"));
+                };
+                if body.end > bytes.len() {
+                    return Err(invalid("truncated data chunk"));
+                }
+                let len = body.len() - body.len() % sample_len;
+                samples = Some(body.start..body.start + len);
+            }
+            _ => {}
+        }
+        // Chunks are padded to an even length
+        position = body.end + body.end % 2;
+    }
+    let (Some(sample_len), Some(samples)) = (sample_len, samples) else {
+        return Err(invalid("missing fmt or data chunk"));
+    };
+
+    // Only the low bytes are handed out, most significant first
+    let exposed = sample_len.min(2);
+    let mut data: Vec<u8> = bytes[samples.clone()]
+        .chunks_exact(sample_len)
+        .flat_map(|sample| sample[..exposed].iter().rev().copied())
+        .collect();
+    data.iter_mut()
+        .step_by(exposed)
+        .for_each(|byte| *byte ^= sign_bit(sample_len));
+    Ok(Image {
+        width: (samples.len() / sample_len) as u32,
+        height: 1,
+        color_type: ColorType::Grayscale,
+        bit_depth: match exposed {
+            1 => BitDepth::Eight,
+            _ => BitDepth::Sixteen,
+        },
+        palette: None,
+        trns: None,
+        interlaced: false,
+        chunks: Vec::new(),
+        attachments: Vec::new(),
+        data,
+        frames: Vec::new(),
+        format: Format::Wav(Wave {
+            file: bytes.to_vec(),
+            samples,
+            sample_len,
+        }),
+    })
+}
+
+/// Encodes an image read from a WAV file back into it
+///
+/// # Arguments:
+/// - **image**: `&Image`
+///   - image returned by [`read`], with changed samples
+/// - **wave**: `&Wave`
+///   - layout of the original file
+/// # Returns:
+/// - `Vec<u8>` complete WAV file contents
+pub(crate) fn write(image: &Image, wave: &Wave) -> Vec<u8> {
+    let mut file = wave.file.clone();
+    let exposed = wave.sample_len.min(2);
+    for (sample, data) in file[wave.samples.clone()]
+        .chunks_exact_mut(wave.sample_len)
+        .zip(image.data.chunks_exact(exposed))
+    {
+        for (byte, value) in sample.iter_mut().zip(data.iter().rev()) {
+            *byte = *value;
+        }
+        sample[exposed - 1] ^= sign_bit(wave.sample_len);
+    }
+    file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::{DecodeOptions, EncodeOptions};
+    use crate::{decode_bytes, decode_bytes_with, encode_bytes_with};
+
+    // WAV file with a LIST chunk of odd length between the fmt and data chunks
+    fn wav_file(format: u16, channels: u16, bits: u16, samples: &[u8]) -> Vec<u8> {
+        let block_align = channels * bits / 8;
+        let mut fmt = Vec::new();
+        fmt.extend(format.to_le_bytes());
+        fmt.extend(channels.to_le_bytes());
+        fmt.extend(44100u32.to_le_bytes());
+        fmt.extend((44100 * block_align as u32).to_le_bytes());
+        fmt.extend(block_align.to_le_bytes());
+        fmt.extend(bits.to_le_bytes());
+        if format == WAVE_FORMAT_EXTENSIBLE {
+            fmt.extend(22u16.to_le_bytes());
+            fmt.extend(bits.to_le_bytes());
+            fmt.extend(3u32.to_le_bytes());
+            fmt.extend(SUBTYPE_PCM);
+        }
+        let mut body = b"WAVE".to_vec();
+        for (id, chunk) in [
+            (b"fmt ", &fmt[..]),
+            (b"LIST", b"INFOINAM\x03\0\0\0ab\0"),
+            (b"data", samples),
+        ] {
+            body.extend(id);
+            body.extend((chunk.len() as u32).to_le_bytes());
+            body.extend(chunk);
+            if chunk.len() % 2 == 1 {
+                body.push(0);
+            }
+        }
+        let mut file = b"RIFF".to_vec();
+        file.extend((body.len() as u32).to_le_bytes());
+        file.extend(body);
+        file
+    }
+
+    #[test]
+    fn test_read_formats() {
+        let image = read(&wav_file(WAVE_FORMAT_PCM, 1, 8, &[1, 2, 3])).unwrap();
+        assert_eq!(image.bit_depth, BitDepth::Eight);
+        assert_eq!(image.data, vec![1, 2, 3]);
+
+        let image = read(&wav_file(WAVE_FORMAT_PCM, 2, 16, &[1, 2, 3, 4])).unwrap();
+        assert_eq!(image.bit_depth, BitDepth::Sixteen);
+        assert_eq!(image.data, vec![0x82, 1, 0x84, 3]);
+
+        // The loudest samples are the extremes of the offset binary range
+        let loudest = wav_file(WAVE_FORMAT_PCM, 1, 16, &[0xFF, 0x7F, 0x00, 0x80]);
+        let image = read(&loudest).unwrap();
+        assert_eq!(image.data, vec![0xFF, 0xFF, 0x00, 0x00]);
+        match &image.format {
+            Format::Wav(wave) => assert_eq!(write(&image, wave), loudest),
+            _ => unreachable!(),
+        }
+
+        let samples = [1, 2, 3, 4, 5, 6];
+        let image = read(&wav_file(WAVE_FORMAT_EXTENSIBLE, 2, 24, &samples)).unwrap();
+        assert_eq!(image.bit_depth, BitDepth::Sixteen);
+        assert_eq!(image.data, vec![2, 1, 5, 4]);
+
+        // Silence carries no message, not an empty one
+        let silent = wav_file(WAVE_FORMAT_PCM, 1, 16, &[0; 400]);
+        assert!(matches!(
+            decode_bytes(&silent),
+            Err(StegError::NoPayload(_))
+        ));
+    }
+
+    #[test]
+    fn test_read_rejects_inconsistent_chunks() {
+        let file = wav_file(WAVE_FORMAT_PCM, 2, 16, &[0; 16]);
+        let data = file.windows(4).position(|id| id == b"data").unwrap();
+        let mut oversized = file.clone();
+        oversized[data + 4..data + 8].copy_from_slice(&u32::MAX.to_le_bytes());
+        let mut no_channels = file.clone();
+        no_channels[22..24].copy_from_slice(&[0, 0]);
+        let mut misaligned = file.clone();
+        misaligned[32] = 3;
+        for refused in [
+            wav_file(3, 1, 16, &[0; 4]),
+            wav_file(WAVE_FORMAT_PCM, 1, 32, &[0; 4]),
+            file[..50].to_vec(),
+            file[..data].to_vec(),
+            oversized,
+            no_channels,
+            misaligned,
+        ] {
+            assert!(matches!(read(&refused), Err(StegError::ImageDecode(_))));
+        }
+    }
+
+    #[test]
+    fn test_encoding_only_changes_low_sample_bytes() {
+        let samples: Vec<u8> = (0..6000).map(|i| (i * 7 % 256) as u8).collect();
+        let encode_options = EncodeOptions::default().with_passphrase("pcm");
+        let decode_options = DecodeOptions::default().with_passphrase("pcm");
+        for (channels, bits) in [(1, 8), (2, 16), (2, 24)] {
+            let carrier = wav_file(WAVE_FORMAT_PCM, channels, bits, &samples);
+            let encoded = encode_bytes_with(&carrier, b"audio", &encode_options).unwrap();
+            assert_eq!(encoded.len(), carrier.len());
+            assert_eq!(
+                decode_bytes_with(&encoded, &decode_options).unwrap(),
+                b"audio"
+            );
+
+            // Only the low bytes of the samples change
+            let data = match read(&carrier).unwrap().format {
+                Format::Wav(wave) => wave.samples,
+                _ => unreachable!(),
+            };
+            assert_eq!(encoded[..data.start], carrier[..data.start]);
+            assert_eq!(encoded[data.end..], carrier[data.end..]);
+            let sample_len = bits as usize / 8;
+            for (old, new) in carrier[data.clone()]
+                .chunks(sample_len)
+                .zip(encoded[data].chunks(sample_len))
+            {
+                assert_eq!(old[sample_len.min(2)..], new[sample_len.min(2)..]);
+            }
+            assert_ne!(encoded, carrier);
+        }
+    }
+}
@@ -0,0 +1,189 @@
+//! Lossless WebP reading and writing
+//!
+//! Only VP8L images are accepted: a lossy WebP is recompressed on every save,
+//! which would wipe out anything hidden in its pixels. The pixels are decoded
+//! to RGB(A) and encoded again losslessly, with the ICC profile, EXIF and XMP
+//! metadata of the carrier carried over.
+use crate::error::{Result, StegError};
+use crate::image::{Format, Image};
+use image_webp::{WebPDecoder, WebPEncoder};
+use png::{BitDepth, ColorType};
+use std::io::Cursor;
+
+/// Most bytes of pixels a byte of WebP file may decode to. Lossless WebP
+/// packs flat areas into next to nothing, but a file claiming more is taken
+/// for a decompression bomb
+const MAX_EXPANSION: usize = 1024;
+
+/// Signature of a WebP file: a RIFF container of form type WEBP
+pub(crate) fn is_webp(bytes: &[u8]) -> bool {
+    bytes.starts_with(b"RIFF") && bytes.get(8..12) == Some(b"WEBP")
+}
+
+/// Metadata of a WebP file, written back along with the new pixels
+pub(crate) struct Webp {
+    icc_profile: Option<Vec<u8>>,
+    exif: Option<Vec<u8>>,
+    xmp: Option<Vec<u8>>,
+}
+
+/// Decodes an in-memory lossless WebP file
+///
+/// # Arguments:
+/// - **bytes**: `&[u8]`
+///   - complete WebP file contents
+/// # Returns:
+/// - `Result<Image>` pixels in RGB(A) order
+///   - `StegError::ImageDecode` for a lossy, animated or malformed WebP
+pub(crate) fn read(bytes: &[u8]) -> Result<Image> {
+    let invalid =
+        |reason: &dyn std::fmt::Display| StegError::ImageDecode(format!("WebP: {}", reason));
+    let mut decoder = WebPDecoder::new(Cursor::new(bytes)).map_err(|e| invalid(&e))?;
+    if decoder.is_lossy() {
+        return Err(invalid(
+            &"lossy images are not supported, saving them again would destroy the message",
+        ));
+    }
+    if decoder.is_animated() {
+        return Err(invalid(&"animated images are not supported"));
+    }
+
+    let (width, height) = decoder.dimensions();
+    let buffer_size = decoder
+        .output_buffer_size()
+        .filter(|size| *size / MAX_EXPANSION <= bytes.len())
+        .ok_or_else(|| invalid(&"image too large for its file"))?;
+    let mut data = vec![0; buffer_size];
+    decoder.read_image(&mut data).map_err(|e| invalid(&e))?;
+    let webp = Webp {
+        icc_profile: decoder.icc_profile().map_err(|e| invalid(&e))?,
+        exif: decoder.exif_metadata().map_err(|e| invalid(&e))?,
+        xmp: decoder.xmp_metadata().map_err(|e| invalid(&e))?,
+    };
+    Ok(Image {
+        width,
+        height,
+        color_type: match decoder.has_alpha() {
+            true => ColorType::Rgba,
+            false => ColorType::Rgb,
+        },
+        bit_depth: BitDepth::Eight,
+        palette: None,
+        trns: None,
+        interlaced: false,
+        chunks: Vec::new(),
+        attachments: Vec::new(),
+        data,
+        frames: Vec::new(),
+        format: Format::Webp(webp),
+    })
+}
+
+/// Encodes an image read from a WebP file as a lossless WebP file
+///
+/// # Arguments:
+/// - **image**: `&Image`
+///   - image returned by [`read`], with changed pixels
+/// - **webp**: `&Webp`
+///   - metadata of the original file
+/// # Returns:
+/// - `Vec<u8>` complete WebP file contents
+pub(crate) fn write(image: &Image, webp: &Webp) -> Vec<u8> {
+    let mut output = Vec::new();
+    let mut encoder = WebPEncoder::new(&mut output);
+    if let Some(icc_profile) = &webp.icc_profile {
+        encoder.set_icc_profile(icc_profile.clone());
+    }
+    if let Some(exif) = &webp.exif {
+        encoder.set_exif_metadata(exif.clone());
+    }
+    if let Some(xmp) = &webp.xmp {
+        encoder.set_xmp_metadata(xmp.clone());
+    }
+    let color = match image.color_type {
+        ColorType::Rgba => image_webp::ColorType::Rgba8,
+        _ => image_webp::ColorType::Rgb8,
+    };
+    // The dimensions come from a decoded WebP, so they are valid for encoding
+    encoder
+        .encode(&image.data, image.width, image.height, color)
+        .expect("in-memory write");
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{decode_bytes, encode_bytes};
+
+    fn webp_file(width: u32, height: u32, color: image_webp::ColorType, data: &[u8]) -> Vec<u8> {
+        let mut file = Vec::new();
+        let mut encoder = WebPEncoder::new(&mut file);
+        encoder.set_exif_metadata(b"Exif\0\0fake".to_vec());
+        encoder.encode(data, width, height, color).unwrap();
+        file
+    }
+
+    #[test]
+    fn test_lossless_encoding_keeps_metadata() {
+        let rgb: Vec<u8> = (0..24 * 16 * 3).map(|i| (i * 7 % 256) as u8).collect();
+        let rgba: Vec<u8> = (0..24 * 16 * 4).map(|i| (i * 5 % 256) as u8).collect();
+        for (carrier, color_type) in [
+            (
+                webp_file(24, 16, image_webp::ColorType::Rgb8, &rgb),
+                ColorType::Rgb,
+            ),
+            (
+                webp_file(24, 16, image_webp::ColorType::Rgba8, &rgba),
+                ColorType::Rgba,
+            ),
+        ] {
+            assert_eq!(read(&carrier).unwrap().color_type, color_type);
+            let encoded = encode_bytes(&carrier, b"lossless").unwrap();
+            assert!(is_webp(&encoded));
+            assert_eq!(decode_bytes(&encoded).unwrap(), b"lossless");
+
+            let mut decoder = WebPDecoder::new(Cursor::new(&encoded)).unwrap();
+            assert_eq!(decoder.exif_metadata().unwrap().unwrap(), b"Exif\0\0fake");
+        }
+    }
+
+    #[test]
+    fn test_read_rejects_pixels_beyond_the_file() {
+        let file = webp_file(8, 8, image_webp::ColorType::Rgb8, &[7; 8 * 8 * 3]);
+        // 16383x16383 pixels announced in front of the data of 8x8, in both
+        // the extended header and the VP8L header after its signature byte
+        let mut oversized = file.clone();
+        oversized[24..30].copy_from_slice(&[0xFE, 0x3F, 0, 0xFE, 0x3F, 0]);
+        let vp8l = file.windows(4).position(|id| id == b"VP8L").unwrap();
+        oversized[vp8l + 9..vp8l + 13].copy_from_slice(&(0x3FFE_u32 | 0x3FFE << 14).to_le_bytes());
+        match read(&oversized) {
+            Err(StegError::ImageDecode(reason)) => assert!(reason.contains("too large")),
+            _ => panic!("oversized WebP accepted"),
+        }
+        for refused in [&file[..file.len() - 4], &file[..24]] {
+            assert!(matches!(read(refused), Err(StegError::ImageDecode(_))));
+        }
+    }
+
+    #[test]
+    fn test_lossy_webp_is_refused() {
+        // Smallest lossy WebP: a 1x1 VP8 key frame
+        let mut frame = vec![0x30, 0x01, 0x00, 0x9D, 0x01, 0x2A, 1, 0, 1, 0];
+        frame.extend([0; 8]);
+        let mut file = b"RIFF".to_vec();
+        file.extend((12 + frame.len() as u32).to_le_bytes());
+        file.extend(b"WEBPVP8 ");
+        file.extend((frame.len() as u32).to_le_bytes());
+        file.extend(frame);
+        assert!(is_webp(&file));
+        // Lossy with an alpha channel, from the test data of CPython's imghdr
+        let with_alpha = include_bytes!("../testdata/python.webp");
+        for file in [&file[..], with_alpha] {
+            match read(file) {
+                Err(StegError::ImageDecode(reason)) => assert!(reason.contains("lossy")),
+                _ => panic!("lossy WebP accepted"),
+            }
+        }
+    }
+}